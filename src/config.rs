@@ -1,8 +1,11 @@
 use serde::Deserialize;
+use std::fmt::Display;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use dotenv::dotenv;
+use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Config {
     pub server: ServerConfig,
     pub app: AppConfig,
@@ -10,6 +13,96 @@ pub struct Config {
     pub auth: AuthConfig,
     pub user: UserConfig,
     pub clipboard: ClipboardConfig,
+    pub storage: StorageConfig,
+    pub maintenance: MaintenanceConfig,
+    pub throttle: ThrottleConfig,
+    pub pairing: PairingConfig,
+    pub blob: BlobConfig,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+    Journal,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "memory" => Ok(Self::Memory),
+            "sqlite" => Ok(Self::Sqlite),
+            "journal" => Ok(Self::Journal),
+            other => Err(format!("Unknown storage backend: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StorageConfig {
+    #[serde(default = "default_storage_backend")]
+    pub backend: StorageBackend,
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    #[serde(default = "default_journal_dir")]
+    pub journal_dir: String,
+    #[serde(default = "default_journal_compact_threshold")]
+    pub journal_compact_threshold: usize,  // journal records before a snapshot is taken
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MaintenanceConfig {
+    #[serde(default = "default_clipboard_cleanup_interval")]
+    pub clipboard_cleanup_interval: u64,  // in seconds, 0 disables the job
+    #[serde(default = "default_token_prune_interval")]
+    pub token_prune_interval: u64,  // in seconds, 0 disables the job
+    #[serde(default = "default_blob_prune_interval")]
+    pub blob_prune_interval: u64,  // in seconds, 0 disables the job
+}
+
+/// Login brute-force protection. Failures are counted per username and per
+/// client IP; past the free attempts each failure doubles the lockout.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ThrottleConfig {
+    #[serde(default = "default_account_attempts")]
+    pub account_attempts: u32,  // failures per username before lockouts start
+    #[serde(default = "default_ip_attempts")]
+    pub ip_attempts: u32,  // failures per IP before lockouts start
+    #[serde(default = "default_base_lockout")]
+    pub base_lockout: u64,  // in seconds, first lockout
+    #[serde(default = "default_max_lockout")]
+    pub max_lockout: u64,  // in seconds
+    #[serde(default = "default_failure_window")]
+    pub failure_window: u64,  // in seconds without failures before counts reset
+}
+
+/// One-time codes an existing device hands out so a new one can join.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PairingConfig {
+    #[serde(default = "default_pairing_code_expiry")]
+    pub code_expiry: u64,  // in seconds
+    #[serde(default = "default_max_pending_codes")]
+    pub max_pending_codes: usize,  // per user; issuing more drops the oldest
+    #[serde(default = "default_redeem_attempts")]
+    pub redeem_attempts: u32,  // wrong codes per IP per `code_expiry`
+}
+
+/// Large clipboard payloads, uploaded in chunks outside the clip itself.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BlobConfig {
+    #[serde(default = "default_blob_chunk_size")]
+    pub chunk_size: u64,  // in bytes
+    #[serde(default = "default_blob_max_size")]
+    pub max_size: u64,  // in bytes
+    #[serde(default = "default_blob_upload_expiry")]
+    pub upload_expiry: u64,  // in seconds; unfinished uploads are dropped after this
+    #[serde(default = "default_blob_max_user_bytes")]
+    pub max_user_bytes: u64,  // in bytes, across all of a user's blobs
+    #[serde(default = "default_blob_max_pending_uploads")]
+    pub max_pending_uploads: usize,  // unfinished uploads per user
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -17,7 +110,11 @@ pub struct ClipboardConfig {
     #[serde(default = "default_retention_period")]
     pub retention_period: u64,  // in seconds
     #[serde(default = "default_max_size")]
-    pub max_size: usize,   // maximum size of clipboard content
+    pub max_size: usize,   // maximum size of clipboard content, all formats together
+    #[serde(default = "default_max_size")]
+    pub max_format_size: usize,  // maximum size of any one format
+    #[serde(default = "default_max_formats")]
+    pub max_formats: usize,  // formats per clip besides the plain text
     #[serde(default = "default_max_size")]
     pub broadcast_capacity: usize, // TODO research this
 }
@@ -26,15 +123,107 @@ pub struct ClipboardConfig {
 pub struct WebSocketConfig {
     #[serde(default = "default_channel_capacity")]
     pub channel_capacity: usize,
+    #[serde(default = "default_replay_limit")]
+    pub replay_limit: usize,  // clips per resume reply
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,  // in seconds between server pings
+    #[serde(default = "default_heartbeat_timeout")]
+    pub heartbeat_timeout: u64,  // in seconds of silence before a socket is dropped, at least two intervals
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct AuthConfig {
     pub jwt_secret: String,
+    #[serde(default = "default_jwt_key_id")]
+    pub jwt_key_id: String,  // `kid` of the key new tokens are signed with
+    #[serde(default = "default_jwt_algorithm")]
+    pub jwt_algorithm: JwtAlgorithm,
+    #[serde(default)]
+    pub jwt_private_key_path: Option<String>,  // PEM file, required unless HS256
+    #[serde(default)]
+    pub previous_keys: Vec<PreviousKeyConfig>,  // still accepted for verification
     #[serde(default = "default_access_token_expiry")]
     pub access_token_expiry: u64,
     #[serde(default = "default_refresh_token_expiry")]
     pub refresh_token_expiry: u64,
+    #[serde(default = "default_mfa_token_expiry")]
+    pub mfa_token_expiry: u64,  // time to enter a code after the password
+    #[serde(default = "default_totp_issuer")]
+    pub totp_issuer: String,  // shown by authenticator apps
+    #[serde(default)]
+    pub admin_user_ids: Vec<Uuid>,  // may use the /admin endpoints
+}
+
+/// How access and refresh tokens are signed. The asymmetric algorithms let
+/// other services verify tokens from the published JWKS without the secret.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "HS256")]
+    Hs256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+    #[serde(rename = "RS256")]
+    Rs256,
+}
+
+impl std::str::FromStr for JwtAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hs256" => Ok(Self::Hs256),
+            "eddsa" | "ed25519" => Ok(Self::EdDsa),
+            "rs256" => Ok(Self::Rs256),
+            other => Err(format!("Unknown JWT algorithm: {}", other)),
+        }
+    }
+}
+
+/// A retired signing key, written `kid[@retire_at][:algorithm]=key` where
+/// `retire_at` is in unix seconds. `key` is the shared secret for HS256,
+/// the default, and the path to the PEM public key otherwise.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+pub struct PreviousKeyConfig {
+    pub kid: String,
+    #[serde(default = "default_jwt_algorithm")]
+    pub algorithm: JwtAlgorithm,
+    #[serde(alias = "secret")]
+    pub key: String,
+    #[serde(default)]
+    pub retire_at: Option<u64>,
+}
+
+impl std::str::FromStr for PreviousKeyConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (id, key) = s
+            .split_once('=')
+            .ok_or_else(|| "Expected kid=key".to_string())?;
+        let (id, algorithm) = match id.split_once(':') {
+            Some((id, algorithm)) => (id, algorithm.parse()?),
+            None => (id, default_jwt_algorithm()),
+        };
+        let (kid, retire_at) = match id.split_once('@') {
+            Some((kid, retire_at)) => {
+                let retire_at = retire_at
+                    .parse()
+                    .map_err(|_| format!("Invalid retire_at for key {}: {}", kid, retire_at))?;
+                (kid, Some(retire_at))
+            }
+            None => (id, None),
+        };
+        if kid.is_empty() || key.is_empty() {
+            return Err("Key id and key must not be empty".to_string());
+        }
+
+        Ok(Self {
+            kid: kid.to_string(),
+            algorithm,
+            key: key.to_string(),
+            retire_at,
+        })
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -43,6 +232,10 @@ pub struct ServerConfig {
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// Take client IPs from `X-Forwarded-For`. Only enable behind a proxy
+    /// that overwrites the header.
+    #[serde(default = "default_trust_forwarded_for")]
+    pub trust_forwarded_for: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -55,6 +248,16 @@ pub struct UserConfig {
     pub password_rounds: u32,
     #[serde(default = "default_memory_size")]
     pub memory_size: u32,
+    #[serde(default = "default_max_password_length")]
+    pub max_password_length: usize,
+    #[serde(default = "default_password_character_classes")]
+    pub password_character_classes: usize,  // of lower, upper, digit, symbol; 0 disables
+    #[serde(default = "default_min_password_score")]
+    pub min_password_score: u8,  // 0-4 strength estimate
+    #[serde(default)]
+    pub password_blocklist_path: Option<String>,  // SHA-1 hashes of breached passwords, one per line
+    #[serde(default = "default_max_devices")]
+    pub max_devices: usize,  // registered devices per user
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -69,16 +272,49 @@ pub struct AppConfig {
 fn default_retention_period() -> u64 { 24 * 60 * 60 }  // 24 hours
 fn default_max_size() -> usize { 1024 * 1024 }         // 1MB
 fn default_channel_capacity() -> usize { 100 }
+fn default_replay_limit() -> usize { 500 }
+fn default_heartbeat_interval() -> u64 { 30 }
+fn default_heartbeat_timeout() -> u64 { 90 }
+fn default_jwt_key_id() -> String { "default".to_string() }
+fn default_jwt_algorithm() -> JwtAlgorithm { JwtAlgorithm::Hs256 }
 fn default_access_token_expiry() -> u64 { 3600 }       // 1 hour
 fn default_refresh_token_expiry() -> u64 { 604800 }    // 7 days
+fn default_mfa_token_expiry() -> u64 { 300 }           // 5 minutes
+fn default_totp_issuer() -> String { "Clipman".to_string() }
 fn default_host() -> String { "127.0.0.1".to_string() }
 fn default_port() -> u16 { 3000 }
+fn default_trust_forwarded_for() -> bool { false }
 fn default_min_password_length() -> usize { 8 }
 fn default_max_username_length() -> usize { 32 }
 fn default_password_rounds() -> u32 { 3 }
 fn default_memory_size() -> u32 { 65536 }
+fn default_max_password_length() -> usize { 128 }
+fn default_password_character_classes() -> usize { 0 }
+fn default_min_password_score() -> u8 { 2 }
+fn default_max_devices() -> usize { 10 }
+fn default_max_formats() -> usize { 8 }
 fn default_history_size() -> usize { 10 }
 fn default_broadcast_capacity() -> usize { 100 }
+fn default_storage_backend() -> StorageBackend { StorageBackend::Memory }
+fn default_database_path() -> String { "data/clipman.db".to_string() }
+fn default_journal_dir() -> String { "data/journal".to_string() }
+fn default_journal_compact_threshold() -> usize { 1000 }
+fn default_clipboard_cleanup_interval() -> u64 { 15 * 60 }  // 15 minutes
+fn default_token_prune_interval() -> u64 { 60 * 60 }        // 1 hour
+fn default_blob_prune_interval() -> u64 { 15 * 60 }         // 15 minutes
+fn default_account_attempts() -> u32 { 5 }
+fn default_ip_attempts() -> u32 { 20 }
+fn default_base_lockout() -> u64 { 30 }
+fn default_max_lockout() -> u64 { 60 * 60 }               // 1 hour
+fn default_failure_window() -> u64 { 15 * 60 }            // 15 minutes
+fn default_pairing_code_expiry() -> u64 { 5 * 60 }        // 5 minutes
+fn default_max_pending_codes() -> usize { 3 }
+fn default_redeem_attempts() -> u32 { 10 }
+fn default_blob_chunk_size() -> u64 { 1024 * 1024 }       // 1 MiB
+fn default_blob_max_size() -> u64 { 256 * 1024 * 1024 }   // 256 MiB
+fn default_blob_upload_expiry() -> u64 { 24 * 60 * 60 }   // 1 day
+fn default_blob_max_user_bytes() -> u64 { 1024 * 1024 * 1024 }  // 1 GiB
+fn default_blob_max_pending_uploads() -> usize { 4 }
 
 // Implement Default for all configs
 impl Default for ClipboardConfig {
@@ -86,6 +322,8 @@ impl Default for ClipboardConfig {
         Self {
             retention_period: default_retention_period(),
             max_size: default_max_size(),
+            max_format_size: default_max_size(),
+            max_formats: default_max_formats(),
 
             //Personal use (2-3 devices): 100-500
             // Small team (5-10 devices): 1000-3000
@@ -100,6 +338,9 @@ impl Default for WebSocketConfig {
     fn default() -> Self {
         Self {
             channel_capacity: default_channel_capacity(),
+            replay_limit: default_replay_limit(),
+            heartbeat_interval: default_heartbeat_interval(),
+            heartbeat_timeout: default_heartbeat_timeout(),
         }
     }
 }
@@ -108,8 +349,15 @@ impl Default for AuthConfig {
     fn default() -> Self {
         Self {
             jwt_secret: "your-secret-key".to_string(),
+            jwt_key_id: default_jwt_key_id(),
+            jwt_algorithm: default_jwt_algorithm(),
+            jwt_private_key_path: None,
+            previous_keys: Vec::new(),
             access_token_expiry: default_access_token_expiry(),
             refresh_token_expiry: default_refresh_token_expiry(),
+            mfa_token_expiry: default_mfa_token_expiry(),
+            totp_issuer: default_totp_issuer(),
+            admin_user_ids: Vec::new(),
         }
     }
 }
@@ -119,6 +367,7 @@ impl Default for ServerConfig {
         Self {
             host: default_host(),
             port: default_port(),
+            trust_forwarded_for: default_trust_forwarded_for(),
         }
     }
 }
@@ -130,37 +379,102 @@ impl Default for UserConfig {
             max_username_length: default_max_username_length(),
             password_rounds: default_password_rounds(),
             memory_size: default_memory_size(),
+            max_password_length: default_max_password_length(),
+            password_character_classes: default_password_character_classes(),
+            min_password_score: default_min_password_score(),
+            password_blocklist_path: None,
+            max_devices: default_max_devices(),
         }
     }
 }
 
-impl Default for AppConfig {
+impl Default for StorageConfig {
     fn default() -> Self {
         Self {
-            history_size: default_history_size(),
-            broadcast_capacity: default_broadcast_capacity(),
+            backend: default_storage_backend(),
+            database_path: default_database_path(),
+            journal_dir: default_journal_dir(),
+            journal_compact_threshold: default_journal_compact_threshold(),
+        }
+    }
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            clipboard_cleanup_interval: default_clipboard_cleanup_interval(),
+            token_prune_interval: default_token_prune_interval(),
+            blob_prune_interval: default_blob_prune_interval(),
+        }
+    }
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            account_attempts: default_account_attempts(),
+            ip_attempts: default_ip_attempts(),
+            base_lockout: default_base_lockout(),
+            max_lockout: default_max_lockout(),
+            failure_window: default_failure_window(),
+        }
+    }
+}
+
+impl Default for PairingConfig {
+    fn default() -> Self {
+        Self {
+            code_expiry: default_pairing_code_expiry(),
+            max_pending_codes: default_max_pending_codes(),
+            redeem_attempts: default_redeem_attempts(),
         }
     }
 }
 
-impl Default for Config {
+impl Default for BlobConfig {
     fn default() -> Self {
         Self {
-            server: ServerConfig::default(),
-            app: AppConfig::default(),
-            websocket: WebSocketConfig::default(),
-            auth: AuthConfig::default(),
-            user: UserConfig::default(),
-            clipboard: ClipboardConfig::default(),
+            chunk_size: default_blob_chunk_size(),
+            max_size: default_blob_max_size(),
+            upload_expiry: default_blob_upload_expiry(),
+            max_user_bytes: default_blob_max_user_bytes(),
+            max_pending_uploads: default_blob_max_pending_uploads(),
         }
     }
 }
 
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            history_size: default_history_size(),
+            broadcast_capacity: default_broadcast_capacity(),
+        }
+    }
+}
+
+/// Parses a comma-separated list, skipping blank entries. A bad entry fails
+/// the whole list: silently dropping a key or an admin id is worse than
+/// refusing to start.
+fn parse_list<T: FromStr>(value: &str) -> Result<Vec<T>, String>
+where
+    T::Err: Display,
+{
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse().map_err(|e: T::Err| e.to_string()))
+        .collect()
+}
+
 impl Config {
+    /// Reads the configuration from the environment. Settings that decide
+    /// how tokens are signed or who is an admin must parse; a mistyped value
+    /// panics rather than falling back to a default.
     pub fn load() -> Self {
         dotenv().ok();
 
-        let config = Config {
+        Config {
             clipboard: ClipboardConfig {
                 retention_period: std::env::var("RETENTION_PERIOD")
                     .ok()
@@ -170,6 +484,14 @@ impl Config {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_max_size()),
+                max_format_size: std::env::var("MAX_CLIPBOARD_FORMAT_SIZE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_size()),
+                max_formats: std::env::var("MAX_CLIPBOARD_FORMATS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_formats()),
                 broadcast_capacity: 3000,
             },
             user: UserConfig {
@@ -189,10 +511,36 @@ impl Config {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_memory_size()),
+                max_password_length: std::env::var("MAX_PASSWORD_LENGTH")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_password_length()),
+                password_character_classes: std::env::var("PASSWORD_CHARACTER_CLASSES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_password_character_classes()),
+                min_password_score: std::env::var("MIN_PASSWORD_SCORE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_min_password_score()),
+                password_blocklist_path: std::env::var("PASSWORD_BLOCKLIST_PATH").ok(),
+                max_devices: std::env::var("MAX_DEVICES_PER_USER")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_devices()),
             },
             auth: AuthConfig {
                 jwt_secret: std::env::var("JWT_SECRET")
                     .unwrap_or_else(|_| "your-secret-key".to_string()),
+                jwt_key_id: std::env::var("JWT_KEY_ID")
+                    .unwrap_or_else(|_| default_jwt_key_id()),
+                jwt_algorithm: std::env::var("JWT_ALGORITHM")
+                    .map(|v| v.parse().unwrap_or_else(|e| panic!("Invalid JWT_ALGORITHM: {}", e)))
+                    .unwrap_or(default_jwt_algorithm()),
+                jwt_private_key_path: std::env::var("JWT_PRIVATE_KEY_PATH").ok(),
+                previous_keys: std::env::var("JWT_PREVIOUS_KEYS")
+                    .map(|v| parse_list(&v).unwrap_or_else(|e| panic!("Invalid JWT_PREVIOUS_KEYS: {}", e)))
+                    .unwrap_or_default(),
                 access_token_expiry: std::env::var("ACCESS_TOKEN_EXPIRY")
                     .ok()
                     .and_then(|v| v.parse().ok())
@@ -201,12 +549,33 @@ impl Config {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_refresh_token_expiry()),
+                mfa_token_expiry: std::env::var("MFA_TOKEN_EXPIRY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_mfa_token_expiry()),
+                totp_issuer: std::env::var("TOTP_ISSUER")
+                    .unwrap_or_else(|_| default_totp_issuer()),
+                admin_user_ids: std::env::var("ADMIN_USER_IDS")
+                    .map(|v| parse_list(&v).unwrap_or_else(|e| panic!("Invalid ADMIN_USER_IDS: {}", e)))
+                    .unwrap_or_default(),
             },
             websocket: WebSocketConfig {
                 channel_capacity: std::env::var("WS_CHANNEL_CAPACITY")
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_channel_capacity()),
+                replay_limit: std::env::var("WS_REPLAY_LIMIT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_replay_limit()),
+                heartbeat_interval: std::env::var("WS_HEARTBEAT_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_heartbeat_interval()),
+                heartbeat_timeout: std::env::var("WS_HEARTBEAT_TIMEOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_heartbeat_timeout()),
             },
             server: ServerConfig {
                 host: std::env::var("SERVER_HOST")
@@ -215,6 +584,10 @@ impl Config {
                     .ok()
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_port()),
+                trust_forwarded_for: std::env::var("TRUST_FORWARDED_FOR")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_trust_forwarded_for()),
             },
             app: AppConfig {
                 history_size: std::env::var("HISTORY_SIZE")
@@ -226,9 +599,93 @@ impl Config {
                     .and_then(|v| v.parse().ok())
                     .unwrap_or(default_broadcast_capacity()),
             },
-        };
-
-        config
+            storage: StorageConfig {
+                backend: std::env::var("STORAGE_BACKEND")
+                    .map(|v| v.parse().unwrap_or_else(|e| panic!("Invalid STORAGE_BACKEND: {}", e)))
+                    .unwrap_or(default_storage_backend()),
+                database_path: std::env::var("DATABASE_PATH")
+                    .unwrap_or_else(|_| default_database_path()),
+                journal_dir: std::env::var("JOURNAL_DIR")
+                    .unwrap_or_else(|_| default_journal_dir()),
+                journal_compact_threshold: std::env::var("JOURNAL_COMPACT_THRESHOLD")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_journal_compact_threshold()),
+            },
+            maintenance: MaintenanceConfig {
+                clipboard_cleanup_interval: std::env::var("CLIPBOARD_CLEANUP_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_clipboard_cleanup_interval()),
+                token_prune_interval: std::env::var("TOKEN_PRUNE_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_token_prune_interval()),
+                blob_prune_interval: std::env::var("BLOB_PRUNE_INTERVAL")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_blob_prune_interval()),
+            },
+            throttle: ThrottleConfig {
+                account_attempts: std::env::var("LOGIN_ACCOUNT_ATTEMPTS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_account_attempts()),
+                ip_attempts: std::env::var("LOGIN_IP_ATTEMPTS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_ip_attempts()),
+                base_lockout: std::env::var("LOGIN_BASE_LOCKOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_base_lockout()),
+                max_lockout: std::env::var("LOGIN_MAX_LOCKOUT")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_lockout()),
+                failure_window: std::env::var("LOGIN_FAILURE_WINDOW")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_failure_window()),
+            },
+            pairing: PairingConfig {
+                code_expiry: std::env::var("PAIRING_CODE_EXPIRY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_pairing_code_expiry()),
+                max_pending_codes: std::env::var("PAIRING_MAX_PENDING_CODES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_max_pending_codes()),
+                redeem_attempts: std::env::var("PAIRING_REDEEM_ATTEMPTS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_redeem_attempts()),
+            },
+            blob: BlobConfig {
+                chunk_size: std::env::var("BLOB_CHUNK_SIZE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|size| *size > 0)
+                    .unwrap_or(default_blob_chunk_size()),
+                max_size: std::env::var("BLOB_MAX_SIZE")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_blob_max_size()),
+                upload_expiry: std::env::var("BLOB_UPLOAD_EXPIRY")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_blob_upload_expiry()),
+                max_user_bytes: std::env::var("BLOB_MAX_USER_BYTES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_blob_max_user_bytes()),
+                max_pending_uploads: std::env::var("BLOB_MAX_PENDING_UPLOADS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(default_blob_max_pending_uploads()),
+            },
+        }
     }
 
     pub fn server_addr(&self) -> SocketAddr {
@@ -256,4 +713,54 @@ mod tests {
         let addr = config.server_addr();
         assert_eq!(addr.port(), 3000);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_storage_backend_from_str() {
+        assert_eq!("sqlite".parse::<StorageBackend>(), Ok(StorageBackend::Sqlite));
+        assert_eq!("Memory".parse::<StorageBackend>(), Ok(StorageBackend::Memory));
+        assert_eq!("journal".parse::<StorageBackend>(), Ok(StorageBackend::Journal));
+        assert!("postgres".parse::<StorageBackend>().is_err());
+        assert_eq!(Config::default().storage.backend, StorageBackend::Memory);
+    }
+
+    #[test]
+    fn test_jwt_algorithm_from_str() {
+        assert_eq!("RS256".parse::<JwtAlgorithm>(), Ok(JwtAlgorithm::Rs256));
+        assert_eq!("ed25519".parse::<JwtAlgorithm>(), Ok(JwtAlgorithm::EdDsa));
+        assert!("none".parse::<JwtAlgorithm>().is_err());
+        assert_eq!(Config::default().auth.jwt_algorithm, JwtAlgorithm::Hs256);
+    }
+
+    #[test]
+    fn test_previous_key_from_str() {
+        assert_eq!(
+            "old=s3cret".parse::<PreviousKeyConfig>(),
+            Ok(PreviousKeyConfig {
+                kid: "old".to_string(),
+                algorithm: JwtAlgorithm::Hs256,
+                key: "s3cret".to_string(),
+                retire_at: None,
+            })
+        );
+        let key: PreviousKeyConfig = "old@1700000000=a=b".parse().unwrap();
+        assert_eq!(key.retire_at, Some(1_700_000_000));
+        assert_eq!(key.key, "a=b");
+        let key: PreviousKeyConfig = "old@1700000000:RS256=/etc/clipman/old.pem".parse().unwrap();
+        assert_eq!((key.algorithm, key.retire_at), (JwtAlgorithm::Rs256, Some(1_700_000_000)));
+        assert_eq!(key.key, "/etc/clipman/old.pem");
+        assert!("old:none=secret".parse::<PreviousKeyConfig>().is_err());
+        assert!("no-secret".parse::<PreviousKeyConfig>().is_err());
+        assert!("old@soon=secret".parse::<PreviousKeyConfig>().is_err());
+        assert!("=secret".parse::<PreviousKeyConfig>().is_err());
+    }
+
+    #[test]
+    fn test_lists_reject_bad_entries() {
+        let admins: Vec<Uuid> = parse_list(" 00000000-0000-0000-0000-000000000001, ,").unwrap();
+        assert_eq!(admins, vec![Uuid::from_u128(1)]);
+        assert!(parse_list::<Uuid>("00000000-0000-0000-0000-000000000001,not-a-uuid").is_err());
+
+        let error = parse_list::<PreviousKeyConfig>("old=s3cret,typo").unwrap_err();
+        assert!(!error.contains("s3cret"), "secrets must not end up in startup errors");
+    }
+}
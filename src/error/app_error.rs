@@ -1,6 +1,6 @@
 use axum::{
     response::{IntoResponse, Response},
-    http::StatusCode,
+    http::{header, StatusCode},
     Json,
 };
 use serde::Serialize;
@@ -12,15 +12,26 @@ pub struct ErrorResponse {
     pub code: u16,
     pub message: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub details: Option<String>,
+    pub details: Option<serde_json::Value>,
+}
+
+/// One password policy rule a password failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyViolation {
+    pub rule: &'static str,
+    pub message: String,
 }
 
 #[derive(Debug)]
 pub enum AppError {
     // Auth errors
     Unauthorized(String),
+    Forbidden(String),
     InvalidToken,
     TokenExpired,
+    SessionNotFound(Uuid),
+    /// Login is locked out; retry after this many seconds.
+    TooManyAttempts(u64),
     // User errors
     UserNotFound(Uuid),
     UserAlreadyExists(String),
@@ -32,10 +43,23 @@ pub enum AppError {
     // Clipboard errors
     ClipboardNotFound(Uuid),
     InvalidClipboardData(String),
+    // Blob errors
+    BlobNotFound(Uuid),
+    /// The upload is finished; its chunks can no longer change.
+    BlobComplete(Uuid),
+    /// The upload is still missing chunks.
+    BlobIncomplete(Uuid),
+    /// A clip still refers to the blob.
+    BlobInUse(Uuid),
+    /// A new upload would take the user past a blob quota.
+    BlobQuotaExceeded(String),
+    /// A download range outside a blob of this many bytes.
+    RangeNotSatisfiable(u64),
     // Database errors
     DatabaseError(String),
     // Validation errors
     ValidationError(String),
+    PasswordRejected(Vec<PolicyViolation>),
     // Generic errors
     InternalError(String),
     // Connection and data transmission errors
@@ -50,8 +74,11 @@ impl fmt::Display for AppError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Unauthorized(msg) => write!(f, "Unauthorized: {}", msg),
+            Self::Forbidden(msg) => write!(f, "Forbidden: {}", msg),
             Self::InvalidToken => write!(f, "Invalid token"),
             Self::TokenExpired => write!(f, "Token expired"),
+            Self::SessionNotFound(id) => write!(f, "Session not found: {}", id),
+            Self::TooManyAttempts(retry_after) => write!(f, "Too many login attempts, retry in {} seconds", retry_after),
             Self::UserNotFound(id) => write!(f, "User not found: {}", id),
             Self::UserAlreadyExists(username) => write!(f, "User already exists: {}", username),
             Self::InvalidCredentials => write!(f, "Invalid credentials"),
@@ -60,8 +87,18 @@ impl fmt::Display for AppError {
             Self::TooManyDevices => write!(f, "Too many devices"),
             Self::ClipboardNotFound(id) => write!(f, "Clipboard not found: {}", id),
             Self::InvalidClipboardData(msg) => write!(f, "Invalid clipboard data: {}", msg),
+            Self::BlobNotFound(id) => write!(f, "Blob not found: {}", id),
+            Self::BlobComplete(id) => write!(f, "Blob is already complete: {}", id),
+            Self::BlobIncomplete(id) => write!(f, "Blob upload is not finished: {}", id),
+            Self::BlobInUse(id) => write!(f, "Blob is still referenced by a clip: {}", id),
+            Self::BlobQuotaExceeded(msg) => write!(f, "Blob quota exceeded: {}", msg),
+            Self::RangeNotSatisfiable(size) => write!(f, "Range not satisfiable for {} bytes", size),
             Self::DatabaseError(msg) => write!(f, "Database error: {}", msg),
             Self::ValidationError(msg) => write!(f, "Validation error: {}", msg),
+            Self::PasswordRejected(violations) => {
+                let messages: Vec<&str> = violations.iter().map(|v| v.message.as_str()).collect();
+                write!(f, "Password rejected: {}", messages.join("; "))
+            }
             Self::InternalError(msg) => write!(f, "Internal error: {}", msg),
             Self::WebSocketConnectionError(msg) => write!(f, "WebSocket connection error: {}", msg),
             Self::WebSocketMessageError(msg) => write!(f, "WebSocket message error: {}", msg),
@@ -75,12 +112,15 @@ impl AppError {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::Unauthorized(_) | Self::InvalidToken | Self::TokenExpired => StatusCode::UNAUTHORIZED,
-            Self::UserNotFound(_) | Self::DeviceNotFound(_) | Self::ClipboardNotFound(_) => StatusCode::NOT_FOUND,
-            Self::UserAlreadyExists(_) => StatusCode::CONFLICT,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::UserNotFound(_) | Self::DeviceNotFound(_) | Self::ClipboardNotFound(_) | Self::SessionNotFound(_) | Self::BlobNotFound(_) => StatusCode::NOT_FOUND,
+            Self::RangeNotSatisfiable(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::BlobQuotaExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UserAlreadyExists(_) | Self::BlobComplete(_) | Self::BlobIncomplete(_) | Self::BlobInUse(_) => StatusCode::CONFLICT,
             Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
             Self::DeviceUnauthorized(_) => StatusCode::FORBIDDEN,
-            Self::TooManyDevices => StatusCode::TOO_MANY_REQUESTS,
-            Self::ValidationError(_) | Self::InvalidClipboardData(_) => StatusCode::BAD_REQUEST,
+            Self::TooManyDevices | Self::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            Self::ValidationError(_) | Self::InvalidClipboardData(_) | Self::PasswordRejected(_) => StatusCode::BAD_REQUEST,
             Self::DatabaseError(_) | Self::InternalError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::WebSocketConnectionError(_) => StatusCode::BAD_GATEWAY,
             Self::WebSocketMessageError(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -93,7 +133,10 @@ impl AppError {
         ErrorResponse {
             code: self.status_code().as_u16(),
             message: self.to_string(),  // Now we can use to_string() because we implemented Display
-            details: None,
+            details: match self {
+                Self::PasswordRejected(violations) => serde_json::to_value(violations).ok(),
+                _ => None,
+            },
         }
     }
 }
@@ -102,7 +145,15 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = self.status_code();
         let body = Json(self.error_response());
-        (status, body).into_response()
+        match self {
+            Self::TooManyAttempts(retry_after) => {
+                (status, [(header::RETRY_AFTER, retry_after.to_string())], body).into_response()
+            }
+            Self::RangeNotSatisfiable(size) => {
+                (status, [(header::CONTENT_RANGE, format!("bytes */{}", size))], body).into_response()
+            }
+            _ => (status, body).into_response(),
+        }
     }
 }
 
@@ -1,3 +1,3 @@
 mod app_error;
 
-pub use app_error::{AppError, AppResult, ErrorResponse};
\ No newline at end of file
+pub use app_error::{AppError, AppResult, ErrorResponse, PolicyViolation};
\ No newline at end of file
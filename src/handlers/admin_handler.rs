@@ -0,0 +1,51 @@
+use axum::{
+    routing::{delete, get},
+    Router,
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use std::net::IpAddr;
+use crate::{
+    services::{LoginLock, ThrottleKey},
+    state::AppState,
+};
+use super::AdminUser;
+
+pub fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/admin/login-locks", get(list_login_locks))
+        .route("/admin/login-locks/accounts/:username", delete(unlock_account))
+        .route("/admin/login-locks/ips/:ip", delete(unlock_ip))
+}
+
+async fn list_login_locks(
+    State(state): State<AppState>,
+    _admin: AdminUser,
+) -> Json<Vec<LoginLock>> {
+    Json(state.throttle_service.locks().await)
+}
+
+async fn unlock(state: &AppState, admin: &AdminUser, key: ThrottleKey) -> StatusCode {
+    tracing::info!(target: "security", "Admin {} unlocking {:?}", admin.0.user_id, key);
+    match state.throttle_service.unlock(&key).await {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::NOT_FOUND,
+    }
+}
+
+async fn unlock_account(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    Path(username): Path<String>,
+) -> StatusCode {
+    unlock(&state, &admin, ThrottleKey::account(&username)).await
+}
+
+async fn unlock_ip(
+    State(state): State<AppState>,
+    admin: AdminUser,
+    Path(ip): Path<IpAddr>,
+) -> StatusCode {
+    unlock(&state, &admin, ThrottleKey::Ip(ip)).await
+}
@@ -1,21 +1,26 @@
 use axum::{
-    routing::post,
+    routing::{get, post},
     Router,
     Json,
     extract::State,
     // response::{IntoResponse, Response},
     http::StatusCode,
 };
+use jsonwebtoken::jwk::JwkSet;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use crate::{
-    error::AppResult,
+    error::{AppError, AppResult},
     state::AppState,
 };
+use super::ClientInfo;
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     username: String,
     password: String,
+    /// Binds the issued tokens to one of the user's registered devices.
+    device_id: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +29,25 @@ pub struct TokenResponse {
     refresh_token: String,
 }
 
+/// What `/login` returns: tokens, or a challenge when the account has 2FA.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenResponse),
+    MfaRequired {
+        mfa_required: bool,
+        /// Exchange at `/login/mfa` together with a code.
+        mfa_token: String,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    mfa_token: String,
+    /// Authenticator or recovery code.
+    code: String,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct RefreshRequest {
     refresh_token: String,
@@ -32,39 +56,106 @@ pub struct RefreshRequest {
 pub fn auth_routes() -> Router<AppState> {
     Router::new()
         .route("/login", post(login))
+        .route("/login/mfa", post(login_mfa))
         .route("/refresh", post(refresh))
         .route("/logout", post(logout))
+        .route("/.well-known/jwks.json", get(jwks))
 }
 
 async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(login_req): Json<LoginRequest>,
+) -> AppResult<Json<LoginResponse>> {
+    let throttle = &state.throttle_service;
+    throttle.check(&login_req.username, client.ip_address).await?;
+
+    // Unknown usernames fail exactly like wrong passwords, in as much time
+    let user = match state.user_service.authenticate(&login_req.username, &login_req.password).await? {
+        Some(user) => user,
+        None => {
+            throttle.record_failure(&login_req.username, client.ip_address).await;
+            return Err(AppError::InvalidCredentials);
+        }
+    };
+
+    if let Some(device_id) = login_req.device_id {
+        state.device_service.verify_device(device_id, user.id).await?;
+    }
+
+    // With 2FA the account is only cleared once the code checks out too
+    if user.mfa_enabled() {
+        let mfa_token = state.auth_service.create_mfa_token(user.id, login_req.device_id)?;
+        return Ok(Json(LoginResponse::MfaRequired {
+            mfa_required: true,
+            mfa_token,
+        }));
+    }
+
+    throttle.record_success(&user.username).await;
+    let tokens = start_session(&state, user.id, login_req.device_id, client).await?;
+    Ok(Json(LoginResponse::Tokens(tokens)))
+}
+
+async fn login_mfa(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(req): Json<MfaLoginRequest>,
 ) -> AppResult<Json<TokenResponse>> {
-    let user = state.user_service
-        .get_user_by_username(&login_req.username).await?;
-    
-    state.user_service
-        .verify_password(&user, &login_req.password).await?;
-    
+    let claims = state.auth_service.verify_mfa_token(&req.mfa_token).await?;
+    let challenge_id = claims.jti.ok_or(AppError::InvalidToken)?;
+    let user = state.user_service.get_user_by_id(claims.sub).await?;
+    let throttle = &state.throttle_service;
+    throttle.check(&user.username, client.ip_address).await?;
+
+    let verified = state.mfa_service
+        .verify_login(user.id, challenge_id, claims.exp as u64, &req.code)
+        .await;
+    if let Err(AppError::InvalidCredentials) = verified {
+        throttle.record_failure(&user.username, client.ip_address).await;
+    }
+    verified?;
+    throttle.record_success(&user.username).await;
+
+    // Spend the challenge before the session exists, so a second exchange
+    // racing this one cannot start a session of its own
+    state.auth_service.consume_mfa_token(&req.mfa_token).await?;
+    let tokens = start_session(&state, claims.sub, claims.device_id, client).await?;
+    Ok(Json(tokens))
+}
+
+async fn start_session(
+    state: &AppState,
+    user_id: Uuid,
+    device_id: Option<Uuid>,
+    client: ClientInfo,
+) -> AppResult<TokenResponse> {
     let (access_token, refresh_token) = state.auth_service
-        .create_token_pair(user.id)?;
-    
-    Ok(Json(TokenResponse {
+        .create_session(
+            user_id,
+            device_id,
+            client.ip_address.map(|ip| ip.to_string()),
+            client.user_agent,
+        )
+        .await?;
+
+    Ok(TokenResponse {
         access_token,
         refresh_token,
-    }))
+    })
 }
 
 async fn refresh(
     State(state): State<AppState>,
     Json(refresh_req): Json<RefreshRequest>,
 ) -> AppResult<Json<TokenResponse>> {
-    let access_token = state.auth_service
+    // The presented refresh token is spent; clients must store the new one
+    let (access_token, refresh_token) = state.auth_service
         .refresh_token(&refresh_req.refresh_token).await?;
     
     Ok(Json(TokenResponse {
         access_token,
-        refresh_token: refresh_req.refresh_token,
+        refresh_token,
     }))
 }
 
@@ -78,3 +169,9 @@ async fn logout(
         Err(e) => (StatusCode::UNAUTHORIZED, Json(e.to_string()))
     }
 }
+
+async fn jwks(
+    State(state): State<AppState>,
+) -> Json<JwkSet> {
+    Json(state.auth_service.jwks())
+}
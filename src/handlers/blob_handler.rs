@@ -0,0 +1,127 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json,
+    Router,
+};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    models::BlobResponse,
+    services::{parse_range, MAX_CHUNK_SIZE},
+    state::AppState,
+};
+use super::AuthUser;
+
+/// Hex SHA-256 of the chunk in the request body.
+pub const CHUNK_CHECKSUM_HEADER: &str = "x-chunk-sha256";
+
+#[derive(Deserialize)]
+pub struct CreateBlobRequest {
+    size: u64,
+}
+
+pub fn blob_routes() -> Router<AppState> {
+    Router::new()
+        .route("/blobs", post(create_blob))
+        .route("/blobs/:id", get(get_blob).delete(delete_blob))
+        .route(
+            "/blobs/:id/chunks/:index",
+            put(upload_chunk).layer(DefaultBodyLimit::max(MAX_CHUNK_SIZE as usize)),
+        )
+        .route("/blobs/:id/content", get(download_blob))
+}
+
+/// Starts an upload. The response gives the chunk size to split it by.
+async fn create_blob(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CreateBlobRequest>,
+) -> AppResult<Json<BlobResponse>> {
+    let blob = state.blob_service.create_upload(auth.user_id, req.size).await?;
+    Ok(Json(blob.into()))
+}
+
+/// Upload progress; `missing` lists the chunks to send when resuming.
+async fn get_blob(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<BlobResponse>> {
+    let blob = state.blob_service.get_blob(id, auth.user_id).await?;
+    Ok(Json(blob.into()))
+}
+
+async fn upload_chunk(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path((id, index)): Path<(Uuid, u32)>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<Json<BlobResponse>> {
+    let checksum = headers
+        .get(CHUNK_CHECKSUM_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| AppError::ValidationError(format!("{} header is required", CHUNK_CHECKSUM_HEADER)))?;
+    let blob = state.blob_service
+        .put_chunk(id, auth.user_id, index, body.to_vec(), checksum)
+        .await?;
+    Ok(Json(blob.into()))
+}
+
+/// Serves a finished blob, or the part named by a `Range` header. A `Range`
+/// header that cannot be parsed is ignored and the whole blob is sent.
+async fn download_blob(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let blob = state.blob_service.get_blob(id, auth.user_id).await?;
+    if !blob.is_complete() {
+        return Err(AppError::BlobIncomplete(id));
+    }
+
+    let size = blob.size;
+    let range = match headers.get(header::RANGE).and_then(|value| value.to_str().ok()) {
+        Some(value) => parse_range(value, size)?,
+        None => None,
+    };
+    let (status, (start, end)) = match range {
+        Some(range) => (StatusCode::PARTIAL_CONTENT, range),
+        None => (StatusCode::OK, (0, size - 1)),
+    };
+
+    let stream = state.blob_service
+        .clone()
+        .read_range(blob, start, end)
+        .map_err(|e| std::io::Error::other(e.to_string()));
+    let mut response = (
+        status,
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::CONTENT_LENGTH, (end - start + 1).to_string()),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response();
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", start, end, size);
+        response.headers_mut().insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+    }
+    Ok(response)
+}
+
+async fn delete_blob(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<()> {
+    state.blob_service.delete_blob(id, auth.user_id).await
+}
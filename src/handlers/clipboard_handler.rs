@@ -0,0 +1,143 @@
+use axum::{
+    routing::{post, get},
+    Router,
+    Json,
+    extract::{State, Path, Query},
+};
+use serde::Deserialize;
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    models::{ClipFormat, ClipboardData},
+    state::AppState,
+};
+use super::AuthUser;
+
+#[derive(Deserialize)]
+pub struct CreateClipboardRequest {
+    #[serde(default)]
+    content: String,
+    /// Representations besides the plain text, with base64 data.
+    #[serde(default)]
+    formats: Vec<ClipFormat>,
+    /// Only needed when the access token is not bound to a device.
+    device_id: Option<Uuid>,
+    #[serde(default)]
+    sent_at: u64,
+    /// Devices to deliver to; empty or omitted means all of them.
+    #[serde(default)]
+    targets: Vec<Uuid>,
+}
+
+/// `?accept=text/html,image/*` returns clips with only those formats
+/// besides the plain text.
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    accept: Option<String>,
+}
+
+impl FormatQuery {
+    fn accepted(&self) -> Vec<String> {
+        self.accept
+            .iter()
+            .flat_map(|accept| accept.split(','))
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn apply(&self, mut data: ClipboardData) -> ClipboardData {
+        data.retain_formats(&self.accepted());
+        data
+    }
+
+    fn apply_all(&self, mut clips: Vec<ClipboardData>) -> Vec<ClipboardData> {
+        let accept = self.accepted();
+        for data in &mut clips {
+            data.retain_formats(&accept);
+        }
+        clips
+    }
+}
+
+pub fn clipboard_routes() -> Router<AppState> {
+    Router::new()
+        .route("/clipboard", post(create_clipboard))
+        .route("/clipboard/latest", get(get_latest_clipboard))
+        .route("/clipboard/:id", get(get_clipboard).delete(delete_clipboard))
+        .route("/users/:user_id/clipboard", get(get_user_clipboard))
+        .route("/devices/:device_id/clipboard", get(get_device_clipboard))
+}
+
+async fn create_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CreateClipboardRequest>,
+) -> AppResult<Json<ClipboardData>> {
+    let device_id = auth
+        .device_id
+        .or(req.device_id)
+        .ok_or_else(|| AppError::ValidationError("device_id is required".to_string()))?;
+    state.device_service.verify_device(device_id, auth.user_id).await?;
+    state.device_service.verify_devices(&req.targets, auth.user_id).await?;
+
+    let mut data = ClipboardData::new(req.content, device_id, auth.user_id);
+    data.sent_at = req.sent_at;
+    data.formats = req.formats;
+    data.targets = req.targets;
+    let saved = state.clipboard_service.save_clipboard(data).await?;
+    Ok(Json(saved))
+}
+
+async fn get_latest_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(formats): Query<FormatQuery>,
+) -> AppResult<Json<ClipboardData>> {
+    let data = state.clipboard_service.get_latest_clipboard(auth.user_id).await?;
+    Ok(Json(formats.apply(data)))
+}
+
+async fn get_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(formats): Query<FormatQuery>,
+) -> AppResult<Json<ClipboardData>> {
+    let data = state.clipboard_service.get_clipboard(id).await?;
+    if data.user_id != auth.user_id {
+        return Err(AppError::DeviceUnauthorized(id));
+    }
+    Ok(Json(formats.apply(data)))
+}
+
+async fn delete_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<()> {
+    state.clipboard_service.delete_clipboard(id, auth.user_id).await
+}
+
+async fn get_user_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(user_id): Path<Uuid>,
+    Query(formats): Query<FormatQuery>,
+) -> AppResult<Json<Vec<ClipboardData>>> {
+    auth.ensure_user(user_id)?;
+    let history = state.clipboard_service.get_user_clipboard(user_id).await?;
+    Ok(Json(formats.apply_all(history)))
+}
+
+async fn get_device_clipboard(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(device_id): Path<Uuid>,
+    Query(formats): Query<FormatQuery>,
+) -> AppResult<Json<Vec<ClipboardData>>> {
+    state.device_service.verify_device(device_id, auth.user_id).await?;
+    let history = state.clipboard_service.get_device_clipboard(device_id).await?;
+    Ok(Json(formats.apply_all(history)))
+}
@@ -1,5 +1,5 @@
 use axum::{
-    routing::{post, get, delete},
+    routing::{post, get, put, delete},
     Router,
     Json,
     extract::{State, Path},
@@ -8,14 +8,15 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use crate::{
     error::AppResult,
-    models::Device,
+    models::{Device, DeviceResponse, Subscription},
+    services::PairingCode,
     state::AppState,
 };
+use super::{AuthUser, ClientInfo};
 
 #[derive(Deserialize)]
 pub struct RegisterDeviceRequest {
     name: String,
-    user_id: Uuid,
 }
 
 #[derive(Deserialize)]
@@ -23,54 +24,234 @@ pub struct UpdateDeviceRequest {
     name: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct RedeemPairingRequest {
+    code: String,
+    /// Name for the new device.
+    name: String,
+}
+
+/// What a newly paired device gets: its record and tokens bound to it.
+#[derive(Serialize)]
+pub struct PairedDeviceResponse {
+    device: Device,
+    access_token: String,
+    refresh_token: String,
+}
+
 pub fn device_routes() -> Router<AppState> {
     Router::new()
         .route("/devices", post(register_device))
+        .route("/devices/pairing", post(create_pairing_code))
+        .route("/devices/pairing/redeem", post(redeem_pairing_code))
         .route("/devices/:id", get(get_device))
+        .route("/devices/:id", put(update_device))
         .route("/devices/:id/status", post(update_device_status))
+        .route("/devices/:id/subscription", get(get_subscription))
+        .route("/devices/:id/subscription", put(update_subscription))
         .route("/devices/:id", delete(remove_device))
         .route("/users/:user_id/devices", get(get_user_devices))
 }
 
 async fn register_device(
     State(state): State<AppState>,
+    auth: AuthUser,
     Json(req): Json<RegisterDeviceRequest>,
 ) -> AppResult<Json<Device>> {
     let device = state.device_service
-        .register_device(req.user_id, req.name)
+        .register_device(auth.user_id, req.name)
         .await?;
     Ok(Json(device))
 }
 
+async fn create_pairing_code(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<PairingCode>> {
+    let code = state.pairing_service.issue(auth.user_id, auth.device_id).await;
+    Ok(Json(code))
+}
+
+/// Unauthenticated: the code is the credential.
+async fn redeem_pairing_code(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(req): Json<RedeemPairingRequest>,
+) -> AppResult<Json<PairedDeviceResponse>> {
+    let pairing = state.pairing_service.redeem(&req.code, client.ip_address).await?;
+    // The account may have been deleted since the code was issued
+    let user = state.user_service.get_user_by_id(pairing.user_id).await?;
+
+    let device = match state.device_service.register_device(user.id, req.name).await {
+        Ok(device) => device,
+        Err(e) => {
+            state.pairing_service.restore(&req.code, pairing).await;
+            return Err(e);
+        }
+    };
+    let (access_token, refresh_token) = state.auth_service
+        .create_session(
+            user.id,
+            Some(device.id),
+            client.ip_address.map(|ip| ip.to_string()),
+            client.user_agent,
+        )
+        .await?;
+    tracing::info!(
+        target: "security",
+        "Paired device {} with user {} via code from device {:?}",
+        device.id,
+        user.id,
+        pairing.issued_by
+    );
+
+    Ok(Json(PairedDeviceResponse {
+        device,
+        access_token,
+        refresh_token,
+    }))
+}
+
 async fn get_device(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> AppResult<Json<Device>> {
+    state.device_service.verify_device(id, auth.user_id).await?;
     let device = state.device_service.get_device(id).await?;
     Ok(Json(device))
 }
 
+async fn update_device(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateDeviceRequest>,
+) -> AppResult<Json<Device>> {
+    let device = match req.name {
+        Some(name) => state.device_service.rename_device(id, auth.user_id, name).await?,
+        None => {
+            state.device_service.verify_device(id, auth.user_id).await?;
+            state.device_service.get_device(id).await?
+        }
+    };
+    Ok(Json(device))
+}
+
 async fn update_device_status(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> AppResult<Json<Device>> {
+    state.device_service.verify_device(id, auth.user_id).await?;
     let device = state.device_service.update_device_status(id).await?;
     Ok(Json(device))
 }
 
+async fn get_subscription(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<Json<Subscription>> {
+    state.device_service.verify_device(id, auth.user_id).await?;
+    let device = state.device_service.get_device(id).await?;
+    Ok(Json(device.subscription))
+}
+
+async fn update_subscription(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(subscription): Json<Subscription>,
+) -> AppResult<Json<Subscription>> {
+    let device = state.device_service
+        .set_subscription(id, auth.user_id, subscription)
+        .await?;
+    // Takes effect for the device's open sockets right away
+    state.ws_service.apply_subscription(auth.user_id, id, &device.subscription).await;
+    Ok(Json(device.subscription))
+}
+
 async fn remove_device(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
-    // TODO: Get user_id from auth token
-    Path(user_id): Path<Uuid>,
 ) -> AppResult<()> {
-    state.device_service.remove_device(id, user_id).await
+    state.device_service.remove_device(id, auth.user_id).await?;
+
+    // Tokens bound to the device stop working and its sockets close
+    let revoked = state.auth_service.revoke_device_sessions(auth.user_id, id).await?;
+    let closed = state.ws_service.disconnect_device(auth.user_id, id).await;
+    tracing::info!(
+        target: "security",
+        "Removed device {} of user {}, revoked {} sessions, closed {} connections",
+        id,
+        auth.user_id,
+        revoked.len(),
+        closed
+    );
+    Ok(())
 }
 
 async fn get_user_devices(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(user_id): Path<Uuid>,
-) -> AppResult<Json<Vec<Device>>> {
+) -> AppResult<Json<Vec<DeviceResponse>>> {
+    auth.ensure_user(user_id)?;
     let devices = state.device_service.get_user_devices(user_id).await?;
-    Ok(Json(devices))
-}
\ No newline at end of file
+    let online = state.ws_service.online_devices(user_id).await;
+    Ok(Json(
+        devices
+            .into_iter()
+            .map(|device| DeviceResponse {
+                online: online.contains(&device.id),
+                device,
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, error::AppError};
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            ip_address: None,
+            user_agent: None,
+        }
+    }
+
+    fn redeem_request(code: &str, name: &str) -> Json<RedeemPairingRequest> {
+        Json(RedeemPairingRequest {
+            code: code.to_string(),
+            name: name.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_redeeming_at_the_device_cap_keeps_the_code() {
+        let mut config = Config::default();
+        config.user.max_devices = 1;
+        let state = AppState::new(config).await;
+        let user = state.user_service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+        let laptop = state.device_service.register_device(user.id, "Laptop".to_string()).await.unwrap();
+        let code = state.pairing_service.issue(user.id, Some(laptop.id)).await;
+
+        let full = redeem_pairing_code(State(state.clone()), client(), redeem_request(&code.code, "Phone")).await;
+        assert!(matches!(full, Err(AppError::TooManyDevices)));
+
+        // Once a slot is free the same code still pairs the device
+        state.device_service.remove_device(laptop.id, user.id).await.unwrap();
+        let Json(paired) = redeem_pairing_code(State(state.clone()), client(), redeem_request(&code.code, "Phone"))
+            .await
+            .unwrap();
+        assert_eq!(paired.device.user_id, user.id);
+        assert_eq!(paired.device.name, "Phone");
+    }
+}
@@ -0,0 +1,279 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts},
+};
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    services::TokenType,
+    state::AppState,
+};
+
+/// The caller identified by a valid access token.
+///
+/// Tokens are read from `Authorization: Bearer <token>`. WebSocket upgrades
+/// may pass `?token=<token>` instead, since browsers cannot set headers on
+/// the handshake.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub user_id: Uuid,
+    pub device_id: Option<Uuid>,
+    /// Session the token belongs to; `None` for tokens issued before sessions.
+    pub session_id: Option<Uuid>,
+    pub token_type: TokenType,
+}
+
+impl AuthUser {
+    /// Rejects requests that act on another user's resources.
+    pub fn ensure_user(&self, user_id: Uuid) -> AppResult<()> {
+        if self.user_id != user_id {
+            return Err(AppError::Forbidden("Cannot access another user's resources".to_string()));
+        }
+        Ok(())
+    }
+}
+
+fn bearer_token(parts: &Parts) -> Option<&str> {
+    parts
+        .headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn websocket_query_token(parts: &Parts) -> Option<&str> {
+    let is_upgrade = parts
+        .headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    if !is_upgrade {
+        return None;
+    }
+
+    parts
+        .uri
+        .query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(parts)
+            .or_else(|| websocket_query_token(parts))
+            .ok_or_else(|| AppError::Unauthorized("Missing access token".to_string()))?;
+
+        let claims = state.auth_service.verify_token(token).await?;
+        match claims.token_type {
+            TokenType::Access => {}
+            TokenType::Refresh => {
+                return Err(AppError::Unauthorized("Refresh tokens cannot be used for access".to_string()));
+            }
+            TokenType::MfaPending => {
+                return Err(AppError::Unauthorized("MFA tokens cannot be used for access".to_string()));
+            }
+        }
+
+        Ok(Self {
+            user_id: claims.sub,
+            device_id: claims.device_id,
+            session_id: claims.family_id,
+            token_type: claims.token_type,
+        })
+    }
+}
+
+/// A caller listed in `auth.admin_user_ids`.
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthUser);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        if !state.config.auth.admin_user_ids.contains(&user.user_id) {
+            return Err(AppError::Forbidden("Admin access required".to_string()));
+        }
+        Ok(Self(user))
+    }
+}
+
+/// Where a request came from, as recorded on the sessions it starts.
+///
+/// The IP is the peer address unless `server.trust_forwarded_for` is set,
+/// in which case the first `X-Forwarded-For` entry wins.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+/// Longer user agents are cut off; they only need to be recognisable.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+fn forwarded_for(parts: &Parts) -> Option<IpAddr> {
+    parts
+        .headers
+        .get("x-forwarded-for")?
+        .to_str()
+        .ok()?
+        .split(',')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let ip_address = match state.config.server.trust_forwarded_for {
+            true => forwarded_for(parts).or(peer),
+            false => peer,
+        };
+
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+
+        Ok(Self { ip_address, user_agent })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use crate::config::Config;
+
+    async fn extract(state: &AppState, request: Request<()>) -> AppResult<AuthUser> {
+        let (mut parts, _) = request.into_parts();
+        AuthUser::from_request_parts(&mut parts, state).await
+    }
+
+    #[tokio::test]
+    async fn test_bearer_access_token() {
+        let state = AppState::new(Config::default()).await;
+        let user_id = Uuid::new_v4();
+        let device_id = Uuid::new_v4();
+        let (access_token, _) = state.auth_service.create_token_pair(user_id, Some(device_id)).await.unwrap();
+
+        let request = Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", access_token))
+            .body(())
+            .unwrap();
+        let user = extract(&state, request).await.unwrap();
+        assert_eq!(user.user_id, user_id);
+        assert_eq!(user.device_id, Some(device_id));
+        assert!(user.session_id.is_some());
+        assert_eq!(user.token_type, TokenType::Access);
+        assert!(user.ensure_user(user_id).is_ok());
+        assert!(matches!(user.ensure_user(Uuid::new_v4()), Err(AppError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn test_rejects_missing_and_refresh_tokens() {
+        let state = AppState::new(Config::default()).await;
+        let (_, refresh_token) = state.auth_service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        let missing = extract(&state, Request::builder().body(()).unwrap()).await;
+        assert!(matches!(missing, Err(AppError::Unauthorized(_))));
+
+        let request = Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", refresh_token))
+            .body(())
+            .unwrap();
+        assert!(matches!(extract(&state, request).await, Err(AppError::Unauthorized(_))));
+
+        let request = Request::builder()
+            .header(header::AUTHORIZATION, "Bearer not-a-jwt")
+            .body(())
+            .unwrap();
+        assert!(matches!(extract(&state, request).await, Err(AppError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_query_token_only_for_websocket_upgrades() {
+        let state = AppState::new(Config::default()).await;
+        let user_id = Uuid::new_v4();
+        let (access_token, _) = state.auth_service.create_token_pair(user_id, None).await.unwrap();
+        let uri = format!("/ws?token={}", access_token);
+
+        let upgrade = Request::builder()
+            .uri(&uri)
+            .header(header::UPGRADE, "websocket")
+            .body(())
+            .unwrap();
+        assert_eq!(extract(&state, upgrade).await.unwrap().user_id, user_id);
+
+        let plain = Request::builder().uri(&uri).body(()).unwrap();
+        assert!(extract(&state, plain).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_listed_user() {
+        let admin_id = Uuid::new_v4();
+        let mut config = Config::default();
+        config.auth.admin_user_ids = vec![admin_id];
+        let state = AppState::new(config).await;
+
+        let request = |token: &str| {
+            let request = Request::builder()
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(())
+                .unwrap();
+            request.into_parts().0
+        };
+        let (admin_token, _) = state.auth_service.create_token_pair(admin_id, None).await.unwrap();
+        let (user_token, _) = state.auth_service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        let admin = AdminUser::from_request_parts(&mut request(&admin_token), &state).await.unwrap();
+        assert_eq!(admin.0.user_id, admin_id);
+        let user = AdminUser::from_request_parts(&mut request(&user_token), &state).await;
+        assert!(matches!(user, Err(AppError::Forbidden(_))));
+    }
+
+    #[tokio::test]
+    async fn test_client_info_only_trusts_forwarded_for_when_configured() {
+        let peer: SocketAddr = "198.51.100.4:5000".parse().unwrap();
+        let request = || {
+            let mut request = Request::builder()
+                .header(header::USER_AGENT, "clipman-cli/0.3")
+                .header("x-forwarded-for", "203.0.113.7, 10.0.0.1")
+                .body(())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(peer));
+            request.into_parts().0
+        };
+
+        let state = AppState::new(Config::default()).await;
+        let info = ClientInfo::from_request_parts(&mut request(), &state).await.unwrap();
+        assert_eq!(info.ip_address, Some(peer.ip()));
+        assert_eq!(info.user_agent.as_deref(), Some("clipman-cli/0.3"));
+
+        let mut config = Config::default();
+        config.server.trust_forwarded_for = true;
+        let state = AppState::new(config).await;
+        let info = ClientInfo::from_request_parts(&mut request(), &state).await.unwrap();
+        assert_eq!(info.ip_address, Some("203.0.113.7".parse().unwrap()));
+    }
+}
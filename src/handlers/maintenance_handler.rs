@@ -0,0 +1,24 @@
+use axum::{
+    routing::get,
+    Router,
+    Json,
+    extract::State,
+};
+use crate::{
+    error::AppResult,
+    services::MaintenanceStatus,
+    state::AppState,
+};
+use super::AuthUser;
+
+pub fn maintenance_routes() -> Router<AppState> {
+    Router::new()
+        .route("/maintenance/status", get(get_status))
+}
+
+async fn get_status(
+    State(state): State<AppState>,
+    _auth: AuthUser,
+) -> AppResult<Json<MaintenanceStatus>> {
+    Ok(Json(state.maintenance_service.status().await))
+}
@@ -0,0 +1,68 @@
+use axum::{
+    routing::post,
+    Router,
+    Json,
+    extract::State,
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use crate::{
+    error::AppResult,
+    services::TotpEnrollment,
+    state::AppState,
+};
+use super::AuthUser;
+
+#[derive(Debug, Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+pub fn mfa_routes() -> Router<AppState> {
+    Router::new()
+        .route("/mfa/totp/enroll", post(enroll))
+        .route("/mfa/totp/confirm", post(confirm))
+        .route("/mfa/totp/disable", post(disable))
+        .route("/mfa/recovery-codes", post(regenerate_recovery_codes))
+}
+
+async fn enroll(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<TotpEnrollment>> {
+    Ok(Json(state.mfa_service.enroll(auth.user_id).await?))
+}
+
+async fn confirm(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CodeRequest>,
+) -> AppResult<StatusCode> {
+    state.mfa_service.confirm(auth.user_id, &req.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn disable(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CodeRequest>,
+) -> AppResult<StatusCode> {
+    state.mfa_service.disable(auth.user_id, &req.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(req): Json<CodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    let recovery_codes = state.mfa_service
+        .regenerate_recovery_codes(auth.user_id, &req.code)
+        .await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
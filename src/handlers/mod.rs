@@ -1,9 +1,23 @@
 mod auth_handler;
 mod user_handler;
 mod device_handler;
+mod clipboard_handler;
+mod blob_handler;
 mod websocket_handler;
+mod maintenance_handler;
+mod session_handler;
+mod mfa_handler;
+mod admin_handler;
+mod extractors;
 
 pub use auth_handler::auth_routes;
 pub use user_handler::user_routes;
 pub use device_handler::device_routes;
-pub use websocket_handler::websocket_handler;
\ No newline at end of file
+pub use clipboard_handler::clipboard_routes;
+pub use blob_handler::{blob_routes, CHUNK_CHECKSUM_HEADER};
+pub use websocket_handler::websocket_handler;
+pub use maintenance_handler::maintenance_routes;
+pub use session_handler::session_routes;
+pub use mfa_handler::mfa_routes;
+pub use admin_handler::admin_routes;
+pub use extractors::{AuthUser, AdminUser, ClientInfo};
\ No newline at end of file
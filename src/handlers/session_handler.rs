@@ -0,0 +1,44 @@
+use axum::{
+    routing::{delete, get},
+    Router,
+    Json,
+    extract::{Path, State},
+    http::StatusCode,
+};
+use uuid::Uuid;
+use crate::{
+    error::AppResult,
+    models::SessionResponse,
+    state::AppState,
+};
+use super::AuthUser;
+
+pub fn session_routes() -> Router<AppState> {
+    Router::new()
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+}
+
+async fn list_sessions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<Vec<SessionResponse>>> {
+    let sessions = state.auth_service.list_sessions(auth.user_id).await?;
+    Ok(Json(
+        sessions
+            .into_iter()
+            .map(|session| SessionResponse::new(session, auth.session_id))
+            .collect(),
+    ))
+}
+
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> AppResult<StatusCode> {
+    state.auth_service.revoke_session(auth.user_id, id).await?;
+    let closed = state.ws_service.disconnect_session(auth.user_id, id).await;
+    tracing::info!("Revoked session {} of user {}, closed {} connections", id, auth.user_id, closed);
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -2,10 +2,9 @@ use axum::{
     routing::{post, get, put, delete},
     Router,
     Json,
-    extract::{State, Path},
+    extract::{State, Path, Query},
 };
 use serde::{Deserialize, Serialize};
-use chrono::{DateTime, Utc};
 
 use uuid::Uuid;
 use crate::{
@@ -13,6 +12,7 @@ use crate::{
     models::User,
     state::AppState,
 };
+use super::AuthUser;
 use crate::models::{UserResponse};
 #[derive(Deserialize)]
 pub struct CreateUserRequest {
@@ -31,6 +31,12 @@ pub struct UpdatePasswordRequest {
     new_password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct Pagination {
+    page: Option<u32>,
+    limit: Option<u32>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
@@ -64,62 +70,68 @@ async fn create_user(
 
 async fn get_user(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> AppResult<Json<User>> {
+    auth.ensure_user(id)?;
     let user = state.user_service.get_user_by_id(id).await?;
     Ok(Json(user))
 }
 
 async fn update_user(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
-    Json(_req): Json<UpdateUserRequest>,
+    Json(req): Json<UpdateUserRequest>,
 ) -> AppResult<Json<User>> {
-    let user = state.user_service.update_user(id).await?;
-
-    // let user = state.user_service.update_user(id, req.username).await?;
+    auth.ensure_user(id)?;
+    let user = state.user_service.update_user(id, req.username).await?;
     Ok(Json(user))
 }
 
 async fn update_password(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdatePasswordRequest>,
 ) -> AppResult<()> {
-    state.user_service.update_password(id, &req.old_password).await
+    auth.ensure_user(id)?;
+    state.user_service.update_password(id, &req.old_password, &req.new_password).await?;
 
-    // state.user_service.update_password(id, &req.old_password, &req.new_password).await
+    // Whoever knew the old password is logged out everywhere but here
+    let revoked = state.auth_service.revoke_other_sessions(id, auth.session_id).await?;
+    let closed = state.ws_service.disconnect_other_sessions(id, auth.session_id).await;
+    tracing::info!("Password change for user {} revoked {} sessions, closed {} connections", id, revoked.len(), closed);
+    Ok(())
 }
 
 async fn delete_user(
     State(state): State<AppState>,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> AppResult<()> {
-    state.user_service.delete_user(id).await
+    auth.ensure_user(id)?;
+    state.user_service.delete_user(id).await?;
+    let closed = state.ws_service.disconnect_other_sessions(id, None).await;
+    tracing::info!("Closed {} connections of deleted user {}", closed, id);
+    Ok(())
 }
 
 async fn list_users(
     State(state): State<AppState>,
-) -> AppResult<Json<Vec<UserResponse>>> {
+    _auth: AuthUser,
+    Query(pagination): Query<Pagination>,
+) -> AppResult<Json<PaginatedResponse<UserResponse>>> {
     let page = pagination.page.unwrap_or(1);
     let limit = pagination.limit.unwrap_or(10);
     let users = state.user_service.list_users_paginated(page, limit).await?;
-    let total = state.user_service.user_count().await;
-    let responses = users.into_iter()
-        .map(|user| UserResponse {
-            id: user.id,
-            username: user.username,
-            created_at: None,
-            updated_at: None,
-        })
-        .collect();
-    // Ok(Json(responses))
-    Json(PaginatedResponse {
-        data: responses,
+    let total = state.user_service.user_count().await?;
+    Ok(Json(PaginatedResponse {
+        data: users,
         meta: PaginationMeta {
             total,
             page,
             limit,
         },
-    })
-}
\ No newline at end of file
+    }))
+}
@@ -4,12 +4,15 @@ use axum::{
     extract::State,
 };
 use crate::state::AppState;
+use super::AuthUser;
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
+    auth: AuthUser,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| async move {
-        state.ws_service.handle_connection(socket).await
+    tracing::info!("WebSocket connection for user {}", auth.user_id);
+    ws.on_upgrade(move |socket| async move {
+        state.ws_service.handle_connection(socket, auth.user_id, auth.device_id, auth.session_id).await
     })
-}
\ No newline at end of file
+}
@@ -4,5 +4,6 @@ pub mod state;
 
 pub mod models;
 pub mod services;
+pub mod store;
 pub mod handlers;
 pub mod utils;
\ No newline at end of file
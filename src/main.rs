@@ -3,15 +3,16 @@ use tower_http::{
     trace::TraceLayer,
     cors::{CorsLayer, Any},
 };
-use axum::http::{Method, header::CONTENT_TYPE};
+use axum::http::{Method, HeaderName, header::{ACCEPT_RANGES, AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, RANGE, RETRY_AFTER}};
 use clipman_platform::{
     state::AppState,
     config::Config,
-    handlers::{auth_routes, user_routes, device_routes, websocket_handler},
+    handlers::{auth_routes, user_routes, device_routes, clipboard_routes, blob_routes, CHUNK_CHECKSUM_HEADER, maintenance_routes, session_routes, mfa_routes, admin_routes, websocket_handler},
     utils::logger::setup_logger,
 };
-use std::sync::Arc;
-use tracing::{info, warn, error};
+use std::net::SocketAddr;
+use tokio::sync::watch;
+use tracing::{info, error};
 
 #[tokio::main]
 async fn main() {
@@ -28,10 +29,16 @@ async fn main() {
     let addr = state.config.server_addr();
     info!("Application state initialized");
 
+    // Background retention jobs run until the server shuts down
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let maintenance = state.maintenance_service.spawn(shutdown_rx);
+    info!("Maintenance tasks started");
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PUT])
-        .allow_headers([CONTENT_TYPE]);
+        .allow_headers([AUTHORIZATION, CONTENT_TYPE, RANGE, HeaderName::from_static(CHUNK_CHECKSUM_HEADER)])
+        .expose_headers([CONTENT_RANGE, ACCEPT_RANGES, RETRY_AFTER]);
     info!("CORS configuration set up");
 
     // Build our application with our routes
@@ -39,6 +46,12 @@ async fn main() {
         .merge(auth_routes())
         .merge(user_routes())
         .merge(device_routes())
+        .merge(clipboard_routes())
+        .merge(blob_routes())
+        .merge(maintenance_routes())
+        .merge(session_routes())
+        .merge(mfa_routes())
+        .merge(admin_routes())
         .route("/ws", get(websocket_handler))
         .layer(cors)
         .layer(TraceLayer::new_for_http())  // Add request tracing
@@ -50,6 +63,11 @@ async fn main() {
     info!("👤 User endpoints enabled");
     info!("🔒 Auth endpoints enabled");
     info!("📱 Device endpoints enabled");
+    info!("📋 Clipboard endpoints enabled");
+    info!("🧹 Maintenance endpoints enabled");
+    info!("🪪 Session endpoints enabled");
+    info!("🔑 Two-factor endpoints enabled");
+    info!("🛡️ Admin endpoints enabled");
     info!("🔌 WebSocket endpoint enabled");
 
     // Start the server
@@ -57,8 +75,19 @@ async fn main() {
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
     info!("🌐 Listening on http://{}", addr);
 
-    match axum::serve(listener, app).await {
+    let shutdown_signal = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for shutdown signal: {}", e);
+        }
+    };
+
+    // Peer addresses are recorded on sessions
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    match axum::serve(listener, app).with_graceful_shutdown(shutdown_signal).await {
         Ok(_) => info!("Server shutdown gracefully"),
         Err(e) => error!("Server error: {}", e),
     }
+
+    let _ = shutdown_tx.send(true);
+    let _ = maintenance.await;
 }
\ No newline at end of file
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// A payload too large for a clip, uploaded in fixed-size chunks. Chunk `i`
+/// covers bytes `i * chunk_size` up to the next chunk or the end. Clips
+/// refer to it from a `ClipFormat` once every chunk is in.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Blob {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub size: u64,
+    pub chunk_size: u64,
+    /// Indices of the chunks stored so far, ascending.
+    pub received: Vec<u32>,
+    /// Set once the last missing chunk arrives.
+    pub completed_at: Option<u64>,
+    pub created_at: u64,
+}
+
+impl Blob {
+    pub fn new(user_id: Uuid, size: u64, chunk_size: u64) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            size,
+            chunk_size,
+            received: Vec::new(),
+            completed_at: None,
+            created_at: now,
+        }
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        self.size.div_ceil(self.chunk_size) as u32
+    }
+
+    /// Byte offset and length of chunk `index`, if there is such a chunk.
+    pub fn chunk_range(&self, index: u32) -> Option<(u64, u64)> {
+        let start = index as u64 * self.chunk_size;
+        (index < self.chunk_count()).then(|| (start, self.chunk_size.min(self.size - start)))
+    }
+
+    /// Chunks still to upload; what a client resumes with.
+    pub fn missing(&self) -> Vec<u32> {
+        (0..self.chunk_count())
+            .filter(|index| self.received.binary_search(index).is_err())
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.completed_at.is_some()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlobResponse {
+    #[serde(flatten)]
+    pub blob: Blob,
+    pub missing: Vec<u32>,
+}
+
+impl From<Blob> for BlobResponse {
+    fn from(blob: Blob) -> Self {
+        Self {
+            missing: blob.missing(),
+            blob,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_layout() {
+        let mut blob = Blob::new(Uuid::new_v4(), 10, 4);
+        assert_eq!(blob.chunk_count(), 3);
+        assert_eq!(blob.chunk_range(0), Some((0, 4)));
+        assert_eq!(blob.chunk_range(2), Some((8, 2)));
+        assert_eq!(blob.chunk_range(3), None);
+
+        blob.received = vec![1];
+        assert_eq!(blob.missing(), vec![0, 2]);
+    }
+}
@@ -1,5 +1,51 @@
+use ring::digest;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use super::Subscription;
+
+/// The MIME type of `ClipboardData::content`, which every clip carries.
+pub const PLAIN_TEXT: &str = "text/plain";
+
+/// One representation of a clip besides its plain text, e.g. `text/html`,
+/// `image/png` or `text/uri-list`. `data` travels base64-encoded; payloads
+/// too large to inline are uploaded as a blob and referenced by `blob_id`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ClipFormat {
+    pub mime_type: String,
+    #[serde(default, with = "super::base64_data", skip_serializing_if = "Vec::is_empty")]
+    pub data: Vec<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_id: Option<Uuid>,
+}
+
+impl ClipFormat {
+    pub fn new(mime_type: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            mime_type: mime_type.into(),
+            data: data.into(),
+            blob_id: None,
+        }
+    }
+
+    pub fn from_blob(mime_type: impl Into<String>, blob_id: Uuid) -> Self {
+        Self {
+            mime_type: mime_type.into(),
+            data: Vec::new(),
+            blob_id: Some(blob_id),
+        }
+    }
+}
+
+/// Whether `mime_type` matches an accept pattern: exact, `image/*` or `*/*`.
+pub fn mime_matches(pattern: &str, mime_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(top_level) => mime_type
+            .split_once('/')
+            .is_some_and(|(mime_top, _)| mime_top.eq_ignore_ascii_case(top_level)),
+        None => pattern.eq_ignore_ascii_case(mime_type),
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClipboardData {
@@ -9,6 +55,19 @@ pub struct ClipboardData {
     pub user_id: Uuid,
     pub sent_at: u64,
     pub received_at: u64,
+    /// Per-user sequence number, assigned by the store on insert.
+    #[serde(default)]
+    pub seq: u64,
+    /// Devices the sender addressed the clip to; empty means all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<Uuid>,
+    /// Representations beyond the plain-text `content`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub formats: Vec<ClipFormat>,
+    /// Hex SHA-256 of the payload (see `payload_hash`), set by the store.
+    /// Clips with the same hash share one stored copy of the payload.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_hash: String,
 }
 
 impl ClipboardData {
@@ -20,6 +79,130 @@ impl ClipboardData {
             user_id,
             sent_at: 0,  // set by client
             received_at: 0,  // set by server
+            seq: 0,  // set by store
+            targets: Vec::new(),
+            formats: Vec::new(),
+            content_hash: String::new(),
         }
     }
-}
\ No newline at end of file
+
+    /// Hashes the plain text and every representation, so two clips hash
+    /// alike exactly when they carry the same payload. Representations are
+    /// taken in MIME type order; each field is length-prefixed.
+    pub fn payload_hash(&self) -> String {
+        let mut formats: Vec<&ClipFormat> = self.formats.iter().collect();
+        formats.sort_by(|a, b| a.mime_type.cmp(&b.mime_type));
+
+        let mut context = digest::Context::new(&digest::SHA256);
+        let mut update = |bytes: &[u8]| {
+            context.update(&(bytes.len() as u64).to_be_bytes());
+            context.update(bytes);
+        };
+        update(self.content.as_bytes());
+        for format in formats {
+            update(format.mime_type.as_bytes());
+            update(&format.data);
+            update(format.blob_id.as_ref().map_or(&[][..], |id| id.as_bytes()));
+        }
+        data_encoding::HEXLOWER.encode(context.finish().as_ref())
+    }
+
+    /// Bytes of inline payload across all representations; blobs do not
+    /// count.
+    pub fn size(&self) -> usize {
+        self.content.len() + self.formats.iter().map(|format| format.data.len()).sum::<usize>()
+    }
+
+    /// Drops the representations a receiver cannot use. The plain text is
+    /// always kept; an empty `accept` keeps everything.
+    pub fn retain_formats(&mut self, accept: &[String]) {
+        if !accept.is_empty() {
+            self.formats
+                .retain(|format| accept.iter().any(|pattern| mime_matches(pattern, &format.mime_type)));
+        }
+    }
+
+    /// Whether a device with `subscription` should receive this clip. A
+    /// device always sees its own clips; others need to be targeted, if the
+    /// sender targeted anyone, and to accept the sender.
+    pub fn is_visible_to(&self, device_id: Uuid, subscription: &Subscription) -> bool {
+        device_id == self.device_id
+            || ((self.targets.is_empty() || self.targets.contains(&device_id))
+                && subscription.accepts(self.device_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_targets_and_subscriptions_both_apply() {
+        let (user, laptop, phone, tablet) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut clip = ClipboardData::new("hi".to_string(), laptop, user);
+        assert!(clip.is_visible_to(phone, &Subscription::All));
+        assert!(!clip.is_visible_to(phone, &Subscription::Except(vec![laptop])));
+
+        clip.targets = vec![phone];
+        assert!(clip.is_visible_to(phone, &Subscription::All));
+        assert!(!clip.is_visible_to(tablet, &Subscription::All));
+        // The receiver's rules win over the sender's addressing
+        assert!(!clip.is_visible_to(phone, &Subscription::Only(vec![tablet])));
+        // Senders always see their own clips
+        assert!(clip.is_visible_to(laptop, &Subscription::Only(vec![tablet])));
+    }
+
+    #[test]
+    fn test_formats_round_trip_as_base64() {
+        let mut clip = ClipboardData::new("hi".to_string(), Uuid::new_v4(), Uuid::new_v4());
+        clip.formats.push(ClipFormat::new("image/png", vec![0x89, b'P', b'N', b'G']));
+        let json = serde_json::to_value(&clip).unwrap();
+        assert_eq!(json["formats"][0], serde_json::json!({"mime_type": "image/png", "data": "iVBORw=="}));
+        let parsed: ClipboardData = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.formats, clip.formats);
+        assert_eq!(parsed.size(), 6);
+
+        // Clips stored before formats existed are plain text
+        let plain = serde_json::to_value(ClipboardData::new("x".to_string(), Uuid::new_v4(), Uuid::new_v4())).unwrap();
+        assert!(plain.get("formats").is_none());
+    }
+
+    #[test]
+    fn test_retain_accepted_formats() {
+        let mut clip = ClipboardData::new("hi".to_string(), Uuid::new_v4(), Uuid::new_v4());
+        clip.formats = vec![
+            ClipFormat::new("text/html", "<b>hi</b>"),
+            ClipFormat::new("image/png", vec![1]),
+            ClipFormat::new("text/uri-list", "file:///hi"),
+        ];
+
+        let mut all = clip.clone();
+        all.retain_formats(&[]);
+        assert_eq!(all.formats.len(), 3);
+
+        clip.retain_formats(&["IMAGE/*".to_string(), "text/uri-list".to_string()]);
+        let kept: Vec<&str> = clip.formats.iter().map(|format| format.mime_type.as_str()).collect();
+        assert_eq!(kept, ["image/png", "text/uri-list"]);
+        assert_eq!(clip.content, "hi");
+
+        assert!(mime_matches("*/*", "application/pdf"));
+        assert!(!mime_matches("image/*", "text/html"));
+    }
+
+    #[test]
+    fn test_payload_hash_ignores_metadata_and_format_order() {
+        let user = Uuid::new_v4();
+        let mut first = ClipboardData::new("https://example.com".to_string(), Uuid::new_v4(), user);
+        first.formats = vec![ClipFormat::new("text/html", "<a>"), ClipFormat::new("text/uri-list", "x")];
+        let mut second = ClipboardData::new("https://example.com".to_string(), Uuid::new_v4(), Uuid::new_v4());
+        second.formats = vec![ClipFormat::new("text/uri-list", "x"), ClipFormat::new("text/html", "<a>")];
+        second.sent_at = 42;
+        assert_eq!(first.payload_hash(), second.payload_hash());
+
+        // Moving bytes between fields changes the hash
+        second.formats[0] = ClipFormat::new("text/uri-lis", "tx");
+        assert_ne!(first.payload_hash(), second.payload_hash());
+        second.formats[0] = ClipFormat::from_blob("text/uri-list", Uuid::new_v4());
+        assert_ne!(first.payload_hash(), second.payload_hash());
+    }
+}
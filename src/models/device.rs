@@ -8,6 +8,38 @@ pub struct Device {
     pub user_id: Uuid,
     pub last_seen: u64,
     pub created_at: u64,
+    #[serde(default)]
+    pub subscription: Subscription,
+}
+
+/// Which of the owner's other devices a device receives clips from:
+/// `{"mode": "all"}`, `{"mode": "only", "devices": [...]}` or
+/// `{"mode": "except", "devices": [...]}`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(tag = "mode", content = "devices", rename_all = "snake_case")]
+pub enum Subscription {
+    #[default]
+    All,
+    Only(Vec<Uuid>),
+    Except(Vec<Uuid>),
+}
+
+impl Subscription {
+    pub fn accepts(&self, source_device: Uuid) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(devices) => devices.contains(&source_device),
+            Self::Except(devices) => !devices.contains(&source_device),
+        }
+    }
+
+    /// The device ids the rule names.
+    pub fn devices(&self) -> &[Uuid] {
+        match self {
+            Self::All => &[],
+            Self::Only(devices) | Self::Except(devices) => devices,
+        }
+    }
 }
 
 impl Device {
@@ -23,13 +55,43 @@ impl Device {
             user_id,
             last_seen: now,
             created_at: now,
+            subscription: Subscription::All,
         }
     }
+}
+
+/// A device as listed to its owner, with whether it has a live socket.
+#[derive(Debug, Serialize, Clone)]
+pub struct DeviceResponse {
+    #[serde(flatten)]
+    pub device: Device,
+    pub online: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_rules() {
+        let (laptop, phone) = (Uuid::new_v4(), Uuid::new_v4());
+        assert!(Subscription::All.accepts(laptop));
+        assert!(Subscription::Only(vec![laptop]).accepts(laptop));
+        assert!(!Subscription::Only(vec![laptop]).accepts(phone));
+        assert!(!Subscription::Except(vec![laptop]).accepts(laptop));
+        assert!(Subscription::Except(vec![laptop]).accepts(phone));
+    }
 
-    pub fn update_last_seen(&mut self) {
-        self.last_seen = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    #[test]
+    fn test_subscription_shape() {
+        let laptop = Uuid::new_v4();
+        let json = serde_json::to_value(Subscription::Only(vec![laptop])).unwrap();
+        assert_eq!(json, serde_json::json!({"mode": "only", "devices": [laptop]}));
+        assert_eq!(serde_json::to_value(Subscription::All).unwrap(), serde_json::json!({"mode": "all"}));
+
+        // Devices stored before subscriptions existed receive everything
+        let raw = format!(r#"{{"id":"{}","name":"old","user_id":"{}","last_seen":1,"created_at":1}}"#, laptop, laptop);
+        let device: Device = serde_json::from_str(&raw).unwrap();
+        assert_eq!(device.subscription, Subscription::All);
     }
-}
\ No newline at end of file
+}
@@ -1,8 +1,14 @@
 mod user;
 mod device;
 mod clipboard;
+mod blob;
+mod token_family;
+pub mod protocol;
+pub(crate) mod base64_data;
 
-pub use user::User;
-pub use device::Device;
-pub use clipboard::ClipboardData;
+pub use user::{User, MfaSettings};
+pub use device::{Device, DeviceResponse, Subscription};
+pub use clipboard::{ClipFormat, ClipboardData, PLAIN_TEXT};
+pub use blob::{Blob, BlobResponse};
+pub use token_family::{TokenFamily, SessionResponse};
 pub use user::UserResponse;
\ No newline at end of file
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use super::{ClipFormat, ClipboardData, Subscription};
+
+/// Bumped whenever a message changes shape incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Every WebSocket frame, in either direction, is a JSON envelope:
+/// `{"v": 1, "id": "...", "type": "...", "data": {...}}`.
+///
+/// `id` is chosen by the client; replies to a client message carry the same
+/// `id` so requests can be matched to responses. Server pushes have no `id`.
+///
+/// Clips carry a per-user `seq`. Clients `ack` the highest `seq` they have
+/// applied and, after a reconnect or a gap in the numbers, send `resume` to
+/// have everything after that point replayed from storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub v: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub message: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(message: T) -> Self {
+        Self { v: PROTOCOL_VERSION, id: None, message }
+    }
+
+    pub fn reply_to(id: Option<String>, message: T) -> Self {
+        Self { v: PROTOCOL_VERSION, id, message }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// First message on a connection; answered with `welcome`.
+    Hello {
+        #[serde(default)]
+        client: Option<String>,
+        /// MIME types or patterns (`image/*`) the device can paste. Clips
+        /// reach it without other formats; empty means all formats.
+        #[serde(default)]
+        accept: Vec<String>,
+    },
+    ClipPush {
+        #[serde(default)]
+        content: String,
+        /// Representations besides the plain text.
+        #[serde(default)]
+        formats: Vec<ClipFormat>,
+        #[serde(default)]
+        sent_at: u64,
+        /// Devices to deliver to; empty or omitted means all of them.
+        #[serde(default)]
+        targets: Vec<Uuid>,
+    },
+    HistoryRequest {
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+    /// Marks every clip up to `seq` as applied on this device.
+    Ack {
+        seq: u64,
+    },
+    /// Asks for clips after `after_seq`, or after this device's last ack
+    /// when omitted; answered with `replay`.
+    Resume {
+        #[serde(default)]
+        after_seq: Option<u64>,
+    },
+    /// Replaces which devices this device receives clips from, e.g.
+    /// `{"mode": "only", "devices": [...]}`; answered with `subscribed`.
+    Subscribe(Subscription),
+    Ping,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Welcome {
+        user_id: Uuid,
+        device_id: Option<Uuid>,
+        version: u32,
+        /// Highest `seq` currently stored for the user.
+        last_seq: u64,
+        /// Last `seq` this device acknowledged, if the server remembers one.
+        acked_seq: Option<u64>,
+    },
+    Clip(ClipboardData),
+    ClipAck {
+        clip_id: Uuid,
+        received_at: u64,
+        seq: u64,
+    },
+    History {
+        clips: Vec<ClipboardData>,
+    },
+    /// Missed clips, oldest first. Resume again from `up_to` while `more` is set.
+    Replay {
+        clips: Vec<ClipboardData>,
+        up_to: u64,
+        more: bool,
+    },
+    /// The subscription now in effect for this device.
+    Subscribed(Subscription),
+    Pong,
+    /// Another of the user's devices came online or went offline. Pushed
+    /// when its first socket connects and when its last one closes.
+    Presence {
+        device_id: Uuid,
+        online: bool,
+        last_seen: u64,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    MalformedFrame,
+    UnsupportedVersion,
+    UnsupportedFrame,
+    DeviceRequired,
+    InvalidClip,
+    InvalidSubscription,
+    Internal,
+}
+
+impl ServerMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Error { code, message: message.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_envelope_round_trip() {
+        let raw = r#"{"v":1,"id":"42","type":"clip_push","data":{"content":"hi","sent_at":7}}"#;
+        let envelope: Envelope<ClientMessage> = serde_json::from_str(raw).unwrap();
+        assert_eq!(envelope.v, 1);
+        assert_eq!(envelope.id.as_deref(), Some("42"));
+        assert!(matches!(
+            envelope.message,
+            ClientMessage::ClipPush { ref content, sent_at: 7, ref targets, .. } if content == "hi" && targets.is_empty()
+        ));
+
+        let raw = r#"{"v":1,"type":"subscribe","data":{"mode":"except","devices":["00000000-0000-0000-0000-000000000001"]}}"#;
+        let subscribe: Envelope<ClientMessage> = serde_json::from_str(raw).unwrap();
+        assert!(matches!(subscribe.message, ClientMessage::Subscribe(Subscription::Except(ref devices)) if devices.len() == 1));
+
+        let ping: Envelope<ClientMessage> = serde_json::from_str(r#"{"v":1,"type":"ping"}"#).unwrap();
+        assert!(matches!(ping.message, ClientMessage::Ping));
+
+        let resume: Envelope<ClientMessage> = serde_json::from_str(r#"{"v":1,"type":"resume","data":{}}"#).unwrap();
+        assert!(matches!(resume.message, ClientMessage::Resume { after_seq: None }));
+        let ack: Envelope<ClientMessage> = serde_json::from_str(r#"{"v":1,"type":"ack","data":{"seq":3}}"#).unwrap();
+        assert!(matches!(ack.message, ClientMessage::Ack { seq: 3 }));
+    }
+
+    #[test]
+    fn test_server_error_shape() {
+        let envelope = Envelope::reply_to(
+            Some("7".to_string()),
+            ServerMessage::error(ErrorCode::MalformedFrame, "bad json"),
+        );
+        let json: serde_json::Value = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["v"], PROTOCOL_VERSION);
+        assert_eq!(json["id"], "7");
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["data"]["code"], "malformed_frame");
+    }
+
+    #[test]
+    fn test_presence_shape() {
+        let device_id = Uuid::new_v4();
+        let envelope = Envelope::new(ServerMessage::Presence { device_id, online: true, last_seen: 5 });
+        let json: serde_json::Value = serde_json::to_value(&envelope).unwrap();
+        assert_eq!(json["type"], "presence");
+        assert_eq!(json["data"]["device_id"], device_id.to_string());
+        assert_eq!(json["data"]["online"], true);
+        assert!(json.get("id").is_none());
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// The chain of refresh tokens descended from one login. Only the newest
+/// token in the chain (`current_jti`) may be redeemed; presenting an older
+/// one means it was copied, and the whole family is revoked.
+///
+/// A family is also what users see as a session: its `id` is the session id
+/// carried in every token's claims.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenFamily {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: Option<Uuid>,
+    pub current_jti: Uuid,
+    pub created_at: u64,
+    /// Expiry of the current refresh token; the family is useless after it.
+    pub expires_at: u64,
+    pub revoked: bool,
+    #[serde(default)]
+    pub ip_address: Option<String>,
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    #[serde(default)]
+    pub last_used_at: u64,
+}
+
+impl TokenFamily {
+    pub fn new(user_id: Uuid, device_id: Option<Uuid>, current_jti: Uuid, expires_at: u64) -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        Self {
+            id: Uuid::new_v4(),
+            user_id,
+            device_id,
+            current_jti,
+            created_at: now,
+            expires_at,
+            revoked: false,
+            ip_address: None,
+            user_agent: None,
+            last_used_at: now,
+        }
+    }
+}
+
+// Response DTO for API; never exposes the refresh token id
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub id: Uuid,
+    pub device_id: Option<Uuid>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: u64,
+    pub last_used_at: u64,
+    pub expires_at: u64,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+impl SessionResponse {
+    pub fn new(family: TokenFamily, current_session: Option<Uuid>) -> Self {
+        Self {
+            current: current_session == Some(family.id),
+            id: family.id,
+            device_id: family.device_id,
+            ip_address: family.ip_address,
+            user_agent: family.user_agent,
+            created_at: family.created_at,
+            last_used_at: family.last_used_at,
+            expires_at: family.expires_at,
+        }
+    }
+}
@@ -8,20 +8,43 @@ pub struct User {
     pub username: String,
     #[serde(skip_serializing)]
     pub password_hash: String,
+    /// Two-factor settings; `None` until the user starts TOTP enrollment.
+    #[serde(skip_serializing, default)]
+    pub mfa: Option<MfaSettings>,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
 
+/// TOTP state for one user. Stored as-is by every backend.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MfaSettings {
+    /// Base32 shared secret, as shown in the provisioning URI.
+    pub totp_secret: String,
+    /// Set once the user proved their authenticator works; until then login
+    /// stays single-step.
+    pub enabled: bool,
+    /// SHA-256 hex digests of the unused recovery codes.
+    pub recovery_code_hashes: Vec<String>,
+    /// Time step of the last accepted code, so a code cannot be replayed.
+    #[serde(default)]
+    pub last_used_step: u64,
+}
+
 impl User {
     pub fn new(username: String, password_hash: String) -> Self {
         Self {
             id: Uuid::new_v4(),
             username,
             password_hash,
+            mfa: None,
             created_at: Some(Utc::now()),
             updated_at: Some(Utc::now()),
         }
     }
+
+    pub fn mfa_enabled(&self) -> bool {
+        self.mfa.as_ref().is_some_and(|mfa| mfa.enabled)
+    }
 }
 
 // Response DTO for API
@@ -29,6 +52,7 @@ impl User {
 pub struct UserResponse {
     pub id: Uuid,
     pub username: String,
+    pub mfa_enabled: bool,
     pub created_at: Option<DateTime<Utc>>,
     pub updated_at: Option<DateTime<Utc>>,
 }
@@ -36,6 +60,7 @@ pub struct UserResponse {
 impl From<User> for UserResponse {
     fn from(user: User) -> Self {
         Self {
+            mfa_enabled: user.mfa_enabled(),
             id: user.id,
             username: user.username,
             created_at: user.created_at,
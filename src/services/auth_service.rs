@@ -1,12 +1,13 @@
 use crate::error::{AppError, AppResult};
-use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation};
+use jsonwebtoken::{encode, decode, decode_header, jwk::JwkSet, Header, Validation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::Mutex;
 use std::time::{SystemTime, UNIX_EPOCH};
 use crate::config::Config;
+use crate::models::TokenFamily;
+use crate::store::{Store, TokenStore};
+use super::keyring::Keyring;
 
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -14,85 +15,207 @@ pub struct Claims {
     pub sub: Uuid,
     pub exp: usize,
     pub token_type: TokenType,
+    /// Device the token was issued to, if the client identified one at login.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<Uuid>,
+    /// Refresh token family this token was issued under, which is also the
+    /// id of the session listed under `/sessions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_id: Option<Uuid>,
+    /// Unique id of a refresh or MFA token. Refresh token ids are checked
+    /// against the family on use.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jti: Option<Uuid>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TokenType {
     Access,
     Refresh,
+    /// Proves the password step of a two-factor login; only accepted by `/login/mfa`.
+    MfaPending,
 }
 
-pub struct AuthService {
+/// Seconds between `last_used_at` updates of a session.
+const LAST_USED_RESOLUTION: u64 = 60;
+
+pub struct AuthService<S: ?Sized = dyn Store> {
     config: Arc<Config>,
-    encoding_key: EncodingKey,
-    decoding_key: DecodingKey,
-    blacklist: Arc<Mutex<HashSet<String>>>,
+    keyring: Keyring,
+    store: Arc<S>,
 }
 
 
-impl AuthService {
-    pub fn new(config: Arc<Config>) -> Self {
+impl<S: TokenStore + ?Sized> AuthService<S> {
+    /// Panics if the configured signing key cannot be loaded.
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
+        let keyring = Keyring::from_config(&config.auth).expect("Failed to load JWT signing key");
         Self {
-            encoding_key: EncodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
-            decoding_key: DecodingKey::from_secret(config.auth.jwt_secret.as_bytes()),
-            blacklist: Arc::new(Mutex::new(HashSet::new())),
+            keyring,
+            store,
             config,
         }
     }
 
-    pub fn create_token_pair(&self, user_id: Uuid) -> AppResult<(String, String)> {
-        let access_token = self.create_access_token(user_id)?;
-        let refresh_token = self.create_refresh_token(user_id)?;
+    /// Issues a token pair that starts a new refresh token family.
+    pub async fn create_token_pair(&self, user_id: Uuid, device_id: Option<Uuid>) -> AppResult<(String, String)> {
+        self.create_session(user_id, device_id, None, None).await
+    }
+
+    /// Like [`create_token_pair`](Self::create_token_pair), recording where
+    /// the login came from so the user can recognise the session later.
+    pub async fn create_session(
+        &self,
+        user_id: Uuid,
+        device_id: Option<Uuid>,
+        ip_address: Option<String>,
+        user_agent: Option<String>,
+    ) -> AppResult<(String, String)> {
+        let jti = Uuid::new_v4();
+        let expires_at = self.refresh_expiry();
+        let mut family = TokenFamily::new(user_id, device_id, jti, expires_at);
+        family.ip_address = ip_address;
+        family.user_agent = user_agent;
+        let family = self.store.insert_token_family(family).await?;
+
+        let access_token = self.create_access_token(user_id, device_id, family.id)?;
+        let refresh_token = self.create_refresh_token(user_id, device_id, family.id, jti, expires_at)?;
         Ok((access_token, refresh_token))
     }
 
-    fn create_access_token(&self, user_id: Uuid) -> AppResult<String> {
-        let exp = jsonwebtoken::get_current_timestamp() as usize + 3600; // 1 hour
+    fn refresh_expiry(&self) -> u64 {
+        jsonwebtoken::get_current_timestamp() + self.config.auth.refresh_token_expiry
+    }
+
+    fn create_access_token(&self, user_id: Uuid, device_id: Option<Uuid>, family_id: Uuid) -> AppResult<String> {
+        let exp = jsonwebtoken::get_current_timestamp() + self.config.auth.access_token_expiry;
         let claims = Claims {
             sub: user_id,
-            exp,
+            exp: exp as usize,
             token_type: TokenType::Access,
+            device_id,
+            family_id: Some(family_id),
+            jti: None,
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| AppError::InternalError(format!("Token creation failed: {}", e)))
+        self.sign(&claims, "Token creation failed")
     }
 
-    fn create_refresh_token(&self, user_id: Uuid) -> AppResult<String> {
-        let exp = jsonwebtoken::get_current_timestamp() as usize + 7 * 24 * 3600; // 7 days
+    fn create_refresh_token(&self, user_id: Uuid, device_id: Option<Uuid>, family_id: Uuid, jti: Uuid, exp: u64) -> AppResult<String> {
         let claims = Claims {
             sub: user_id,
-            exp,
+            exp: exp as usize,
             token_type: TokenType::Refresh,
+            device_id,
+            family_id: Some(family_id),
+            jti: Some(jti),
+        };
+
+        self.sign(&claims, "Refresh token creation failed")
+    }
+
+    /// Issues the short-lived token a two-factor login trades, together with
+    /// a code, for a real token pair.
+    pub fn create_mfa_token(&self, user_id: Uuid, device_id: Option<Uuid>) -> AppResult<String> {
+        let exp = jsonwebtoken::get_current_timestamp() + self.config.auth.mfa_token_expiry;
+        let claims = Claims {
+            sub: user_id,
+            exp: exp as usize,
+            token_type: TokenType::MfaPending,
+            device_id,
+            family_id: None,
+            jti: Some(Uuid::new_v4()),
         };
 
-        encode(&Header::default(), &claims, &self.encoding_key)
-            .map_err(|e| AppError::InternalError(format!("Refresh token creation failed: {}", e)))
+        self.sign(&claims, "MFA token creation failed")
     }
 
-    pub async fn refresh_token(&self, refresh_token: &str) -> AppResult<String> {
+    /// Callers spend the token with `consume_mfa_token` once the code is accepted.
+    pub async fn verify_mfa_token(&self, token: &str) -> AppResult<Claims> {
+        let claims = self.verify_token(token).await?;
+        if claims.token_type != TokenType::MfaPending || claims.jti.is_none() {
+            return Err(AppError::InvalidToken);
+        }
+        Ok(claims)
+    }
+
+    /// Signs with the active key and names it in the header's `kid`.
+    fn sign(&self, claims: &Claims, context: &str) -> AppResult<String> {
+        let (kid, key) = self.keyring.active();
+        let header = Header {
+            kid: Some(kid.to_string()),
+            ..Header::new(key.algorithm)
+        };
+        let encoding_key = key
+            .encoding_key
+            .as_ref()
+            .ok_or_else(|| AppError::InternalError(format!("{}: key {} cannot sign", context, kid)))?;
+        encode(&header, claims, encoding_key)
+            .map_err(|e| AppError::InternalError(format!("{}: {}", context, e)))
+    }
+
+    /// Checks the signature against the key named by the token's `kid`.
+    fn decode_claims(&self, token: &str, validate_exp: bool) -> AppResult<Claims> {
+        let header = decode_header(token).map_err(|_| AppError::InvalidToken)?;
+        let key = self.keyring.verifying_key(header.kid.as_deref(), jsonwebtoken::get_current_timestamp())?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.validate_exp = validate_exp;
+        decode::<Claims>(token, &key.decoding_key, &validation)
+            .map(|token_data| token_data.claims)
+            .map_err(|_| AppError::InvalidToken)
+    }
+
+    /// Public keys other services can use to verify our tokens. Empty when
+    /// only HMAC secrets are in use.
+    pub fn jwks(&self) -> JwkSet {
+        self.keyring.jwks(jsonwebtoken::get_current_timestamp())
+    }
+
+    /// Key ids currently accepted for verification, sorted.
+    pub fn key_ids(&self) -> Vec<String> {
+        self.keyring.key_ids()
+    }
+
+    /// Exchanges a refresh token for a new pair. The presented token is spent:
+    /// using it again revokes its whole family, which cuts off whoever copied
+    /// it as well as the legitimate holder.
+    pub async fn refresh_token(&self, refresh_token: &str) -> AppResult<(String, String)> {
         let claims = self.verify_token(refresh_token).await?;
         
         if claims.token_type != TokenType::Refresh {
             return Err(AppError::InvalidToken);
         }
-        
-        self.create_access_token(claims.sub)
+        // Tokens issued before families existed cannot be rotated; log in again
+        let (Some(family_id), Some(jti)) = (claims.family_id, claims.jti) else {
+            return Err(AppError::InvalidToken);
+        };
+
+        let new_jti = Uuid::new_v4();
+        let expires_at = self.refresh_expiry();
+        if !self.store.rotate_token_family(family_id, jti, new_jti, expires_at).await? {
+            tracing::warn!(
+                target: "security",
+                "Refresh token reuse detected for user {}, revoking token family {}",
+                claims.sub,
+                family_id
+            );
+            self.store.revoke_token_family(family_id).await?;
+            return Err(AppError::Unauthorized("Refresh token has already been used".to_string()));
+        }
+
+        let access_token = self.create_access_token(claims.sub, claims.device_id, family_id)?;
+        let refresh_token = self.create_refresh_token(claims.sub, claims.device_id, family_id, new_jti, expires_at)?;
+        Ok((access_token, refresh_token))
     }
 
     pub async fn verify_token(&self, token: &str) -> AppResult<Claims> {
         // Check blacklist first
-        if self.blacklist.lock().await.contains(token) {
+        if self.store.is_token_revoked(token).await? {
             return Err(AppError::InvalidToken);
         }
 
-        let claims = decode::<Claims>(
-            token,
-            &self.decoding_key,
-            &Validation::default()
-        )
-        .map(|token_data| token_data.claims)
-        .map_err(|_| AppError::InvalidToken)?;
+        let claims = self.decode_claims(token, true)?;
 
         // Check if token is expired
         let now = SystemTime::now()
@@ -104,19 +227,496 @@ impl AuthService {
             return Err(AppError::TokenExpired);
         }
 
+        // A revoked family takes every access and refresh token with it
+        if let Some(family_id) = claims.family_id {
+            match self.store.get_token_family(family_id).await? {
+                Some(family) if !family.revoked => {
+                    // Coarse last-used times keep this off the write path of most requests
+                    let now = now as u64;
+                    if now >= family.last_used_at + LAST_USED_RESOLUTION {
+                        self.store.touch_token_family(family_id, now).await?;
+                    }
+                }
+                _ => return Err(AppError::InvalidToken),
+            }
+        }
+
         Ok(claims)
     }
 
+    /// A user's sessions that can still be refreshed, oldest first.
+    pub async fn list_sessions(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let mut sessions = self.store.list_user_token_families(user_id).await?;
+        sessions.retain(|family| !family.revoked && family.expires_at > now);
+        Ok(sessions)
+    }
+
+    /// Revokes one of the user's sessions, invalidating all of its tokens.
+    /// Sessions of other users are reported as missing.
+    pub async fn revoke_session(&self, user_id: Uuid, session_id: Uuid) -> AppResult<()> {
+        match self.store.get_token_family(session_id).await? {
+            Some(family) if family.user_id == user_id && !family.revoked => {
+                self.store.revoke_token_family(session_id).await?;
+                Ok(())
+            }
+            _ => Err(AppError::SessionNotFound(session_id)),
+        }
+    }
+
+    /// Revokes all of the user's sessions except `keep`, e.g. the one that
+    /// just changed the password. Returns the revoked session ids.
+    pub async fn revoke_other_sessions(&self, user_id: Uuid, keep: Option<Uuid>) -> AppResult<Vec<Uuid>> {
+        let mut revoked = Vec::new();
+        for family in self.store.list_user_token_families(user_id).await? {
+            if !family.revoked && Some(family.id) != keep {
+                self.store.revoke_token_family(family.id).await?;
+                revoked.push(family.id);
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// Revokes the sessions bound to one of the user's devices. Returns the
+    /// revoked session ids.
+    pub async fn revoke_device_sessions(&self, user_id: Uuid, device_id: Uuid) -> AppResult<Vec<Uuid>> {
+        let mut revoked = Vec::new();
+        for family in self.store.list_user_token_families(user_id).await? {
+            if !family.revoked && family.device_id == Some(device_id) {
+                self.store.revoke_token_family(family.id).await?;
+                revoked.push(family.id);
+            }
+        }
+        Ok(revoked)
+    }
+
     pub async fn invalidate_token(&self, token: &str) -> AppResult<()> {
-        let mut blacklist = self.blacklist.lock().await;
-        blacklist.insert(token.to_string());
+        let expires_at = self.token_expiry(token);
+        self.store.revoke_token(token, expires_at).await?;
         Ok(())
     }
 
+    /// Spends an MFA token. Of several requests racing with the same token
+    /// only the first succeeds; the rest get `InvalidToken`.
+    pub async fn consume_mfa_token(&self, token: &str) -> AppResult<()> {
+        let expires_at = self.token_expiry(token);
+        match self.store.revoke_token(token, expires_at).await? {
+            true => Ok(()),
+            false => Err(AppError::InvalidToken),
+        }
+    }
+
     pub async fn logout(&self, access_token: &str, refresh_token: &str) -> AppResult<()> {
-        let mut blacklist = self.blacklist.lock().await;
-        blacklist.insert(access_token.to_string());
-        blacklist.insert(refresh_token.to_string());
+        self.invalidate_token(access_token).await?;
+        self.invalidate_token(refresh_token).await?;
+
+        if let Ok(Claims { family_id: Some(family_id), .. }) = self.decode_claims(refresh_token, false) {
+            self.store.revoke_token_family(family_id).await?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Drops blacklist entries and token families that have expired on their
+    /// own. Returns the total number removed.
+    pub async fn prune_revoked_tokens(&self) -> AppResult<usize> {
+        let now = jsonwebtoken::get_current_timestamp();
+        let tokens = self.store.prune_revoked_tokens(now).await?;
+        let families = self.store.prune_token_families(now).await?;
+        Ok(tokens + families)
+    }
+
+    // Blacklist entries only need to outlive the token itself. Tokens we
+    // cannot decode are kept for the longest lifetime we ever issue.
+    fn token_expiry(&self, token: &str) -> u64 {
+        self.decode_claims(token, false)
+            .map(|claims| claims.exp as u64)
+            .unwrap_or_else(|_| jsonwebtoken::get_current_timestamp() + self.config.auth.refresh_token_expiry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::JwtAlgorithm;
+    use crate::store::MemoryStore;
+
+    fn create_test_service() -> AuthService<MemoryStore> {
+        AuthService::new(Arc::new(Config::default()), Arc::new(MemoryStore::new()))
+    }
+
+    fn service_with_config(config: Config, store: &Arc<MemoryStore>) -> AuthService<MemoryStore> {
+        AuthService::new(Arc::new(config), store.clone())
+    }
+
+    #[tokio::test]
+    async fn test_refresh_keeps_device_binding() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let device_id = Uuid::new_v4();
+
+        let (access_token, refresh_token) = service.create_token_pair(user_id, Some(device_id)).await.unwrap();
+        let claims = service.verify_token(&access_token).await.unwrap();
+        assert_eq!(claims.sub, user_id);
+        assert_eq!(claims.device_id, Some(device_id));
+        assert_eq!(claims.token_type, TokenType::Access);
+
+        let (refreshed, _) = service.refresh_token(&refresh_token).await.unwrap();
+        let claims = service.verify_token(&refreshed).await.unwrap();
+        assert_eq!(claims.device_id, Some(device_id));
+    }
+
+    #[tokio::test]
+    async fn test_logout_revokes_both_tokens() {
+        let service = create_test_service();
+        let (access_token, refresh_token) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        service.logout(&access_token, &refresh_token).await.unwrap();
+
+        assert!(service.verify_token(&access_token).await.is_err());
+        assert!(service.verify_token(&refresh_token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expiry_comes_from_config() {
+        let mut config = Config::default();
+        config.auth.access_token_expiry = 120;
+        config.auth.refresh_token_expiry = 600;
+        let service = service_with_config(config, &Arc::new(MemoryStore::new()));
+
+        let now = jsonwebtoken::get_current_timestamp() as usize;
+        let (access_token, refresh_token) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+        let access = service.verify_token(&access_token).await.unwrap();
+        let refresh = service.verify_token(&refresh_token).await.unwrap();
+        assert!((now + 120..=now + 121).contains(&access.exp));
+        assert!((now + 600..=now + 601).contains(&refresh.exp));
+    }
+
+    #[tokio::test]
+    async fn test_rotation_keeps_old_tokens_valid() {
+        let store = Arc::new(MemoryStore::new());
+        let user_id = Uuid::new_v4();
+        let (old_token, _) = service_with_config(Config::default(), &store)
+            .create_token_pair(user_id, None)
+            .await
+            .unwrap();
+        assert_eq!(decode_header(&old_token).unwrap().kid.as_deref(), Some("default"));
+
+        // Rotating is a restart with a new active key and the old one listed as previous
+        let mut config = Config::default();
+        config.auth.jwt_key_id = "2026-10".to_string();
+        config.auth.jwt_secret = "fresh-secret".to_string();
+        config.auth.previous_keys = vec!["default=your-secret-key".parse().unwrap()];
+        let service = service_with_config(config.clone(), &store);
+        let (new_token, _) = service.create_token_pair(user_id, None).await.unwrap();
+        assert_eq!(decode_header(&new_token).unwrap().kid.as_deref(), Some("2026-10"));
+
+        assert_eq!(service.verify_token(&old_token).await.unwrap().sub, user_id);
+        assert_eq!(service.verify_token(&new_token).await.unwrap().sub, user_id);
+        assert_eq!(service.key_ids(), vec!["2026-10", "default"]);
+
+        config.auth.previous_keys = vec!["2026-10=reused".parse().unwrap()];
+        assert!(Keyring::from_config(&config.auth).is_err(), "key ids must stay unique");
+        config.auth.previous_keys = vec!["old=a".parse().unwrap(), "old=b".parse().unwrap()];
+        assert!(Keyring::from_config(&config.auth).is_err(), "key ids must stay unique");
+    }
+
+    #[tokio::test]
+    async fn test_previous_and_retired_keys() {
+        let mut old_config = Config::default();
+        old_config.auth.jwt_key_id = "old".to_string();
+        old_config.auth.jwt_secret = "old-secret".to_string();
+        // Families live in the store, so every service must share it
+        let store = Arc::new(MemoryStore::new());
+        let (old_token, _) = service_with_config(old_config, &store)
+            .create_token_pair(Uuid::new_v4(), None)
+            .await
+            .unwrap();
+
+        let mut config = Config::default();
+        config.auth.jwt_key_id = "new".to_string();
+        config.auth.jwt_secret = "new-secret".to_string();
+        config.auth.previous_keys = vec!["old=old-secret".parse().unwrap()];
+        assert!(service_with_config(config.clone(), &store).verify_token(&old_token).await.is_ok());
+
+        config.auth.previous_keys = vec!["old@1=old-secret".parse().unwrap()];
+        let retired = service_with_config(config.clone(), &store).verify_token(&old_token).await;
+        assert!(matches!(retired, Err(AppError::InvalidToken)));
+
+        // A kid the keyring has never heard of is rejected outright
+        config.auth.previous_keys.clear();
+        let unknown = service_with_config(config, &store).verify_token(&old_token).await;
+        assert!(matches!(unknown, Err(AppError::InvalidToken)));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_rotates_the_refresh_token() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (_, first) = service.create_token_pair(user_id, None).await.unwrap();
+
+        let (access, second) = service.refresh_token(&first).await.unwrap();
+        assert_ne!(first, second);
+        assert_eq!(service.verify_token(&access).await.unwrap().sub, user_id);
+
+        let first_claims = service.verify_token(&first).await.unwrap();
+        let second_claims = service.verify_token(&second).await.unwrap();
+        assert_eq!(first_claims.family_id, second_claims.family_id);
+        assert_ne!(first_claims.jti, second_claims.jti);
+
+        // The rotated token keeps working for the next refresh
+        assert!(service.refresh_token(&second).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reused_refresh_token_revokes_family() {
+        let service = create_test_service();
+        let (_, stolen) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+        let (_, other_session) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        // The legitimate client refreshes first...
+        let (access, current) = service.refresh_token(&stolen).await.unwrap();
+        // ...then the attacker replays the copy they took
+        let replay = service.refresh_token(&stolen).await;
+        assert!(matches!(replay, Err(AppError::Unauthorized(_))));
+
+        // Every token in the family is now dead, including the legitimate ones
+        assert!(matches!(service.refresh_token(&current).await, Err(AppError::InvalidToken)));
+        assert!(matches!(service.verify_token(&access).await, Err(AppError::InvalidToken)));
+
+        // Other families are untouched
+        assert!(service.refresh_token(&other_session).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_cannot_both_win() {
+        let service = Arc::new(create_test_service());
+        let (_, refresh_token) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        let racers: Vec<_> = (0..2)
+            .map(|_| {
+                let service = service.clone();
+                let refresh_token = refresh_token.clone();
+                tokio::spawn(async move { service.refresh_token(&refresh_token).await })
+            })
+            .collect();
+        let mut succeeded = 0;
+        for racer in racers {
+            if racer.await.unwrap().is_ok() {
+                succeeded += 1;
+            }
+        }
+        assert!(succeeded <= 1);
+    }
+
+    #[tokio::test]
+    async fn test_logout_revokes_family() {
+        let service = create_test_service();
+        let (access_token, refresh_token) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+        let (rotated_access, _) = service.refresh_token(&refresh_token).await.unwrap();
+
+        service.logout(&access_token, &refresh_token).await.unwrap();
+        assert!(service.verify_token(&rotated_access).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_list_and_revoke_sessions() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (phone_access, _) = service
+            .create_session(user_id, None, Some("203.0.113.7".to_string()), Some("clipman-ios/2.1".to_string()))
+            .await
+            .unwrap();
+        let (laptop_access, _) = service.create_token_pair(user_id, None).await.unwrap();
+        service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+
+        let sessions = service.list_sessions(user_id).await.unwrap();
+        assert_eq!(sessions.len(), 2);
+        let phone = service.verify_token(&phone_access).await.unwrap().family_id.unwrap();
+        let phone_session = sessions.iter().find(|s| s.id == phone).unwrap();
+        assert_eq!(phone_session.ip_address.as_deref(), Some("203.0.113.7"));
+        assert_eq!(phone_session.user_agent.as_deref(), Some("clipman-ios/2.1"));
+
+        // Other users cannot see or revoke the session
+        let stranger = service.revoke_session(Uuid::new_v4(), phone).await;
+        assert!(matches!(stranger, Err(AppError::SessionNotFound(_))));
+
+        service.revoke_session(user_id, phone).await.unwrap();
+        assert!(service.verify_token(&phone_access).await.is_err());
+        assert!(service.verify_token(&laptop_access).await.is_ok());
+        assert_eq!(service.list_sessions(user_id).await.unwrap().len(), 1);
+        assert!(matches!(service.revoke_session(user_id, phone).await, Err(AppError::SessionNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_revoke_other_sessions() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (current_access, _) = service.create_token_pair(user_id, None).await.unwrap();
+        let (other_access, _) = service.create_token_pair(user_id, None).await.unwrap();
+        let (stranger_access, _) = service.create_token_pair(Uuid::new_v4(), None).await.unwrap();
+        let current = service.verify_token(&current_access).await.unwrap().family_id;
+
+        let revoked = service.revoke_other_sessions(user_id, current).await.unwrap();
+        assert_eq!(revoked.len(), 1);
+        assert!(service.verify_token(&current_access).await.is_ok());
+        assert!(service.verify_token(&other_access).await.is_err());
+        assert!(service.verify_token(&stranger_access).await.is_ok());
+
+        // Without a session to keep, everything goes
+        assert_eq!(service.revoke_other_sessions(user_id, None).await.unwrap(), vec![current.unwrap()]);
+        assert!(service.verify_token(&current_access).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revoke_device_sessions() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let removed = Uuid::new_v4();
+        let (removed_access, removed_refresh) = service.create_token_pair(user_id, Some(removed)).await.unwrap();
+        let (kept_access, _) = service.create_token_pair(user_id, Some(Uuid::new_v4())).await.unwrap();
+
+        assert_eq!(service.revoke_device_sessions(user_id, removed).await.unwrap().len(), 1);
+        assert!(service.verify_token(&removed_access).await.is_err());
+        assert!(service.refresh_token(&removed_refresh).await.is_err());
+        assert!(service.verify_token(&kept_access).await.is_ok());
+        // Another user's sessions are never matched
+        assert!(service.revoke_device_sessions(Uuid::new_v4(), removed).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mfa_tokens_are_not_access_or_refresh_tokens() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let mfa_token = service.create_mfa_token(user_id, None).unwrap();
+
+        let claims = service.verify_mfa_token(&mfa_token).await.unwrap();
+        assert_eq!(claims.sub, user_id);
+        assert_eq!(claims.token_type, TokenType::MfaPending);
+        assert!(matches!(service.refresh_token(&mfa_token).await, Err(AppError::InvalidToken)));
+
+        let (access_token, _) = service.create_token_pair(user_id, None).await.unwrap();
+        assert!(matches!(service.verify_mfa_token(&access_token).await, Err(AppError::InvalidToken)));
+
+        // Of two exchanges racing with the same token only one wins
+        let (first, second) = tokio::join!(
+            service.consume_mfa_token(&mfa_token),
+            service.consume_mfa_token(&mfa_token),
+        );
+        assert!(first.is_ok() != second.is_ok());
+        assert!(service.verify_mfa_token(&mfa_token).await.is_err());
+    }
+
+    fn asymmetric_config(algorithm: JwtAlgorithm, path: &std::path::Path) -> Config {
+        let mut config = Config::default();
+        config.auth.jwt_key_id = "signing".to_string();
+        config.auth.jwt_algorithm = algorithm;
+        config.auth.jwt_private_key_path = Some(path.display().to_string());
+        config
+    }
+
+    /// Verifies `token` the way a companion service would: with nothing but
+    /// our published JWKS.
+    fn verify_with_jwks(jwks: &JwkSet, token: &str) -> Claims {
+        let kid = decode_header(token).unwrap().kid.unwrap();
+        let jwk = jwks.find(&kid).expect("signing key missing from JWKS");
+        let algorithm = decode_header(token).unwrap().alg;
+        let key = jsonwebtoken::DecodingKey::from_jwk(jwk).unwrap();
+        decode::<Claims>(token, &key, &Validation::new(algorithm)).unwrap().claims
+    }
+
+    #[tokio::test]
+    async fn test_ed25519_tokens_verify_from_jwks() {
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let pem_bytes = pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref().to_vec()));
+        let path = std::env::temp_dir().join(format!("clipman-ed25519-{}.pem", Uuid::new_v4()));
+        std::fs::write(&path, pem_bytes).unwrap();
+
+        let service = service_with_config(asymmetric_config(JwtAlgorithm::EdDsa, &path), &Arc::new(MemoryStore::new()));
+        let user_id = Uuid::new_v4();
+        let (access_token, _) = service.create_token_pair(user_id, None).await.unwrap();
+        assert_eq!(decode_header(&access_token).unwrap().alg, jsonwebtoken::Algorithm::EdDSA);
+        assert_eq!(service.verify_token(&access_token).await.unwrap().sub, user_id);
+
+        let jwks = service.jwks();
+        assert_eq!(jwks.keys.len(), 1);
+        assert_eq!(verify_with_jwks(&jwks, &access_token).sub, user_id);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn test_rsa_tokens_verify_from_jwks() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/services/testdata/jwt_rsa_private.pem");
+        let service = service_with_config(asymmetric_config(JwtAlgorithm::Rs256, &path), &Arc::new(MemoryStore::new()));
+        let user_id = Uuid::new_v4();
+        let (access_token, _) = service.create_token_pair(user_id, None).await.unwrap();
+
+        let jwks = service.jwks();
+        let json = serde_json::to_value(&jwks).unwrap();
+        assert_eq!(json["keys"][0]["kty"], "RSA");
+        assert_eq!(json["keys"][0]["kid"], "signing");
+        assert_eq!(json["keys"][0]["alg"], "RS256");
+        assert!(json["keys"][0].get("d").is_none(), "private exponent must never be published");
+        assert_eq!(verify_with_jwks(&jwks, &access_token).sub, user_id);
+    }
+
+    #[tokio::test]
+    async fn test_previous_asymmetric_keys_keep_verifying() {
+        let testdata = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/services/testdata");
+        let store = Arc::new(MemoryStore::new());
+        let user_id = Uuid::new_v4();
+        let rsa_service = service_with_config(
+            asymmetric_config(JwtAlgorithm::Rs256, &testdata.join("jwt_rsa_private.pem")),
+            &store,
+        );
+        let (rsa_token, _) = rsa_service.create_token_pair(user_id, None).await.unwrap();
+
+        // Ed25519 public keys are the raw point behind a fixed SPKI header
+        let pkcs8 = ring::signature::Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let key_pair = ring::signature::Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let private_path = std::env::temp_dir().join(format!("clipman-ed25519-{}.pem", Uuid::new_v4()));
+        std::fs::write(&private_path, pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref().to_vec()))).unwrap();
+        let mut spki = vec![0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00];
+        spki.extend_from_slice(ring::signature::KeyPair::public_key(&key_pair).as_ref());
+        let public_path = std::env::temp_dir().join(format!("clipman-ed25519-{}.pub.pem", Uuid::new_v4()));
+        std::fs::write(&public_path, pem::encode(&pem::Pem::new("PUBLIC KEY", spki))).unwrap();
+        let mut ed_config = asymmetric_config(JwtAlgorithm::EdDsa, &private_path);
+        ed_config.auth.jwt_key_id = "ed".to_string();
+        let (ed_token, _) = service_with_config(ed_config, &store)
+            .create_token_pair(user_id, None)
+            .await
+            .unwrap();
+
+        let mut config = Config::default();
+        config.auth.jwt_key_id = "new".to_string();
+        config.auth.previous_keys = vec![
+            format!("signing:RS256={}", testdata.join("jwt_rsa_public.pem").display()).parse().unwrap(),
+            format!("ed:EdDSA={}", public_path.display()).parse().unwrap(),
+        ];
+        let service = service_with_config(config, &store);
+        assert_eq!(service.verify_token(&rsa_token).await.unwrap().sub, user_id);
+        assert_eq!(service.verify_token(&ed_token).await.unwrap().sub, user_id);
+
+        // Both stay published, matching what the old signer published
+        let jwks = service.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        let published = rsa_service.jwks();
+        assert_eq!(
+            serde_json::to_value(jwks.find("signing")).unwrap(),
+            serde_json::to_value(published.find("signing")).unwrap()
+        );
+        assert_eq!(verify_with_jwks(&jwks, &ed_token).sub, user_id);
+
+        let _ = std::fs::remove_file(&private_path);
+        let _ = std::fs::remove_file(&public_path);
+    }
+
+    #[tokio::test]
+    async fn test_hmac_keys_are_not_published() {
+        assert!(create_test_service().jwks().keys.is_empty());
+
+        let mut config = Config::default();
+        config.auth.jwt_algorithm = JwtAlgorithm::EdDsa;
+        assert!(Keyring::from_config(&config.auth).is_err(), "asymmetric keys need a PEM path");
+    }
+}
@@ -0,0 +1,361 @@
+use futures::Stream;
+use ring::digest;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::Blob,
+    store::{BlobStore, Store},
+};
+
+/// Upper bound on `blob.chunk_size`; chunk uploads are buffered whole.
+pub const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+/// Parses a `Range: bytes=...` header against a blob of `size` bytes into
+/// an inclusive byte range. Headers that are malformed, use another unit or
+/// ask for several ranges yield `None`, and the whole blob is served; a
+/// well-formed range that misses the blob fails with 416.
+pub fn parse_range(header: &str, size: u64) -> AppResult<Option<(u64, u64)>> {
+    let Some((start, end)) = header
+        .trim()
+        .strip_prefix("bytes=")
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return Ok(None);
+    };
+    let parse = |value: &str| value.trim().parse::<u64>().ok();
+    let unsatisfiable = || AppError::RangeNotSatisfiable(size);
+
+    let (start, end) = match (start.trim().is_empty(), end.trim().is_empty()) {
+        // `bytes=-500`: the last 500 bytes
+        (true, false) => {
+            let Some(suffix) = parse(end) else { return Ok(None) };
+            if suffix == 0 {
+                return Err(unsatisfiable());
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (false, true) => match parse(start) {
+            Some(start) => (start, size.saturating_sub(1)),
+            None => return Ok(None),
+        },
+        (false, false) => match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            _ => return Ok(None),
+        },
+        (true, true) => return Ok(None),
+    };
+    if start >= size {
+        return Err(unsatisfiable());
+    }
+    Ok(Some((start, end)))
+}
+
+/// Chunked, resumable uploads of payloads too large to inline in a clip.
+/// Clients upload chunks in any order, each with its SHA-256; asking for the
+/// blob tells them which chunks are still missing after an interruption.
+pub struct BlobService<S: ?Sized = dyn Store> {
+    config: Arc<Config>,
+    store: Arc<S>,
+    // Serializes new uploads so concurrent ones cannot overshoot the quotas
+    uploads: Mutex<()>,
+}
+
+impl<S: BlobStore + ?Sized> BlobService<S> {
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
+        Self {
+            config,
+            store,
+            uploads: Mutex::new(()),
+        }
+    }
+
+    /// Starts an upload of `size` bytes. Fails with `BlobQuotaExceeded` if
+    /// the user's blobs would total more than `blob.max_user_bytes`, or they
+    /// already have `blob.max_pending_uploads` unfinished uploads.
+    pub async fn create_upload(&self, user_id: Uuid, size: u64) -> AppResult<Blob> {
+        if size == 0 {
+            return Err(AppError::ValidationError("Blob is empty".to_string()));
+        }
+        if size > self.config.blob.max_size {
+            return Err(AppError::ValidationError("Blob exceeds maximum size".to_string()));
+        }
+
+        let _guard = self.uploads.lock().await;
+        let blobs = self.store.list_user_blobs(user_id).await?;
+        let pending = blobs.iter().filter(|blob| !blob.is_complete()).count();
+        if pending >= self.config.blob.max_pending_uploads {
+            return Err(AppError::BlobQuotaExceeded("Too many unfinished uploads".to_string()));
+        }
+        let used: u64 = blobs.iter().map(|blob| blob.size).sum();
+        if used.saturating_add(size) > self.config.blob.max_user_bytes {
+            return Err(AppError::BlobQuotaExceeded("Blob storage is full".to_string()));
+        }
+
+        let chunk_size = self.config.blob.chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self.store.insert_blob(Blob::new(user_id, size, chunk_size)).await
+    }
+
+    /// Other users' blobs are reported as missing.
+    pub async fn get_blob(&self, id: Uuid, user_id: Uuid) -> AppResult<Blob> {
+        self.store
+            .get_blob(id)
+            .await?
+            .filter(|blob| blob.user_id == user_id)
+            .ok_or(AppError::BlobNotFound(id))
+    }
+
+    /// Stores chunk `index` if it hashes to `sha256` (hex). The upload
+    /// completes with its last missing chunk; complete blobs are immutable,
+    /// which the store enforces with `BlobComplete`.
+    pub async fn put_chunk(&self, id: Uuid, user_id: Uuid, index: u32, data: Vec<u8>, sha256: &str) -> AppResult<Blob> {
+        let blob = self.get_blob(id, user_id).await?;
+        let (_, len) = blob
+            .chunk_range(index)
+            .ok_or_else(|| AppError::ValidationError(format!("Blob has no chunk {}", index)))?;
+        if data.len() as u64 != len {
+            return Err(AppError::ValidationError(format!("Chunk {} must be {} bytes", index, len)));
+        }
+        let actual = data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, &data).as_ref());
+        if !actual.eq_ignore_ascii_case(sha256.trim()) {
+            return Err(AppError::ValidationError(format!("Checksum mismatch for chunk {}", index)));
+        }
+
+        let mut blob = self.store.put_blob_chunk(id, index, data).await?;
+        if blob.received.len() as u32 == blob.chunk_count() {
+            let completed_at = now();
+            self.store.complete_blob(id, completed_at).await?;
+            blob.completed_at = Some(completed_at);
+        }
+        Ok(blob)
+    }
+
+    /// Streams bytes `start..=end` of a blob chunk by chunk, so a download
+    /// never holds more than one chunk in memory.
+    pub fn read_range(self: Arc<Self>, blob: Blob, start: u64, end: u64) -> impl Stream<Item = AppResult<Vec<u8>>> + Send
+    where
+        S: 'static,
+    {
+        futures::stream::try_unfold(start, move |position| {
+            let service = self.clone();
+            let blob = blob.clone();
+            async move {
+                if position > end {
+                    return Ok(None);
+                }
+                let index = (position / blob.chunk_size) as u32;
+                let missing = || AppError::InternalError(format!("Blob {} is missing chunk {}", blob.id, index));
+                let (chunk_start, _) = blob.chunk_range(index).ok_or_else(missing)?;
+                let chunk = service.store.get_blob_chunk(blob.id, index).await?.ok_or_else(missing)?;
+
+                let from = (position - chunk_start) as usize;
+                let to = ((end - chunk_start + 1) as usize).min(chunk.len());
+                Ok(Some((chunk[from..to].to_vec(), chunk_start + to as u64)))
+            }
+        })
+    }
+
+    /// Fails with `BlobInUse` while one of the user's clips refers to it.
+    pub async fn delete_blob(&self, id: Uuid, user_id: Uuid) -> AppResult<()> {
+        self.get_blob(id, user_id).await?;
+        self.store.delete_blob(id).await?;
+        Ok(())
+    }
+
+    /// Drops uploads not finished within `blob.upload_expiry`, and finished
+    /// blobs past `clipboard.retention_period` that no clip refers to. The
+    /// store checks for references as it deletes, so a clip saved meanwhile
+    /// keeps its blob.
+    pub async fn prune(&self) -> AppResult<usize> {
+        let now = now();
+        let upload_cutoff = now.saturating_sub(self.config.blob.upload_expiry);
+        let retention_cutoff = now.saturating_sub(self.config.clipboard.retention_period);
+
+        let candidates = self
+            .store
+            .list_blobs_created_before(upload_cutoff.max(retention_cutoff))
+            .await?;
+        let mut removed = 0;
+        for blob in candidates {
+            let stale = match blob.completed_at {
+                None => blob.created_at < upload_cutoff,
+                Some(completed_at) => completed_at < retention_cutoff,
+            };
+            if !stale {
+                continue;
+            }
+            match self.store.delete_blob(blob.id).await {
+                Ok(true) => removed += 1,
+                Ok(false) | Err(AppError::BlobInUse(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClipFormat, ClipboardData};
+    use crate::store::{ClipStore, MemoryStore};
+    use futures::TryStreamExt;
+
+    fn create_test_service(config: Config) -> (Arc<BlobService<MemoryStore>>, Arc<MemoryStore>) {
+        let store = Arc::new(MemoryStore::new());
+        (Arc::new(BlobService::new(Arc::new(config), store.clone())), store)
+    }
+
+    fn small_chunks() -> Config {
+        let mut config = Config::default();
+        config.blob.chunk_size = 4;
+        config.blob.max_size = 64;
+        config
+    }
+
+    fn sha256(data: &[u8]) -> String {
+        data_encoding::HEXLOWER.encode(digest::digest(&digest::SHA256, data).as_ref())
+    }
+
+    async fn upload(service: &BlobService<MemoryStore>, user_id: Uuid, payload: &[u8]) -> Blob {
+        let blob = service.create_upload(user_id, payload.len() as u64).await.unwrap();
+        let mut last = blob;
+        for (index, chunk) in payload.chunks(4).enumerate() {
+            last = service.put_chunk(last.id, user_id, index as u32, chunk.to_vec(), &sha256(chunk)).await.unwrap();
+        }
+        last
+    }
+
+    #[tokio::test]
+    async fn test_interrupted_upload_resumes() {
+        let (service, _) = create_test_service(small_chunks());
+        let user_id = Uuid::new_v4();
+        let payload = b"0123456789";
+
+        let blob = service.create_upload(user_id, payload.len() as u64).await.unwrap();
+        assert_eq!(blob.missing(), vec![0, 1, 2]);
+        service.put_chunk(blob.id, user_id, 2, b"89".to_vec(), &sha256(b"89")).await.unwrap();
+
+        // After reconnecting, the client asks what is missing and sends only that
+        let status = service.get_blob(blob.id, user_id).await.unwrap();
+        assert_eq!(status.missing(), vec![0, 1]);
+        assert!(!status.is_complete());
+        for index in status.missing() {
+            let chunk = &payload[index as usize * 4..][..4];
+            service.put_chunk(blob.id, user_id, index, chunk.to_vec(), &sha256(chunk).to_uppercase()).await.unwrap();
+        }
+
+        let blob = service.get_blob(blob.id, user_id).await.unwrap();
+        assert!(blob.is_complete());
+        let bytes: Vec<Vec<u8>> = service.clone().read_range(blob.clone(), 0, 9).try_collect().await.unwrap();
+        assert_eq!(bytes.concat(), payload);
+        let bytes: Vec<Vec<u8>> = service.clone().read_range(blob, 3, 8).try_collect().await.unwrap();
+        assert_eq!(bytes.concat(), b"345678");
+
+        let rejected = service.put_chunk(status.id, user_id, 0, b"0123".to_vec(), &sha256(b"0123")).await;
+        assert!(matches!(rejected, Err(AppError::BlobComplete(_))), "complete blobs are immutable");
+    }
+
+    #[tokio::test]
+    async fn test_bad_chunks_are_rejected() {
+        let (service, _) = create_test_service(small_chunks());
+        let user_id = Uuid::new_v4();
+        let blob = service.create_upload(user_id, 6).await.unwrap();
+
+        let cases = [
+            (0, b"0123".to_vec(), sha256(b"3210")),
+            (0, b"012".to_vec(), sha256(b"012")),
+            (1, b"4567".to_vec(), sha256(b"4567")),
+            (2, b"".to_vec(), sha256(b"")),
+        ];
+        for (index, data, checksum) in cases {
+            let result = service.put_chunk(blob.id, user_id, index, data, &checksum).await;
+            assert!(matches!(result, Err(AppError::ValidationError(_))), "{:?}", result);
+        }
+        assert!(service.get_blob(blob.id, user_id).await.unwrap().received.is_empty());
+
+        let stranger = service.put_chunk(blob.id, Uuid::new_v4(), 0, b"0123".to_vec(), &sha256(b"0123")).await;
+        assert!(matches!(stranger, Err(AppError::BlobNotFound(_))));
+        assert!(service.create_upload(user_id, 0).await.is_err());
+        assert!(service.create_upload(user_id, 65).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_uploads_are_capped_per_user() {
+        let mut config = small_chunks();
+        config.blob.max_user_bytes = 20;
+        config.blob.max_pending_uploads = 2;
+        let (service, _) = create_test_service(config);
+        let user_id = Uuid::new_v4();
+
+        let finished = upload(&service, user_id, b"01234567").await;
+        let pending = service.create_upload(user_id, 4).await.unwrap();
+        let second = service.create_upload(user_id, 4).await.unwrap();
+        let third = service.create_upload(user_id, 1).await;
+        assert!(matches!(third, Err(AppError::BlobQuotaExceeded(_))), "{:?}", third);
+
+        // Finishing an upload frees a pending slot but still counts its bytes
+        service.put_chunk(pending.id, user_id, 0, b"abcd".to_vec(), &sha256(b"abcd")).await.unwrap();
+        let too_big = service.create_upload(user_id, 5).await;
+        assert!(matches!(too_big, Err(AppError::BlobQuotaExceeded(_))), "{:?}", too_big);
+        service.create_upload(user_id, 4).await.unwrap();
+        assert!(service.create_upload(Uuid::new_v4(), 20).await.is_ok());
+
+        // Deleting a blob gives its bytes back
+        service.delete_blob(finished.id, user_id).await.unwrap();
+        service.put_chunk(second.id, user_id, 0, b"efgh".to_vec(), &sha256(b"efgh")).await.unwrap();
+        service.create_upload(user_id, 8).await.unwrap();
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000).unwrap(), Some((0, 99)));
+        assert_eq!(parse_range("bytes=900-", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000).unwrap(), Some((900, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000).unwrap(), Some((0, 999)));
+        assert_eq!(parse_range("bytes=500-5000", 1000).unwrap(), Some((500, 999)));
+
+        for header in ["bytes=1000-", "bytes=1000-2000", "bytes=-0"] {
+            assert!(matches!(parse_range(header, 1000), Err(AppError::RangeNotSatisfiable(1000))), "{}", header);
+        }
+        // Ranges we cannot make sense of are ignored rather than refused
+        for header in ["", "bytes=5-4", "bytes=-", "bytes=abc-", "items=0-1", "bytes=0-1,4-5"] {
+            assert_eq!(parse_range(header, 1000).unwrap(), None, "{}", header);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_referenced_and_recent_blobs() {
+        let mut config = small_chunks();
+        config.blob.upload_expiry = 0;
+        config.clipboard.retention_period = 0;
+        let (service, store) = create_test_service(config);
+        let user_id = Uuid::new_v4();
+
+        let referenced = upload(&service, user_id, b"kept").await;
+        let orphaned = upload(&service, user_id, b"gone").await;
+        let abandoned = service.create_upload(user_id, 8).await.unwrap();
+        let mut clip = ClipboardData::new(String::new(), Uuid::new_v4(), user_id);
+        clip.formats.push(ClipFormat::from_blob("image/png", referenced.id));
+        store.insert_clip(clip).await.unwrap();
+
+        // Everything was created this second, which the cutoffs exclude
+        assert_eq!(service.prune().await.unwrap(), 0);
+        tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+        assert_eq!(service.prune().await.unwrap(), 2);
+
+        assert!(service.get_blob(referenced.id, user_id).await.is_ok());
+        assert!(service.get_blob(orphaned.id, user_id).await.is_err());
+        assert!(service.get_blob(abandoned.id, user_id).await.is_err());
+
+        let in_use = service.delete_blob(referenced.id, user_id).await;
+        assert!(matches!(in_use, Err(AppError::BlobInUse(_))), "{:?}", in_use);
+        assert!(service.get_blob(referenced.id, user_id).await.is_ok());
+    }
+}
@@ -1,25 +1,38 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 use crate::{
     error::{AppError, AppResult},
-    models::ClipboardData,
+    models::{ClipboardData, PLAIN_TEXT},
     config::Config,
+    store::{Store, ClipStore, BlobStore},
 };
-use tokio::sync::{broadcast, RwLock};
-use std::collections::HashMap;
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
-pub struct ClipboardService {
+pub struct ClipboardService<S: ?Sized = dyn Store> {
     config: Arc<Config>,
-    clipboard_data: Arc<RwLock<HashMap<Uuid, ClipboardData>>>,
+    store: Arc<S>,
     tx: broadcast::Sender<ClipboardData>,
 }
 
-impl ClipboardService {
-    pub fn new(config: Arc<Config>) -> Self {
+/// Lowercases a MIME type, or returns `None` unless it is a bare
+/// `type/subtype` made of RFC 6838 name characters.
+fn normalize_mime_type(mime_type: &str) -> Option<String> {
+    let is_name = |name: &str| {
+        !name.is_empty()
+            && name.len() <= 127
+            && name.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b))
+    };
+    let (top_level, subtype) = mime_type.trim().split_once('/')?;
+    (is_name(top_level) && is_name(subtype)).then(|| format!("{}/{}", top_level, subtype).to_ascii_lowercase())
+}
+
+impl<S: ClipStore + BlobStore + ?Sized> ClipboardService<S> {
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
         let (tx, _) = broadcast::channel(config.clipboard.broadcast_capacity);
         Self {
             config,
-            clipboard_data: Arc::new(RwLock::new(HashMap::new())),
+            store,
             tx,
         }
     }
@@ -29,18 +42,25 @@ impl ClipboardService {
     }
 
     pub async fn save_clipboard(&self, mut data: ClipboardData) -> AppResult<ClipboardData> {
-        // Validate content size
-        if data.content.len() > self.config.clipboard.max_size {
+        self.validate_formats(&mut data)?;
+        self.validate_blobs(&data).await?;
+        if data.size() > self.config.clipboard.max_size {
             return Err(AppError::ValidationError("Content exceeds maximum size".to_string()));
         }
+        data.targets.sort();
+        data.targets.dedup();
 
         data.received_at = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .map_err(|e| AppError::InternalError(format!("Time error: {}", e)))?
             .as_secs();
 
-        let mut storage = self.clipboard_data.write().await;
-        storage.insert(data.id, data.clone());
+        // The store rechecks that blobs exist, in case one was deleted since
+        // `validate_blobs` looked
+        let data = self.store.insert_clip(data).await.map_err(|e| match e {
+            AppError::BlobNotFound(id) => AppError::InvalidClipboardData(format!("Blob {} is missing or unfinished", id)),
+            e => e,
+        })?;
 
         // Broadcast update, ignore errors as receivers might have disconnected
         let _ = self.tx.send(data.clone());
@@ -48,100 +68,150 @@ impl ClipboardService {
         Ok(data)
     }
 
+    /// Checks each representation against the per-format limits and
+    /// normalizes its MIME type. The plain text counts as a format too.
+    fn validate_formats(&self, data: &mut ClipboardData) -> AppResult<()> {
+        let limits = &self.config.clipboard;
+        if data.formats.len() > limits.max_formats {
+            return Err(AppError::InvalidClipboardData(format!(
+                "At most {} formats besides plain text are allowed",
+                limits.max_formats
+            )));
+        }
+        if data.content.len() > limits.max_format_size {
+            return Err(AppError::ValidationError("Content exceeds maximum size".to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        for format in &mut data.formats {
+            format.mime_type = normalize_mime_type(&format.mime_type).ok_or_else(|| {
+                AppError::InvalidClipboardData(format!("Invalid MIME type: {:?}", format.mime_type))
+            })?;
+            if format.mime_type == PLAIN_TEXT {
+                return Err(AppError::InvalidClipboardData("Plain text belongs in content".to_string()));
+            }
+            if !seen.insert(format.mime_type.clone()) {
+                return Err(AppError::InvalidClipboardData(format!("Duplicate format: {}", format.mime_type)));
+            }
+            if format.data.len() > limits.max_format_size {
+                return Err(AppError::ValidationError(format!("Format {} exceeds maximum size", format.mime_type)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats held in blobs must name one of the sender's finished uploads
+    /// and carry no inline data.
+    async fn validate_blobs(&self, data: &ClipboardData) -> AppResult<()> {
+        for format in &data.formats {
+            let Some(blob_id) = format.blob_id else { continue };
+            if !format.data.is_empty() {
+                return Err(AppError::InvalidClipboardData(format!(
+                    "Format {} has both inline data and a blob",
+                    format.mime_type
+                )));
+            }
+            let usable = self
+                .store
+                .get_blob(blob_id)
+                .await?
+                .is_some_and(|blob| blob.user_id == data.user_id && blob.is_complete());
+            if !usable {
+                return Err(AppError::InvalidClipboardData(format!("Blob {} is missing or unfinished", blob_id)));
+            }
+        }
+        Ok(())
+    }
+
     pub async fn get_clipboard(&self, id: Uuid) -> AppResult<ClipboardData> {
-        let storage = self.clipboard_data.read().await;
-        storage
-            .get(&id)
-            .cloned()
+        self.store
+            .get_clip(id)
+            .await?
             .ok_or(AppError::ClipboardNotFound(id))
     }
 
     pub async fn get_user_clipboard(&self, user_id: Uuid) -> AppResult<Vec<ClipboardData>> {
-        let storage = self.clipboard_data.read().await;
-        let mut user_data: Vec<ClipboardData> = storage
-            .values()
-            .filter(|data| data.user_id == user_id)
-            .cloned()
-            .collect();
+        // Sorted by received_at in descending order
+        let user_data = self.store.list_user_clips(user_id).await?;
 
         if user_data.is_empty() {
             return Err(AppError::ValidationError("No clipboard data found for user".to_string()));
         }
 
-        // Sort by received_at in descending order
-        user_data.sort_by(|a, b| b.received_at.cmp(&a.received_at));
         Ok(user_data)
     }
 
+    /// Newest-first history for a user, possibly empty.
+    pub async fn get_user_history(&self, user_id: Uuid, limit: usize) -> AppResult<Vec<ClipboardData>> {
+        let mut history = self.store.list_user_clips(user_id).await?;
+        history.truncate(limit);
+        Ok(history)
+    }
+
+    /// Clips with `seq` above `after_seq`, oldest first, for replay.
+    pub async fn get_user_clips_after(&self, user_id: Uuid, after_seq: u64) -> AppResult<Vec<ClipboardData>> {
+        self.store.list_user_clips_after(user_id, after_seq).await
+    }
+
+    /// Highest sequence number among the user's stored clips, 0 if none.
+    pub async fn latest_seq(&self, user_id: Uuid) -> AppResult<u64> {
+        let clips = self.store.list_user_clips(user_id).await?;
+        Ok(clips.iter().map(|data| data.seq).max().unwrap_or(0))
+    }
+
     pub async fn get_device_clipboard(&self, device_id: Uuid) -> AppResult<Vec<ClipboardData>> {
-        let storage = self.clipboard_data.read().await;
-        let mut device_data: Vec<ClipboardData> = storage
-            .values()
-            .filter(|data| data.device_id == device_id)
-            .cloned()
-            .collect();
+        // Sorted by received_at in descending order
+        let device_data = self.store.list_device_clips(device_id).await?;
 
         if device_data.is_empty() {
             return Err(AppError::ValidationError("No clipboard data found for device".to_string()));
         }
 
-        // Sort by received_at in descending order
-        device_data.sort_by(|a, b| b.received_at.cmp(&a.received_at));
         Ok(device_data)
     }
 
     pub async fn delete_clipboard(&self, id: Uuid, user_id: Uuid) -> AppResult<()> {
-        let mut storage = self.clipboard_data.write().await;
-        
         // Check if the clipboard belongs to the user
-        if let Some(data) = storage.get(&id) {
-            if data.user_id != user_id {
-                return Err(AppError::DeviceUnauthorized(id));
-            }
+        let data = self.get_clipboard(id).await?;
+        if data.user_id != user_id {
+            return Err(AppError::DeviceUnauthorized(id));
         }
 
-        storage
-            .remove(&id)
-            .ok_or(AppError::ClipboardNotFound(id))?;
+        if !self.store.delete_clip(id).await? {
+            return Err(AppError::ClipboardNotFound(id));
+        }
         Ok(())
     }
 
     pub async fn delete_user_clipboard(&self, user_id: Uuid) -> AppResult<()> {
-        let mut storage = self.clipboard_data.write().await;
-        let initial_len = storage.len();
-        
-        storage.retain(|_, data| data.user_id != user_id);
+        let removed = self.store.delete_user_clips(user_id).await?;
         
-        if storage.len() == initial_len {
+        if removed == 0 {
             return Err(AppError::ValidationError("No clipboard data found for user".to_string()));
         }
         
         Ok(())
     }
 
-    pub async fn cleanup_old_data(&self) -> AppResult<()> {
+    /// Removes clips older than `retention_period`. Returns how many were removed.
+    pub async fn cleanup_old_data(&self) -> AppResult<usize> {
         let now = std::time::SystemTime::now()
             .duration_since(std::time::UNIX_EPOCH)
             .map_err(|e| AppError::InternalError(format!("Time error: {}", e)))?
             .as_secs();
             
         let retention_period = self.config.clipboard.retention_period;
-        let mut storage = self.clipboard_data.write().await;
-        
-        storage.retain(|_, data| {
-            now - data.received_at < retention_period
-        });
-        
-        Ok(())
+        self.store
+            .delete_clips_received_before(now.saturating_sub(retention_period))
+            .await
     }
 
     pub async fn get_latest_clipboard(&self, user_id: Uuid) -> AppResult<ClipboardData> {
-        let storage = self.clipboard_data.read().await;
-        storage
-            .values()
-            .filter(|data| data.user_id == user_id)
-            .max_by_key(|data| data.received_at)
-            .cloned()
+        self.store
+            .list_user_clips(user_id)
+            .await?
+            .into_iter()
+            .next()
             .ok_or(AppError::ValidationError("No clipboard data found for user".to_string()))
     }
 }
@@ -149,6 +219,8 @@ impl ClipboardService {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{Blob, ClipFormat};
+    use crate::store::MemoryStore;
     use std::time::Duration;
 
     fn create_test_config() -> Arc<Config> {
@@ -159,6 +231,10 @@ mod tests {
         Arc::new(config)
     }
 
+    fn create_test_service() -> ClipboardService<MemoryStore> {
+        ClipboardService::new(create_test_config(), Arc::new(MemoryStore::new()))
+    }
+
     fn create_test_data(user_id: Uuid, device_id: Uuid) -> ClipboardData {
         ClipboardData::new(
             "test content".to_string(),
@@ -169,7 +245,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_save_and_get_clipboard() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let user_id = Uuid::new_v4();
         let device_id = Uuid::new_v4();
         
@@ -184,7 +260,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_user_clipboard() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let user_id = Uuid::new_v4();
         let device_id = Uuid::new_v4();
         
@@ -200,7 +276,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_content_too_large() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let user_id = Uuid::new_v4();
         let device_id = Uuid::new_v4();
         
@@ -214,9 +290,79 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_format_limits() {
+        let mut config = Config::default();
+        config.clipboard.max_size = 100;
+        config.clipboard.max_format_size = 60;
+        config.clipboard.max_formats = 2;
+        let service = ClipboardService::new(Arc::new(config), Arc::new(MemoryStore::new()));
+        let clip = |formats: Vec<ClipFormat>| {
+            let mut data = create_test_data(Uuid::new_v4(), Uuid::new_v4());
+            data.formats = formats;
+            data
+        };
+
+        let saved = service
+            .save_clipboard(clip(vec![ClipFormat::new(" Text/HTML", "<b>test content</b>"), ClipFormat::new("image/png", vec![0; 60])]))
+            .await
+            .unwrap();
+        let types: Vec<&str> = saved.formats.iter().map(|format| format.mime_type.as_str()).collect();
+        assert_eq!(types, ["text/html", "image/png"]);
+
+        // One format over its own limit, and several over the total
+        let too_big = service.save_clipboard(clip(vec![ClipFormat::new("image/png", vec![0; 61])])).await;
+        assert!(matches!(too_big, Err(AppError::ValidationError(msg)) if msg.contains("image/png")));
+        let total = clip(vec![ClipFormat::new("image/png", vec![0; 50]), ClipFormat::new("image/jpeg", vec![0; 50])]);
+        assert!(matches!(service.save_clipboard(total).await, Err(AppError::ValidationError(_))));
+
+        let rejected = [
+            vec![ClipFormat::new("text/plain", "dup")],
+            vec![ClipFormat::new("image/png", "a"), ClipFormat::new("IMAGE/PNG", "b")],
+            vec![ClipFormat::new("text/html; charset=utf-8", "x")],
+            vec![ClipFormat::new("png", "x")],
+            vec![ClipFormat::new("a/a", "x"), ClipFormat::new("b/b", "x"), ClipFormat::new("c/c", "x")],
+        ];
+        for formats in rejected {
+            let result = service.save_clipboard(clip(formats)).await;
+            assert!(matches!(result, Err(AppError::InvalidClipboardData(_))), "{:?}", result);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_formats_can_reference_finished_blobs() {
+        let store = Arc::new(MemoryStore::new());
+        let service = ClipboardService::new(create_test_config(), store.clone());
+        let user_id = Uuid::new_v4();
+        let finished = store.insert_blob(Blob::new(user_id, 500, 500)).await.unwrap();
+        store.complete_blob(finished.id, 1).await.unwrap();
+        let unfinished = store.insert_blob(Blob::new(user_id, 500, 500)).await.unwrap();
+        let foreign = store.insert_blob(Blob::new(Uuid::new_v4(), 500, 500)).await.unwrap();
+        store.complete_blob(foreign.id, 1).await.unwrap();
+
+        // The blob's size does not count towards `max_size`
+        let mut data = create_test_data(user_id, Uuid::new_v4());
+        data.formats.push(ClipFormat::from_blob("image/png", finished.id));
+        service.save_clipboard(data).await.unwrap();
+
+        let mut both = ClipFormat::from_blob("image/png", finished.id);
+        both.data = vec![1];
+        for format in [
+            ClipFormat::from_blob("image/png", unfinished.id),
+            ClipFormat::from_blob("image/png", foreign.id),
+            ClipFormat::from_blob("image/png", Uuid::new_v4()),
+            both,
+        ] {
+            let mut data = create_test_data(user_id, Uuid::new_v4());
+            data.formats.push(format);
+            let result = service.save_clipboard(data).await;
+            assert!(matches!(result, Err(AppError::InvalidClipboardData(_))), "{:?}", result);
+        }
+    }
+
     #[tokio::test]
     async fn test_delete_unauthorized() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let user_id = Uuid::new_v4();
         let other_user_id = Uuid::new_v4();
         let device_id = Uuid::new_v4();
@@ -230,7 +376,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_broadcast_updates() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let mut rx1 = service.subscribe();
         let mut rx2 = service.subscribe();
 
@@ -247,9 +393,30 @@ mod tests {
         assert_eq!(received2.id, saved_data.id);
     }
 
+    #[tokio::test]
+    async fn test_repeat_copies_bump_history() {
+        let service = create_test_service();
+        let mut rx = service.subscribe();
+        let user_id = Uuid::new_v4();
+        let (laptop, phone) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let first = service.save_clipboard(create_test_data(user_id, laptop)).await.unwrap();
+        let again = service.save_clipboard(create_test_data(user_id, phone)).await.unwrap();
+        assert_eq!(again.id, first.id);
+        assert_eq!((first.seq, again.seq), (1, 2));
+
+        // Both copies still reach the other devices
+        assert_eq!(rx.try_recv().unwrap().device_id, laptop);
+        assert_eq!(rx.try_recv().unwrap().device_id, phone);
+
+        let history = service.get_user_history(user_id, 10).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].device_id, phone);
+    }
+
     #[tokio::test]
     async fn test_cleanup_old_data() {
-        let service = ClipboardService::new(create_test_config());
+        let service = create_test_service();
         let user_id = Uuid::new_v4();
         let device_id = Uuid::new_v4();
         
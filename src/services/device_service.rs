@@ -1,39 +1,112 @@
 use std::sync::Arc;
+use tokio::sync::Mutex;
 use uuid::Uuid;
 use crate::{
     config::Config,
     error::{AppError, AppResult},
-    models::Device,
+    models::{Device, Subscription},
+    store::{Store, DeviceStore},
 };
 
-pub struct DeviceService {
+const MAX_DEVICE_NAME_LENGTH: usize = 64;
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+pub struct DeviceService<S: ?Sized = dyn Store> {
     config: Arc<Config>,
+    store: Arc<S>,
+    // Serializes registrations so concurrent ones cannot overshoot the cap
+    registration: Mutex<()>,
+}
+
+fn validate_name(name: &str) -> AppResult<()> {
+    if name.trim().is_empty() {
+        return Err(AppError::ValidationError("Device name must not be empty".to_string()));
+    }
+    if name.chars().count() > MAX_DEVICE_NAME_LENGTH {
+        return Err(AppError::ValidationError(format!(
+            "Device name must be at most {} characters",
+            MAX_DEVICE_NAME_LENGTH
+        )));
+    }
+    Ok(())
 }
 
-impl DeviceService {
-    pub fn new(config: Arc<Config>) -> Self {
-        Self { config }
+impl<S: DeviceStore + ?Sized> DeviceService<S> {
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
+        Self {
+            config,
+            store,
+            registration: Mutex::new(()),
+        }
     }
 
+    /// Fails with `TooManyDevices` once the user has `max_devices`.
     pub async fn register_device(&self, user_id: Uuid, name: String) -> AppResult<Device> {
-        // TODO add storage later
+        validate_name(&name)?;
+        let _guard = self.registration.lock().await;
+        if self.store.list_user_devices(user_id).await?.len() >= self.config.user.max_devices {
+            return Err(AppError::TooManyDevices);
+        }
         let device = Device::new(name, user_id);
-        Ok(device)
+        self.store.insert_device(device).await
+    }
+
+    pub async fn rename_device(&self, id: Uuid, user_id: Uuid, name: String) -> AppResult<Device> {
+        validate_name(&name)?;
+        self.verify_device(id, user_id).await?;
+        let mut device = self.get_device(id).await?;
+        device.name = name;
+        self.store.update_device(device).await
     }
 
     pub async fn get_user_devices(&self, user_id: Uuid) -> AppResult<Vec<Device>> {
-        // TODO add storage later
-        Ok(Vec::new())
+        self.store.list_user_devices(user_id).await
     }
 
     pub async fn get_device(&self, id: Uuid) -> AppResult<Device> {
-        Err(AppError::DeviceNotFound(id))
+        self.store
+            .get_device(id)
+            .await?
+            .ok_or(AppError::DeviceNotFound(id))
     }
 
-    pub async fn update_device_status(&self, id: Uuid) -> AppResult<Device> {
+    /// Replaces the device's subscription. Every listed device has to be
+    /// another of the user's devices.
+    pub async fn set_subscription(&self, id: Uuid, user_id: Uuid, mut subscription: Subscription) -> AppResult<Device> {
+        self.verify_device(id, user_id).await?;
+        if let Subscription::Only(devices) | Subscription::Except(devices) = &mut subscription {
+            devices.sort();
+            devices.dedup();
+        }
+        for source in subscription.devices() {
+            let owned = self
+                .store
+                .get_device(*source)
+                .await?
+                .is_some_and(|device| device.user_id == user_id && device.id != id);
+            if !owned {
+                return Err(AppError::ValidationError(format!(
+                    "Device {} is not another of your devices",
+                    source
+                )));
+            }
+        }
+
         let mut device = self.get_device(id).await?;
-        device.update_last_seen();
-        Ok(device)
+        device.subscription = subscription;
+        self.store.update_device(device).await
+    }
+
+    /// Writes only `last_seen`, so it cannot undo a rename or subscription
+    /// change made at the same time.
+    pub async fn update_device_status(&self, id: Uuid) -> AppResult<Device> {
+        if !self.store.touch_device(id, now()).await? {
+            return Err(AppError::DeviceNotFound(id));
+        }
+        self.get_device(id).await
     }
 
     pub async fn verify_device(&self, device_id: Uuid, user_id: Uuid) -> AppResult<bool> {
@@ -44,9 +117,105 @@ impl DeviceService {
         Ok(true)
     }
 
+    /// Checks every device in `ids` the way `verify_device` does.
+    pub async fn verify_devices(&self, ids: &[Uuid], user_id: Uuid) -> AppResult<()> {
+        for id in ids {
+            self.verify_device(*id, user_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the device record. Its sessions and sockets are the
+    /// caller's to revoke.
     pub async fn remove_device(&self, id: Uuid, user_id: Uuid) -> AppResult<()> {
         self.verify_device(id, user_id).await?;
-        // TODO add deletion logic later
+        if !self.store.delete_device(id).await? {
+            return Err(AppError::DeviceNotFound(id));
+        }
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn create_test_service(max_devices: usize) -> DeviceService<MemoryStore> {
+        let mut config = Config::default();
+        config.user.max_devices = max_devices;
+        DeviceService::new(Arc::new(config), Arc::new(MemoryStore::new()))
+    }
+
+    #[tokio::test]
+    async fn test_devices_are_capped_per_user() {
+        let service = create_test_service(2);
+        let user_id = Uuid::new_v4();
+        let laptop = service.register_device(user_id, "laptop".to_string()).await.unwrap();
+        service.register_device(user_id, "phone".to_string()).await.unwrap();
+
+        let third = service.register_device(user_id, "tablet".to_string()).await;
+        assert!(matches!(third, Err(AppError::TooManyDevices)));
+        // The cap is per user
+        service.register_device(Uuid::new_v4(), "laptop".to_string()).await.unwrap();
+
+        // Removing a device frees its slot
+        service.remove_device(laptop.id, user_id).await.unwrap();
+        service.register_device(user_id, "tablet".to_string()).await.unwrap();
+        let names: Vec<String> = service
+            .get_user_devices(user_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|device| device.name)
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"tablet".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_remove_check_ownership() {
+        let service = create_test_service(10);
+        let user_id = Uuid::new_v4();
+        let device = service.register_device(user_id, "laptop".to_string()).await.unwrap();
+        let stranger = Uuid::new_v4();
+
+        let renamed = service.rename_device(device.id, user_id, "work laptop".to_string()).await.unwrap();
+        assert_eq!(renamed.name, "work laptop");
+        assert_eq!(service.get_device(device.id).await.unwrap().name, "work laptop");
+        assert!(matches!(
+            service.rename_device(device.id, user_id, "  ".to_string()).await,
+            Err(AppError::ValidationError(_))
+        ));
+        assert!(matches!(
+            service.rename_device(device.id, stranger, "mine".to_string()).await,
+            Err(AppError::DeviceUnauthorized(_))
+        ));
+
+        assert!(service.remove_device(device.id, stranger).await.is_err());
+        assert!(service.set_subscription(device.id, stranger, Subscription::All).await.is_err());
+        service.remove_device(device.id, user_id).await.unwrap();
+        assert!(matches!(service.get_device(device.id).await, Err(AppError::DeviceNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions_only_name_own_devices() {
+        let service = create_test_service(10);
+        let user_id = Uuid::new_v4();
+        let laptop = service.register_device(user_id, "laptop".to_string()).await.unwrap();
+        let phone = service.register_device(user_id, "phone".to_string()).await.unwrap();
+        let foreign = service.register_device(Uuid::new_v4(), "foreign".to_string()).await.unwrap();
+
+        let updated = service
+            .set_subscription(laptop.id, user_id, Subscription::Only(vec![phone.id, phone.id]))
+            .await
+            .unwrap();
+        assert_eq!(updated.subscription, Subscription::Only(vec![phone.id]));
+        assert_eq!(service.get_device(laptop.id).await.unwrap().subscription, updated.subscription);
+
+        for bad in [vec![foreign.id], vec![laptop.id], vec![Uuid::new_v4()]] {
+            let rejected = service.set_subscription(laptop.id, user_id, Subscription::Except(bad)).await;
+            assert!(matches!(rejected, Err(AppError::ValidationError(_))));
+        }
+    }
+}
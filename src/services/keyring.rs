@@ -0,0 +1,285 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use std::collections::HashMap;
+use crate::{
+    config::{AuthConfig, JwtAlgorithm},
+    error::{AppError, AppResult},
+};
+
+pub(crate) struct SigningKey {
+    pub algorithm: Algorithm,
+    /// `None` for retired keys loaded from their public half, which can
+    /// only verify.
+    pub encoding_key: Option<EncodingKey>,
+    pub decoding_key: DecodingKey,
+    /// Public half for the JWKS; `None` for shared secrets.
+    pub public_jwk: Option<Jwk>,
+    /// Unix seconds after which tokens signed with this key are rejected.
+    pub retire_at: Option<u64>,
+}
+
+fn key_err(message: impl std::fmt::Display) -> AppError {
+    AppError::InternalError(format!("Invalid JWT signing key: {}", message))
+}
+
+/// Splits the first DER element off `input` as (tag, contents, rest).
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, input) = input.split_first()?;
+    let (&first, input) = input.split_first()?;
+    let (len, input) = match first {
+        0..=0x7f => (first as usize, input),
+        0x81..=0x84 => {
+            let (bytes, input) = input.split_at_checked((first & 0x7f) as usize)?;
+            (bytes.iter().fold(0, |len, &byte| (len << 8) | byte as usize), input)
+        }
+        _ => return None,
+    };
+    let (contents, rest) = input.split_at_checked(len)?;
+    Some((tag, contents, rest))
+}
+
+/// The key bits of a SubjectPublicKeyInfo. Which algorithm they belong to
+/// is taken from the configuration, not the embedded identifier.
+fn spki_key_bits(der: &[u8]) -> Option<&[u8]> {
+    let (0x30, spki, _) = der_element(der)? else { return None };
+    let (0x30, _algorithm, rest) = der_element(spki)? else { return None };
+    let (0x03, bits, _) = der_element(rest)? else { return None };
+    bits.strip_prefix(&[0])
+}
+
+/// Modulus and exponent of a PKCS#1 `RSAPublicKey`, without sign padding.
+fn rsa_public_components(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (0x30, key, _) = der_element(der)? else { return None };
+    let (0x02, n, rest) = der_element(key)? else { return None };
+    let (0x02, e, _) = der_element(rest)? else { return None };
+    Some((unsigned(n), unsigned(e)))
+}
+
+/// Drops the zero byte DER puts before integers with the top bit set.
+fn unsigned(int: &[u8]) -> &[u8] {
+    match int {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        int => int,
+    }
+}
+
+fn public_jwk(key_algorithm: KeyAlgorithm, algorithm: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            ..Default::default()
+        },
+        algorithm,
+    }
+}
+
+impl SigningKey {
+    pub fn hmac(secret: &[u8], retire_at: Option<u64>) -> Self {
+        Self {
+            algorithm: Algorithm::HS256,
+            encoding_key: Some(EncodingKey::from_secret(secret)),
+            decoding_key: DecodingKey::from_secret(secret),
+            public_jwk: None,
+            retire_at,
+        }
+    }
+
+    /// Loads a private key from PEM. Ed25519 keys must be PKCS#8; RSA keys
+    /// may be PKCS#8 or PKCS#1. The verifying key is derived from the public
+    /// components we publish, so the JWKS cannot drift from what we accept.
+    pub fn from_pem(algorithm: JwtAlgorithm, pem_bytes: &[u8]) -> AppResult<Self> {
+        let parsed = pem::parse(pem_bytes).map_err(key_err)?;
+
+        let (algorithm, encoding_key, jwk) = match algorithm {
+            JwtAlgorithm::Hs256 => return Err(key_err("HS256 keys are shared secrets, not PEM files")),
+            JwtAlgorithm::EdDsa => {
+                let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(parsed.contents())
+                    .map_err(key_err)?;
+                let jwk = public_jwk(
+                    KeyAlgorithm::EdDSA,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+                    }),
+                );
+                (Algorithm::EdDSA, EncodingKey::from_ed_pem(pem_bytes).map_err(key_err)?, jwk)
+            }
+            JwtAlgorithm::Rs256 => {
+                let key_pair = match parsed.tag() {
+                    "RSA PRIVATE KEY" => RsaKeyPair::from_der(parsed.contents()),
+                    _ => RsaKeyPair::from_pkcs8(parsed.contents()),
+                }
+                .map_err(key_err)?;
+                let components = RsaPublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+                let jwk = public_jwk(
+                    KeyAlgorithm::RS256,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(&components.n),
+                        e: URL_SAFE_NO_PAD.encode(&components.e),
+                    }),
+                );
+                (Algorithm::RS256, EncodingKey::from_rsa_pem(pem_bytes).map_err(key_err)?, jwk)
+            }
+        };
+
+        Ok(Self {
+            algorithm,
+            encoding_key: Some(encoding_key),
+            decoding_key: DecodingKey::from_jwk(&jwk).map_err(key_err)?,
+            public_jwk: Some(jwk),
+            retire_at: None,
+        })
+    }
+
+    /// Loads a verify-only key from a PEM public key: SPKI (`PUBLIC KEY`)
+    /// for either algorithm, or PKCS#1 (`RSA PUBLIC KEY`) for RSA. Like the
+    /// keys above, it is still published in the JWKS until it retires.
+    pub fn from_public_pem(algorithm: JwtAlgorithm, pem_bytes: &[u8], retire_at: Option<u64>) -> AppResult<Self> {
+        let parsed = pem::parse(pem_bytes).map_err(key_err)?;
+        let malformed = || key_err(format!("Malformed {} public key", parsed.tag()));
+
+        let (algorithm, jwk) = match algorithm {
+            JwtAlgorithm::Hs256 => return Err(key_err("HS256 keys are shared secrets, not PEM files")),
+            JwtAlgorithm::EdDsa => {
+                let x = match parsed.tag() {
+                    "PUBLIC KEY" => spki_key_bits(parsed.contents()).filter(|bits| bits.len() == 32),
+                    _ => None,
+                }
+                .ok_or_else(malformed)?;
+                let jwk = public_jwk(
+                    KeyAlgorithm::EdDSA,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(x),
+                    }),
+                );
+                (Algorithm::EdDSA, jwk)
+            }
+            JwtAlgorithm::Rs256 => {
+                let (n, e) = match parsed.tag() {
+                    "PUBLIC KEY" => spki_key_bits(parsed.contents()).and_then(rsa_public_components),
+                    "RSA PUBLIC KEY" => rsa_public_components(parsed.contents()),
+                    _ => None,
+                }
+                .ok_or_else(malformed)?;
+                let jwk = public_jwk(
+                    KeyAlgorithm::RS256,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(n),
+                        e: URL_SAFE_NO_PAD.encode(e),
+                    }),
+                );
+                (Algorithm::RS256, jwk)
+            }
+        };
+
+        Ok(Self {
+            algorithm,
+            encoding_key: None,
+            decoding_key: DecodingKey::from_jwk(&jwk).map_err(key_err)?,
+            public_jwk: Some(jwk),
+            retire_at,
+        })
+    }
+
+    fn is_retired(&self, now: u64) -> bool {
+        self.retire_at.is_some_and(|retire_at| retire_at <= now)
+    }
+}
+
+/// Signing keys addressed by the `kid` in the JWT header. Exactly one key
+/// signs new tokens; the others only verify tokens issued before a rotation
+/// until they retire.
+pub(crate) struct Keyring {
+    active_kid: String,
+    keys: HashMap<String, SigningKey>,
+}
+
+impl Keyring {
+    pub fn from_config(config: &AuthConfig) -> AppResult<Self> {
+        let mut keys = HashMap::new();
+        for previous in &config.previous_keys {
+            let key = match previous.algorithm {
+                JwtAlgorithm::Hs256 => SigningKey::hmac(previous.key.as_bytes(), previous.retire_at),
+                algorithm => {
+                    let pem_bytes = std::fs::read(&previous.key)
+                        .map_err(|e| key_err(format!("Failed to read {}: {}", previous.key, e)))?;
+                    SigningKey::from_public_pem(algorithm, &pem_bytes, previous.retire_at)?
+                }
+            };
+            if keys.insert(previous.kid.clone(), key).is_some() {
+                return Err(key_err(format!("Key id {} is listed twice", previous.kid)));
+            }
+        }
+        if keys.contains_key(&config.jwt_key_id) {
+            return Err(key_err(format!("Key id {} is already used by a previous key", config.jwt_key_id)));
+        }
+
+        let active = match (config.jwt_algorithm, &config.jwt_private_key_path) {
+            (JwtAlgorithm::Hs256, _) => SigningKey::hmac(config.jwt_secret.as_bytes(), None),
+            (algorithm, Some(path)) => {
+                let pem_bytes = std::fs::read(path)
+                    .map_err(|e| key_err(format!("Failed to read {}: {}", path, e)))?;
+                SigningKey::from_pem(algorithm, &pem_bytes)?
+            }
+            (algorithm, None) => {
+                return Err(key_err(format!("{:?} needs jwt_private_key_path", algorithm)));
+            }
+        };
+        keys.insert(config.jwt_key_id.clone(), active);
+
+        Ok(Self {
+            active_kid: config.jwt_key_id.clone(),
+            keys,
+        })
+    }
+
+    pub fn active(&self) -> (&str, &SigningKey) {
+        (&self.active_kid, &self.keys[&self.active_kid])
+    }
+
+    /// Looks up the key a token names. Tokens without a `kid` predate the
+    /// keyring and are checked against the active key.
+    pub fn verifying_key(&self, kid: Option<&str>, now: u64) -> AppResult<&SigningKey> {
+        let key = match kid {
+            Some(kid) => self.keys.get(kid).ok_or(AppError::InvalidToken)?,
+            None => self.active().1,
+        };
+        if key.is_retired(now) {
+            return Err(AppError::InvalidToken);
+        }
+        Ok(key)
+    }
+
+    pub fn key_ids(&self) -> Vec<String> {
+        let mut kids: Vec<String> = self.keys.keys().cloned().collect();
+        kids.sort();
+        kids
+    }
+
+    /// Public keys of every asymmetric key still accepted, for `/.well-known/jwks.json`.
+    pub fn jwks(&self, now: u64) -> JwkSet {
+        let mut keys: Vec<Jwk> = self
+            .keys
+            .iter()
+            .filter(|(_, key)| !key.is_retired(now))
+            .filter_map(|(kid, key)| {
+                let mut jwk = key.public_jwk.clone()?;
+                jwk.common.key_id = Some(kid.clone());
+                Some(jwk)
+            })
+            .collect();
+        keys.sort_by(|a, b| a.common.key_id.cmp(&b.common.key_id));
+        JwkSet { keys }
+    }
+}
@@ -0,0 +1,227 @@
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{watch, RwLock};
+use tokio::task::JoinHandle;
+use crate::{
+    config::Config,
+    error::AppResult,
+};
+use super::{AuthService, BlobService, ClipboardService};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobStatus {
+    /// Unix seconds of the last run, successful or not.
+    pub last_run: Option<u64>,
+    /// Number of entries removed by the last successful run.
+    pub last_removed: usize,
+    pub last_error: Option<String>,
+    pub runs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MaintenanceStatus {
+    pub clipboard_cleanup: JobStatus,
+    pub token_prune: JobStatus,
+    pub blob_prune: JobStatus,
+}
+
+/// Periodic housekeeping: enforces `clipboard.retention_period`, drops
+/// blacklisted tokens once they have expired anyway and frees abandoned or
+/// unreferenced blobs.
+pub struct MaintenanceService {
+    config: Arc<Config>,
+    clipboard_service: Arc<ClipboardService>,
+    auth_service: Arc<AuthService>,
+    blob_service: Arc<BlobService>,
+    status: RwLock<MaintenanceStatus>,
+}
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+fn record(job: &mut JobStatus, result: &AppResult<usize>) {
+    job.last_run = Some(now());
+    job.runs += 1;
+    match result {
+        Ok(removed) => {
+            job.last_removed = *removed;
+            job.last_error = None;
+        }
+        Err(e) => job.last_error = Some(e.to_string()),
+    }
+}
+
+/// Ticks every `interval` seconds, or never if it is 0.
+async fn tick(interval: &mut Option<tokio::time::Interval>) {
+    match interval {
+        Some(interval) => { interval.tick().await; }
+        None => std::future::pending().await,
+    }
+}
+
+fn interval(seconds: u64) -> Option<tokio::time::Interval> {
+    (seconds > 0).then(|| {
+        let mut interval = tokio::time::interval(Duration::from_secs(seconds));
+        // A slow run should not be followed by a burst of catch-up runs
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    })
+}
+
+impl MaintenanceService {
+    pub fn new(
+        config: Arc<Config>,
+        clipboard_service: Arc<ClipboardService>,
+        auth_service: Arc<AuthService>,
+        blob_service: Arc<BlobService>,
+    ) -> Self {
+        Self {
+            config,
+            clipboard_service,
+            auth_service,
+            blob_service,
+            status: RwLock::new(MaintenanceStatus::default()),
+        }
+    }
+
+    pub async fn status(&self) -> MaintenanceStatus {
+        self.status.read().await.clone()
+    }
+
+    pub async fn run_clipboard_cleanup(&self) -> AppResult<usize> {
+        let result = self.clipboard_service.cleanup_old_data().await;
+        self.finish("clipboard cleanup", &result, |status| &mut status.clipboard_cleanup).await;
+        result
+    }
+
+    pub async fn run_token_prune(&self) -> AppResult<usize> {
+        let result = self.auth_service.prune_revoked_tokens().await;
+        self.finish("token prune", &result, |status| &mut status.token_prune).await;
+        result
+    }
+
+    pub async fn run_blob_prune(&self) -> AppResult<usize> {
+        let result = self.blob_service.prune().await;
+        self.finish("blob prune", &result, |status| &mut status.blob_prune).await;
+        result
+    }
+
+    async fn finish<F>(&self, name: &str, result: &AppResult<usize>, job: F)
+    where
+        F: FnOnce(&mut MaintenanceStatus) -> &mut JobStatus,
+    {
+        match result {
+            Ok(removed) => tracing::debug!("Maintenance {} removed {} entries", name, removed),
+            Err(e) => tracing::error!("Maintenance {} failed: {}", name, e),
+        }
+        record(job(&mut *self.status.write().await), result);
+    }
+
+    /// Runs all jobs on their configured intervals until `shutdown` flips to
+    /// `true` or its sender is dropped. Each job runs once right away.
+    pub fn spawn(self: &Arc<Self>, mut shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+        let service = self.clone();
+        let mut cleanup = interval(self.config.maintenance.clipboard_cleanup_interval);
+        let mut prune = interval(self.config.maintenance.token_prune_interval);
+        let mut blob_prune = interval(self.config.maintenance.blob_prune_interval);
+
+        tokio::spawn(async move {
+            loop {
+                // Failures are logged and recorded in the job status
+                tokio::select! {
+                    _ = tick(&mut cleanup) => { let _ = service.run_clipboard_cleanup().await; }
+                    _ = tick(&mut prune) => { let _ = service.run_token_prune().await; }
+                    _ = tick(&mut blob_prune) => { let _ = service.run_blob_prune().await; }
+                    changed = shutdown.changed() => {
+                        if changed.is_err() || *shutdown.borrow() {
+                            break;
+                        }
+                    }
+                }
+            }
+            tracing::info!("Maintenance tasks stopped");
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ClipboardData;
+    use crate::store::{MemoryStore, Store};
+    use uuid::Uuid;
+
+    fn create_test_service(config: Config) -> (Arc<MaintenanceService>, Arc<ClipboardService>, Arc<AuthService>) {
+        let config = Arc::new(config);
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let auth_service = Arc::new(AuthService::new(config.clone(), store.clone()));
+        let blob_service = Arc::new(BlobService::new(config.clone(), store));
+        let service = Arc::new(MaintenanceService::new(
+            config,
+            clipboard_service.clone(),
+            auth_service.clone(),
+            blob_service,
+        ));
+        (service, clipboard_service, auth_service)
+    }
+
+    #[tokio::test]
+    async fn test_jobs_record_their_runs() {
+        let mut config = Config::default();
+        config.clipboard.retention_period = 0;
+        let (service, clipboard_service, auth_service) = create_test_service(config);
+
+        let user_id = Uuid::new_v4();
+        let old = ClipboardData::new("old".to_string(), Uuid::new_v4(), user_id);
+        clipboard_service.save_clipboard(old).await.unwrap();
+        auth_service.invalidate_token("not-a-jwt").await.unwrap();
+
+        assert!(service.status().await.clipboard_cleanup.last_run.is_none());
+        // `save_clipboard` stamps `received_at` with the current second
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(service.run_clipboard_cleanup().await.unwrap(), 1);
+        // Undecodable tokens are kept for the longest lifetime we issue
+        assert_eq!(service.run_token_prune().await.unwrap(), 0);
+
+        let status = service.status().await;
+        assert!(status.clipboard_cleanup.last_run.is_some());
+        assert_eq!(status.clipboard_cleanup.last_removed, 1);
+        assert_eq!(status.clipboard_cleanup.runs, 1);
+        assert_eq!(status.token_prune.runs, 1);
+        assert!(status.token_prune.last_error.is_none());
+
+        assert_eq!(service.run_blob_prune().await.unwrap(), 0);
+        assert_eq!(service.status().await.blob_prune.runs, 1);
+    }
+
+    #[tokio::test]
+    async fn test_spawned_jobs_run_and_stop_on_shutdown() {
+        let mut config = Config::default();
+        config.maintenance.clipboard_cleanup_interval = 3600;
+        config.maintenance.token_prune_interval = 0;
+        config.maintenance.blob_prune_interval = 0;
+        let (service, _, _) = create_test_service(config);
+
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let handle = service.spawn(shutdown_rx);
+
+        // The first tick fires immediately
+        tokio::time::timeout(Duration::from_secs(1), async {
+            while service.status().await.clipboard_cleanup.runs == 0 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("cleanup never ran");
+        assert_eq!(service.status().await.token_prune.runs, 0, "interval 0 disables the job");
+
+        shutdown_tx.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .expect("maintenance task did not stop")
+            .unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+    models::{MfaSettings, User},
+    store::{Store, UserStore},
+};
+use super::totp;
+
+/// Wrong codes allowed per MFA token before the user has to log in again.
+const MAX_CODE_ATTEMPTS: u32 = 5;
+
+/// Everything an authenticator app and the user need, shown once.
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+    pub recovery_codes: Vec<String>,
+}
+
+/// RFC 6238 TOTP enrollment and code checks. Login itself stays in the
+/// handlers; this service only decides whether a code is good.
+pub struct MfaService<S: ?Sized = dyn Store> {
+    config: Arc<Config>,
+    store: Arc<S>,
+    // MFA token id -> (failed attempts, token expiry). Also serializes code
+    // checks so one code cannot be spent twice by racing requests.
+    attempts: Mutex<HashMap<Uuid, (u32, u64)>>,
+}
+
+fn invalid_code() -> AppError {
+    AppError::ValidationError("Invalid two-factor code".to_string())
+}
+
+impl<S: UserStore + ?Sized> MfaService<S> {
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
+        Self {
+            config,
+            store,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> AppResult<User> {
+        self.store
+            .get_user(user_id)
+            .await?
+            .ok_or(AppError::UserNotFound(user_id))
+    }
+
+    /// Starts enrollment with a fresh secret and recovery codes. 2FA is not
+    /// enforced until [`confirm`](Self::confirm) sees a valid code; enrolling
+    /// again before that replaces the pending secret.
+    pub async fn enroll(&self, user_id: Uuid) -> AppResult<TotpEnrollment> {
+        let mut user = self.get_user(user_id).await?;
+        if user.mfa_enabled() {
+            return Err(AppError::ValidationError("Two-factor authentication is already enabled".to_string()));
+        }
+
+        let secret = totp::generate_secret();
+        let recovery_codes = totp::generate_recovery_codes();
+        let provisioning_uri = totp::provisioning_uri(&self.config.auth.totp_issuer, &user.username, &secret);
+        user.mfa = Some(MfaSettings {
+            totp_secret: secret.clone(),
+            enabled: false,
+            recovery_code_hashes: recovery_codes.iter().map(|code| totp::hash_recovery_code(code)).collect(),
+            last_used_step: 0,
+        });
+        self.store.update_user(user).await?;
+
+        Ok(TotpEnrollment {
+            secret,
+            provisioning_uri,
+            recovery_codes,
+        })
+    }
+
+    /// Turns 2FA on once the user shows their authenticator produces codes.
+    pub async fn confirm(&self, user_id: Uuid, code: &str) -> AppResult<()> {
+        let _guard = self.attempts.lock().await;
+        let mut user = self.get_user(user_id).await?;
+        if user.mfa.is_none() || user.mfa_enabled() {
+            return Err(AppError::ValidationError("No two-factor enrollment is pending".to_string()));
+        }
+        if !Self::check_totp(&mut user, code) {
+            return Err(invalid_code());
+        }
+
+        if let Some(mfa) = user.mfa.as_mut() {
+            mfa.enabled = true;
+        }
+        self.store.update_user(user).await?;
+        tracing::info!("Two-factor authentication enabled for user {}", user_id);
+        Ok(())
+    }
+
+    /// Turns 2FA off. Takes an authenticator or recovery code, so a stolen
+    /// access token alone is not enough.
+    pub async fn disable(&self, user_id: Uuid, code: &str) -> AppResult<()> {
+        let _guard = self.attempts.lock().await;
+        let mut user = self.get_user(user_id).await?;
+        if !user.mfa_enabled() {
+            return Err(AppError::ValidationError("Two-factor authentication is not enabled".to_string()));
+        }
+        if !Self::check_code(&mut user, code) {
+            return Err(invalid_code());
+        }
+
+        user.mfa = None;
+        self.store.update_user(user).await?;
+        tracing::info!("Two-factor authentication disabled for user {}", user_id);
+        Ok(())
+    }
+
+    /// Replaces all recovery codes. Needs an authenticator code: someone
+    /// down to their last recovery code should not be able to mint more.
+    pub async fn regenerate_recovery_codes(&self, user_id: Uuid, code: &str) -> AppResult<Vec<String>> {
+        let _guard = self.attempts.lock().await;
+        let mut user = self.get_user(user_id).await?;
+        if !user.mfa_enabled() {
+            return Err(AppError::ValidationError("Two-factor authentication is not enabled".to_string()));
+        }
+        if !Self::check_totp(&mut user, code) {
+            return Err(invalid_code());
+        }
+
+        let recovery_codes = totp::generate_recovery_codes();
+        if let Some(mfa) = user.mfa.as_mut() {
+            mfa.recovery_code_hashes = recovery_codes.iter().map(|code| totp::hash_recovery_code(code)).collect();
+        }
+        self.store.update_user(user).await?;
+        Ok(recovery_codes)
+    }
+
+    /// Second login step. `challenge_id` and `expires_at` come from the MFA
+    /// token; after `MAX_CODE_ATTEMPTS` wrong codes the token is useless.
+    pub async fn verify_login(&self, user_id: Uuid, challenge_id: Uuid, expires_at: u64, code: &str) -> AppResult<()> {
+        let mut attempts = self.attempts.lock().await;
+        let now = jsonwebtoken::get_current_timestamp();
+        attempts.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let (failed, _) = attempts.entry(challenge_id).or_insert((0, expires_at));
+        if *failed >= MAX_CODE_ATTEMPTS {
+            return Err(AppError::Unauthorized("Too many invalid codes, log in again".to_string()));
+        }
+
+        let mut user = self.get_user(user_id).await?;
+        if !user.mfa_enabled() || !Self::check_code(&mut user, code) {
+            *failed += 1;
+            tracing::warn!(target: "security", "Invalid two-factor code for user {} ({} failed)", user_id, failed);
+            return Err(AppError::InvalidCredentials);
+        }
+
+        attempts.remove(&challenge_id);
+        self.store.update_user(user).await?;
+        Ok(())
+    }
+
+    /// Accepts an authenticator or recovery code, spending it on `user`.
+    fn check_code(user: &mut User, code: &str) -> bool {
+        Self::check_totp(user, code) || Self::use_recovery_code(user, code)
+    }
+
+    fn check_totp(user: &mut User, code: &str) -> bool {
+        let Some(mfa) = user.mfa.as_mut() else {
+            return false;
+        };
+        let now = jsonwebtoken::get_current_timestamp();
+        match totp::verify(&mfa.totp_secret, code, now, mfa.last_used_step) {
+            Some(step) => {
+                mfa.last_used_step = step;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn use_recovery_code(user: &mut User, code: &str) -> bool {
+        let Some(mfa) = user.mfa.as_mut() else {
+            return false;
+        };
+        let hash = totp::hash_recovery_code(code);
+        let before = mfa.recovery_code_hashes.len();
+        mfa.recovery_code_hashes.retain(|stored| *stored != hash);
+        mfa.recovery_code_hashes.len() < before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    async fn create_test_service() -> (MfaService<MemoryStore>, Uuid) {
+        let store = Arc::new(MemoryStore::new());
+        let user = store
+            .insert_user(User::new("alice".to_string(), "hash".to_string()))
+            .await
+            .unwrap();
+        (MfaService::new(Arc::new(Config::default()), store), user.id)
+    }
+
+    // The code an authenticator app would show `steps` periods from now
+    fn current_code(secret: &str, steps: i64) -> String {
+        let now = jsonwebtoken::get_current_timestamp() as i64 + steps * 30;
+        totp::code_for(secret, now as u64)
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_needs_confirmation() {
+        let (service, user_id) = create_test_service().await;
+        let enrollment = service.enroll(user_id).await.unwrap();
+        assert!(enrollment.provisioning_uri.starts_with("otpauth://totp/Clipman:alice?"));
+        assert_eq!(enrollment.recovery_codes.len(), 10);
+        assert!(!service.get_user(user_id).await.unwrap().mfa_enabled());
+
+        assert!(matches!(service.confirm(user_id, "not-a-code").await, Err(AppError::ValidationError(_))));
+        service.confirm(user_id, &current_code(&enrollment.secret, 0)).await.unwrap();
+        assert!(service.get_user(user_id).await.unwrap().mfa_enabled());
+        assert!(matches!(service.enroll(user_id).await, Err(AppError::ValidationError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_login_codes_are_single_use() {
+        let (service, user_id) = create_test_service().await;
+        let enrollment = service.enroll(user_id).await.unwrap();
+        service.confirm(user_id, &current_code(&enrollment.secret, -1)).await.unwrap();
+        let expires_at = jsonwebtoken::get_current_timestamp() + 300;
+
+        // The confirmation code cannot be replayed, the next one works once
+        let code = current_code(&enrollment.secret, 0);
+        service.verify_login(user_id, Uuid::new_v4(), expires_at, &code).await.unwrap();
+        assert!(service.verify_login(user_id, Uuid::new_v4(), expires_at, &code).await.is_err());
+
+        // Recovery codes work once, in any case and spacing
+        let recovery = enrollment.recovery_codes[0].to_uppercase();
+        service.verify_login(user_id, Uuid::new_v4(), expires_at, &recovery).await.unwrap();
+        let reused = service.verify_login(user_id, Uuid::new_v4(), expires_at, &recovery).await;
+        assert!(matches!(reused, Err(AppError::InvalidCredentials)));
+    }
+
+    #[tokio::test]
+    async fn test_login_attempts_are_limited_per_token() {
+        let (service, user_id) = create_test_service().await;
+        let enrollment = service.enroll(user_id).await.unwrap();
+        service.confirm(user_id, &current_code(&enrollment.secret, -1)).await.unwrap();
+        let challenge = Uuid::new_v4();
+        let expires_at = jsonwebtoken::get_current_timestamp() + 300;
+
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let wrong = service.verify_login(user_id, challenge, expires_at, "not-a-code").await;
+            assert!(matches!(wrong, Err(AppError::InvalidCredentials)));
+        }
+        // Even the right code is refused now
+        let code = current_code(&enrollment.secret, 0);
+        let locked = service.verify_login(user_id, challenge, expires_at, &code).await;
+        assert!(matches!(locked, Err(AppError::Unauthorized(_))));
+        service.verify_login(user_id, Uuid::new_v4(), expires_at, &code).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_and_disable() {
+        let (service, user_id) = create_test_service().await;
+        let enrollment = service.enroll(user_id).await.unwrap();
+        service.confirm(user_id, &current_code(&enrollment.secret, -1)).await.unwrap();
+
+        // Regenerating needs the authenticator, not a recovery code
+        let by_recovery = service.regenerate_recovery_codes(user_id, &enrollment.recovery_codes[0]).await;
+        assert!(by_recovery.is_err());
+        let fresh = service
+            .regenerate_recovery_codes(user_id, &current_code(&enrollment.secret, 0))
+            .await
+            .unwrap();
+        assert_ne!(fresh, enrollment.recovery_codes);
+
+        // Old recovery codes are gone; new ones can turn 2FA off
+        assert!(service.disable(user_id, &enrollment.recovery_codes[1]).await.is_err());
+        service.disable(user_id, &fresh[0]).await.unwrap();
+        let user = service.get_user(user_id).await.unwrap();
+        assert!(user.mfa.is_none());
+    }
+}
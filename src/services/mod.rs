@@ -1,11 +1,25 @@
 mod user_service;
 mod auth_service;
+mod keyring;
+mod totp;
 mod device_service;
 mod websocket_service;
 mod clipboard_service;
+mod maintenance_service;
+mod mfa_service;
+mod throttle_service;
+mod password_policy;
+mod pairing_service;
+mod blob_service;
 
 pub use user_service::UserService;
-pub use auth_service::{AuthService, Claims};
+pub use auth_service::{AuthService, Claims, TokenType};
 pub use device_service::DeviceService;
 pub use websocket_service::WebSocketService;
-pub use clipboard_service::ClipboardService;
\ No newline at end of file
+pub use clipboard_service::ClipboardService;
+pub use maintenance_service::{MaintenanceService, MaintenanceStatus, JobStatus};
+pub use mfa_service::{MfaService, TotpEnrollment};
+pub use throttle_service::{ThrottleService, ThrottleKey, LoginLock};
+pub use password_policy::{PasswordPolicy, PasswordRule};
+pub use pairing_service::{PairingService, PairingCode, Pairing};
+pub use blob_service::{BlobService, MAX_CHUNK_SIZE, parse_range};
\ No newline at end of file
@@ -0,0 +1,244 @@
+use rand::{distributions::Uniform, Rng};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use crate::{
+    config::Config,
+    error::{AppError, AppResult},
+};
+
+// No 0/O, 1/I/L: codes are read off one screen and typed into another
+const CODE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+const CODE_LENGTH: usize = 8;
+
+/// A pairing code as shown on the device that asked for it.
+#[derive(Debug, Clone, Serialize)]
+pub struct PairingCode {
+    /// `XXXX-XXXX`; case and separators do not matter when redeeming.
+    pub code: String,
+    /// The same code as a URI, for rendering as a QR code.
+    pub qr_payload: String,
+    pub expires_at: u64,
+}
+
+/// Who a redeemed code pairs the new device with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pairing {
+    pub user_id: Uuid,
+    /// The device that issued the code, if it was a device-bound session.
+    pub issued_by: Option<Uuid>,
+    /// When the code would have expired, kept in case it is restored.
+    pub expires_at: u64,
+}
+
+struct PendingCode {
+    pairing: Pairing,
+    // Issue order; codes issued in the same second share `expires_at`
+    serial: u64,
+}
+
+#[derive(Default)]
+struct PairingTables {
+    // Normalized code -> pending pairing
+    codes: HashMap<String, PendingCode>,
+    // Client IP -> (wrong codes, start of the counting window)
+    failures: HashMap<Option<IpAddr>, (u32, u64)>,
+    next_serial: u64,
+}
+
+/// Short-lived, single-use pairing codes, kept in memory: a restart voids
+/// codes nobody has typed in yet.
+pub struct PairingService {
+    config: Arc<Config>,
+    tables: Mutex<PairingTables>,
+}
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+/// Uppercases and drops separators, so `abcd efgh` finds `ABCD-EFGH`.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn generate_code() -> String {
+    let alphabet = Uniform::from(0..CODE_ALPHABET.len());
+    rand::thread_rng()
+        .sample_iter(alphabet)
+        .take(CODE_LENGTH)
+        .map(|i| CODE_ALPHABET[i] as char)
+        .collect()
+}
+
+impl PairingService {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            config,
+            tables: Mutex::new(PairingTables::default()),
+        }
+    }
+
+    /// Issues a code that pairs a new device with `user_id`. Past
+    /// `max_pending_codes` the user's oldest unredeemed code stops working.
+    pub async fn issue(&self, user_id: Uuid, issued_by: Option<Uuid>) -> PairingCode {
+        let now = now();
+        let pairing_config = &self.config.pairing;
+        let mut tables = self.tables.lock().await;
+        Self::prune(&mut tables, now, pairing_config.code_expiry);
+
+        let mut pending: Vec<(String, u64)> = tables
+            .codes
+            .iter()
+            .filter(|(_, pending)| pending.pairing.user_id == user_id)
+            .map(|(code, pending)| (code.clone(), pending.serial))
+            .collect();
+        pending.sort_by_key(|(_, serial)| *serial);
+        let excess = (pending.len() + 1).saturating_sub(pairing_config.max_pending_codes.max(1));
+        for (code, _) in pending.into_iter().take(excess) {
+            tables.codes.remove(&code);
+        }
+
+        let code = loop {
+            let code = generate_code();
+            if !tables.codes.contains_key(&code) {
+                break code;
+            }
+        };
+        let expires_at = now + pairing_config.code_expiry;
+        let serial = tables.next_serial;
+        tables.next_serial += 1;
+        tables.codes.insert(
+            code.clone(),
+            PendingCode {
+                pairing: Pairing { user_id, issued_by, expires_at },
+                serial,
+            },
+        );
+
+        let code = format!("{}-{}", &code[..CODE_LENGTH / 2], &code[CODE_LENGTH / 2..]);
+        PairingCode {
+            qr_payload: format!("clipman://pair?code={}", code),
+            code,
+            expires_at,
+        }
+    }
+
+    /// Spends a code. Each client IP gets `redeem_attempts` wrong codes per
+    /// `code_expiry`, far too few to guess a live code before it expires.
+    pub async fn redeem(&self, code: &str, ip: Option<IpAddr>) -> AppResult<Pairing> {
+        let now = now();
+        let pairing_config = &self.config.pairing;
+        let window = pairing_config.code_expiry;
+        let mut tables = self.tables.lock().await;
+        Self::prune(&mut tables, now, window);
+
+        if let Some((failures, window_start)) = tables.failures.get(&ip) {
+            if *failures >= pairing_config.redeem_attempts {
+                tracing::warn!(target: "security", "Rejected pairing attempt from locked {:?}", ip);
+                return Err(AppError::TooManyAttempts(window_start + window - now));
+            }
+        }
+
+        match tables.codes.remove(&normalize(code)) {
+            Some(pending) => Ok(pending.pairing),
+            None => {
+                let (failures, _) = tables.failures.entry(ip).or_insert((0, now));
+                *failures += 1;
+                tracing::warn!(target: "security", "Invalid pairing code from {:?} ({} failed)", ip, failures);
+                Err(AppError::Unauthorized("Invalid or expired pairing code".to_string()))
+            }
+        }
+    }
+
+    /// Puts back a code whose pairing could not be completed, so a full
+    /// device list or a bad device name does not cost the user the code.
+    /// It keeps its original expiry.
+    pub async fn restore(&self, code: &str, pairing: Pairing) {
+        let mut tables = self.tables.lock().await;
+        if pairing.expires_at <= now() {
+            return;
+        }
+        let serial = tables.next_serial;
+        tables.next_serial += 1;
+        tables.codes.entry(normalize(code)).or_insert(PendingCode { pairing, serial });
+    }
+
+    fn prune(tables: &mut PairingTables, now: u64, window: u64) {
+        tables.codes.retain(|_, pending| pending.pairing.expires_at > now);
+        tables.failures.retain(|_, (_, window_start)| *window_start + window > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_service() -> PairingService {
+        let mut config = Config::default();
+        config.pairing.max_pending_codes = 2;
+        config.pairing.redeem_attempts = 3;
+        PairingService::new(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn test_codes_are_single_use() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let issuer = Some(Uuid::new_v4());
+        let code = service.issue(user_id, issuer).await;
+        assert_eq!(code.code.len(), CODE_LENGTH + 1);
+        assert_eq!(code.qr_payload, format!("clipman://pair?code={}", code.code));
+        assert!(code.expires_at > now());
+
+        // Typed sloppily, the code still works, but only once
+        let typed = code.code.to_lowercase().replace('-', " ");
+        let pairing = service.redeem(&typed, None).await.unwrap();
+        assert_eq!(pairing, Pairing { user_id, issued_by: issuer, expires_at: code.expires_at });
+        assert!(matches!(service.redeem(&code.code, None).await, Err(AppError::Unauthorized(_))));
+
+        // A restored code works once more, until its original expiry
+        service.restore(&typed, pairing.clone()).await;
+        assert_eq!(service.redeem(&code.code, None).await.unwrap(), pairing);
+        service.restore(&code.code, Pairing { expires_at: now(), ..pairing }).await;
+        assert!(service.redeem(&code.code, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_oldest_codes_make_room() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let first = service.issue(user_id, None).await;
+        let second = service.issue(user_id, None).await;
+        let other_user = service.issue(Uuid::new_v4(), None).await;
+        let third = service.issue(user_id, None).await;
+
+        assert!(service.redeem(&first.code, None).await.is_err());
+        for code in [second, third, other_user] {
+            service.redeem(&code.code, None).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_guessing_is_rate_limited_per_ip() {
+        let service = create_test_service();
+        let code = service.issue(Uuid::new_v4(), None).await;
+        let guesser: IpAddr = "203.0.113.7".parse().unwrap();
+
+        for _ in 0..3 {
+            assert!(service.redeem("AAAA-AAAA", Some(guesser)).await.is_err());
+        }
+        // Locked out, even with the right code
+        let locked = service.redeem(&code.code, Some(guesser)).await;
+        assert!(matches!(locked, Err(AppError::TooManyAttempts(retry_after)) if retry_after > 0));
+
+        // Other clients are unaffected
+        service.redeem(&code.code, Some("198.51.100.4".parse().unwrap())).await.unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+use ring::digest;
+use std::collections::HashSet;
+use crate::{
+    config::UserConfig,
+    error::{AppError, AppResult, PolicyViolation},
+};
+
+/// One check a new password must pass. Rules see the username so they can
+/// reject passwords derived from it.
+pub trait PasswordRule: Send + Sync {
+    fn check(&self, password: &str, username: &str) -> Option<PolicyViolation>;
+}
+
+/// An ordered set of rules. Every rule runs, so a rejection lists all of
+/// the problems at once instead of making the user guess one at a time.
+#[derive(Default)]
+pub struct PasswordPolicy {
+    rules: Vec<Box<dyn PasswordRule>>,
+}
+
+impl PasswordPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_rule(mut self, rule: impl PasswordRule + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// The rules configured in `UserConfig`. Reads the blocklist file, if
+    /// one is set, so this belongs at startup.
+    pub fn from_config(config: &UserConfig) -> AppResult<Self> {
+        let mut policy = Self::new()
+            .with_rule(LengthRule {
+                min: config.min_password_length,
+                max: config.max_password_length,
+            })
+            .with_rule(CharacterClassRule { required: config.password_character_classes })
+            .with_rule(StrengthRule { min_score: config.min_password_score })
+            .with_rule(UsernameRule);
+
+        if let Some(path) = &config.password_blocklist_path {
+            let contents = std::fs::read_to_string(path).map_err(|e| {
+                AppError::InternalError(format!("Failed to read password blocklist {}: {}", path, e))
+            })?;
+            let blocklist = BlocklistRule::parse(&contents);
+            tracing::info!("Loaded {} breached password hashes from {}", blocklist.len(), path);
+            policy = policy.with_rule(blocklist);
+        }
+        Ok(policy)
+    }
+
+    pub fn check(&self, password: &str, username: &str) -> AppResult<()> {
+        let violations: Vec<PolicyViolation> = self
+            .rules
+            .iter()
+            .filter_map(|rule| rule.check(password, username))
+            .collect();
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(AppError::PasswordRejected(violations)),
+        }
+    }
+}
+
+pub struct LengthRule {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl PasswordRule for LengthRule {
+    fn check(&self, password: &str, _username: &str) -> Option<PolicyViolation> {
+        let length = password.chars().count();
+        if length < self.min {
+            return Some(PolicyViolation {
+                rule: "min_length",
+                message: format!("Password must be at least {} characters", self.min),
+            });
+        }
+        if length > self.max {
+            return Some(PolicyViolation {
+                rule: "max_length",
+                message: format!("Password must be at most {} characters", self.max),
+            });
+        }
+        None
+    }
+}
+
+/// Requires characters from at least `required` of: lowercase, uppercase,
+/// digits, and everything else. 0 turns the rule off.
+pub struct CharacterClassRule {
+    pub required: usize,
+}
+
+fn character_classes(password: &str) -> [bool; 4] {
+    [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+}
+
+impl PasswordRule for CharacterClassRule {
+    fn check(&self, password: &str, _username: &str) -> Option<PolicyViolation> {
+        let present = character_classes(password).iter().filter(|present| **present).count();
+        (present < self.required).then(|| PolicyViolation {
+            rule: "character_classes",
+            message: format!(
+                "Password must mix at least {} of lowercase, uppercase, digits and symbols",
+                self.required
+            ),
+        })
+    }
+}
+
+/// Rejects passwords scoring below `min_score` on the 0-4 scale of
+/// [`strength_score`].
+pub struct StrengthRule {
+    pub min_score: u8,
+}
+
+impl PasswordRule for StrengthRule {
+    fn check(&self, password: &str, _username: &str) -> Option<PolicyViolation> {
+        let score = strength_score(password);
+        (score < self.min_score).then(|| PolicyViolation {
+            rule: "strength",
+            message: format!("Password is too guessable (strength {} of 4, need {})", score, self.min_score),
+        })
+    }
+}
+
+/// Rejects passwords containing the username, reversed or not, after
+/// undoing case, leetspeak and punctuation: `Alice`, `4l1ce!` and `ecila`
+/// all count for `alice`.
+pub struct UsernameRule;
+
+impl PasswordRule for UsernameRule {
+    fn check(&self, password: &str, username: &str) -> Option<PolicyViolation> {
+        let username: String = normalize(username).into_iter().collect();
+        // Very short names would match too many unrelated passwords
+        if username.chars().count() < 3 {
+            return None;
+        }
+        let password: String = normalize(password).into_iter().collect();
+        let reversed: String = username.chars().rev().collect();
+        (password.contains(&username) || password.contains(&reversed)).then(|| PolicyViolation {
+            rule: "username",
+            message: "Password must not contain the username".to_string(),
+        })
+    }
+}
+
+/// Known breached passwords, as SHA-1 hashes in the format of the Have I
+/// Been Pwned dumps (`HEX[:count]` per line). Only the first 64 bits of each
+/// hash are kept, which keeps large lists small in memory; lines shorter
+/// than that are skipped.
+pub struct BlocklistRule {
+    prefixes: HashSet<u64>,
+}
+
+const BLOCKLIST_PREFIX_HEX: usize = 16;
+
+impl BlocklistRule {
+    pub fn parse(contents: &str) -> Self {
+        let prefixes = contents
+            .lines()
+            .filter_map(|line| {
+                let hash = line.split(':').next()?.trim();
+                u64::from_str_radix(hash.get(..BLOCKLIST_PREFIX_HEX)?, 16).ok()
+            })
+            .collect();
+        Self { prefixes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    fn prefix(password: &str) -> u64 {
+        let hash = digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes());
+        let mut prefix = [0u8; 8];
+        prefix.copy_from_slice(&hash.as_ref()[..8]);
+        u64::from_be_bytes(prefix)
+    }
+}
+
+impl PasswordRule for BlocklistRule {
+    fn check(&self, password: &str, _username: &str) -> Option<PolicyViolation> {
+        self.prefixes.contains(&Self::prefix(password)).then(|| PolicyViolation {
+            rule: "breached",
+            message: "Password appears in a known data breach".to_string(),
+        })
+    }
+}
+
+// Strength estimation, loosely after zxcvbn: the password is covered with
+// the cheapest guessable patterns and the bits of each piece are summed.
+
+// Most common passwords and password stems, lowercase letters only since
+// they are matched after leetspeak is undone
+const COMMON_WORDS: &[&str] = &[
+    "password", "qwerty", "letmein", "welcome", "admin", "administrator", "login",
+    "dragon", "monkey", "football", "baseball", "basketball", "soccer", "hockey", "iloveyou",
+    "sunshine", "princess", "master", "shadow", "superman", "batman", "trustno", "freedom",
+    "whatever", "starwars", "pokemon", "charlie", "michael", "jennifer", "jordan", "hunter",
+    "ashley", "bailey", "buster", "secret", "summer", "winter", "spring", "autumn", "flower",
+    "cookie", "cheese", "computer", "internet", "killer", "ninja", "mustang", "access",
+    "hello", "love", "lovely", "angel", "family", "blessed", "changeme", "default",
+    "guest", "root", "test", "testing", "user", "temp", "qazwsx", "asdf",
+    "zxcv", "clipman", "clipboard", "google", "apple", "samsung", "london", "america",
+    "orange", "banana", "chocolate", "purple", "silver", "golden", "tigger", "maggie",
+    "ginger", "pepper", "matrix", "thomas", "robert", "daniel", "andrew", "joshua", "george",
+];
+
+const KEYBOARD_ROWS: &[&str] = &["`1234567890-=", "qwertyuiop[]\\", "asdfghjkl;'", "zxcvbnm,./"];
+
+/// Lowercases and maps common leetspeak substitutions back to letters.
+fn unleet(c: char) -> char {
+    match c {
+        '4' | '@' => 'a',
+        '3' => 'e',
+        '1' | '!' => 'i',
+        '0' => 'o',
+        '$' | '5' => 's',
+        '7' | '+' => 't',
+        c => c.to_ascii_lowercase(),
+    }
+}
+
+/// [`unleet`], dropping everything that is not alphanumeric.
+fn normalize(text: &str) -> Vec<char> {
+    text.chars().map(unleet).filter(char::is_ascii_alphanumeric).collect()
+}
+
+fn keyboard_position(c: char) -> Option<(usize, usize)> {
+    KEYBOARD_ROWS
+        .iter()
+        .enumerate()
+        .find_map(|(row, keys)| keys.chars().position(|key| key == c).map(|column| (row, column)))
+}
+
+fn run_length(chars: &[char], start: usize, step: impl Fn(char, char) -> bool) -> usize {
+    let mut end = start + 1;
+    while end < chars.len() && step(chars[end - 1], chars[end]) {
+        end += 1;
+    }
+    end - start
+}
+
+/// Bits an attacker needs for the cheapest pattern starting at `start`, with
+/// the number of characters it covers. `plain` is `lower` with leetspeak
+/// undone, for the dictionary.
+fn cheapest_pattern(lower: &[char], plain: &[char], start: usize, char_bits: f64) -> Option<(usize, f64)> {
+    let mut candidates: Vec<(usize, f64)> = Vec::new();
+
+    let repeat = run_length(lower, start, |a, b| a == b);
+    if repeat >= 3 {
+        candidates.push((repeat, char_bits + (repeat as f64).log2()));
+    }
+
+    let ascending = run_length(lower, start, |a, b| b as i64 - a as i64 == 1);
+    let descending = run_length(lower, start, |a, b| a as i64 - b as i64 == 1);
+    let sequence = ascending.max(descending);
+    if sequence >= 3 {
+        candidates.push((sequence, char_bits + (sequence as f64).log2() + 1.0));
+    }
+
+    let walk = run_length(lower, start, |a, b| match (keyboard_position(a), keyboard_position(b)) {
+        (Some((row_a, col_a)), Some((row_b, col_b))) => row_a == row_b && col_a.abs_diff(col_b) == 1,
+        _ => false,
+    });
+    if walk >= 4 {
+        candidates.push((walk, (47f64).log2() + (walk as f64).log2() + 1.0));
+    }
+
+    // One bit each for capitalization and leetspeak on top of the word
+    let dictionary_bits = (COMMON_WORDS.len() as f64).log2() + 2.0;
+    for word in COMMON_WORDS {
+        let word: Vec<char> = word.chars().collect();
+        if plain[start..].starts_with(&word) {
+            candidates.push((word.len(), dictionary_bits));
+        }
+    }
+
+    candidates
+        .into_iter()
+        .filter(|(length, bits)| *bits < *length as f64 * char_bits)
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.total_cmp(&a.1)))
+}
+
+/// Estimated guessing difficulty on zxcvbn's 0-4 scale: 0 falls to about a
+/// thousand guesses, 4 needs well over ten billion.
+pub fn strength_score(password: &str) -> u8 {
+    let lower: Vec<char> = password.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let plain: Vec<char> = lower.iter().copied().map(unleet).collect();
+    let pool: usize = character_classes(password)
+        .iter()
+        .zip([26, 26, 10, 33])
+        .filter(|(present, _)| **present)
+        .map(|(_, size)| size)
+        .sum();
+    let char_bits = (pool.max(1) as f64).log2();
+
+    let mut bits = 0.0;
+    let mut position = 0;
+    while position < lower.len() {
+        match cheapest_pattern(&lower, &plain, position, char_bits) {
+            Some((length, pattern_bits)) => {
+                bits += pattern_bits;
+                position += length;
+            }
+            None => {
+                bits += char_bits;
+                position += 1;
+            }
+        }
+    }
+
+    // log2 of zxcvbn's 10^3, 10^6, 10^8 and 10^10 guess thresholds
+    match bits {
+        b if b < 10.0 => 0,
+        b if b < 20.0 => 1,
+        b if b < 26.6 => 2,
+        b if b < 33.2 => 3,
+        _ => 4,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(password: &str, username: &str, policy: &PasswordPolicy) -> Vec<&'static str> {
+        match policy.check(password, username) {
+            Ok(()) => Vec::new(),
+            Err(AppError::PasswordRejected(violations)) => violations.iter().map(|v| v.rule).collect(),
+            Err(e) => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_strength_scores() {
+        for weak in ["password", "password123", "aaaaaaaaaaaa", "qwertyuiop", "abcdefgh1", "P@ssw0rd"] {
+            assert!(strength_score(weak) <= 1, "{} scored {}", weak, strength_score(weak));
+        }
+        for strong in ["correct-horse-battery", "Tr0ub4dor&3x", "vN8#qL2!mZ5w"] {
+            assert!(strength_score(strong) >= 3, "{} scored {}", strong, strength_score(strong));
+        }
+    }
+
+    #[test]
+    fn test_every_failed_rule_is_reported() {
+        let config = UserConfig {
+            password_character_classes: 3,
+            ..UserConfig::default()
+        };
+        let policy = PasswordPolicy::from_config(&config).unwrap();
+
+        assert_eq!(
+            rules("qwerty", "qwerty", &policy),
+            vec!["min_length", "character_classes", "strength", "username"]
+        );
+        assert!(rules("Plum-Harbor-Sixty-2", "alice", &policy).is_empty());
+        assert_eq!(rules(&"Ab1!".repeat(40), "alice", &policy), vec!["max_length"]);
+    }
+
+    #[test]
+    fn test_username_variants() {
+        let policy = PasswordPolicy::new().with_rule(UsernameRule);
+        for variant in ["Alice-Rocks-2024", "my4l1cepass", "ecila-backwards", "xx-A.L.I.C.E"] {
+            assert_eq!(rules(variant, "alice", &policy), vec!["username"], "{}", variant);
+        }
+        assert!(rules("Plum-Harbor-Sixty", "alice", &policy).is_empty());
+        // Two-letter names are not checked
+        assert!(rules("jo-jo-jo-jo", "jo", &policy).is_empty());
+    }
+
+    #[test]
+    fn test_blocklist_matches_sha1_prefixes() {
+        // SHA-1("Plum-Harbor-Sixty") and SHA-1("password"), in HIBP format
+        let hashed = hex(digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, b"Plum-Harbor-Sixty").as_ref());
+        let contents = format!(
+            "{}:12\n5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\nshort\n\n",
+            &hashed[..20]
+        );
+        let blocklist = BlocklistRule::parse(&contents);
+        assert_eq!(blocklist.len(), 2);
+
+        let policy = PasswordPolicy::new().with_rule(blocklist);
+        assert_eq!(rules("Plum-Harbor-Sixty", "alice", &policy), vec!["breached"]);
+        assert_eq!(rules("password", "alice", &policy), vec!["breached"]);
+        assert!(rules("Plum-Harbor-Seventy", "alice", &policy).is_empty());
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+    }
+
+    #[test]
+    fn test_missing_blocklist_fails_startup() {
+        let config = UserConfig {
+            password_blocklist_path: Some("/nonexistent/breached.txt".to_string()),
+            ..UserConfig::default()
+        };
+        assert!(matches!(PasswordPolicy::from_config(&config), Err(AppError::InternalError(_))));
+    }
+}
@@ -0,0 +1,144 @@
+use data_encoding::BASE32_NOPAD;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::{distributions::Uniform, Rng, RngCore};
+use ring::{digest, hmac};
+
+/// RFC 6238 defaults; every common authenticator app assumes them.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Steps either side of now still accepted, to absorb clock drift.
+const SKEW_STEPS: u64 = 1;
+const SECRET_BYTES: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+// No 0/o, 1/l/i: recovery codes are typed in from paper
+const RECOVERY_ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+pub(crate) fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_BYTES];
+    rand::thread_rng().fill_bytes(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+/// The `otpauth://` URI authenticator apps import, usually via a QR code.
+pub(crate) fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    let issuer = utf8_percent_encode(issuer, NON_ALPHANUMERIC);
+    let account = utf8_percent_encode(account, NON_ALPHANUMERIC);
+    format!(
+        "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}"
+    )
+}
+
+fn code_at(key: &hmac::Key, step: u64) -> u32 {
+    let tag = hmac::sign(key, &step.to_be_bytes());
+    let tag = tag.as_ref();
+    // Dynamic truncation, RFC 4226 section 5.3
+    let offset = (tag[tag.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([tag[offset], tag[offset + 1], tag[offset + 2], tag[offset + 3]]) & 0x7fff_ffff;
+    binary % 10u32.pow(DIGITS)
+}
+
+#[cfg(test)]
+pub(crate) fn code_for(secret: &str, now: u64) -> String {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).unwrap();
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret);
+    format!("{:0width$}", code_at(&key, now / STEP_SECONDS), width = DIGITS as usize)
+}
+
+/// Checks `code` against the steps around `now`. Returns the matching step
+/// if it is newer than `last_used_step`, so each code works only once.
+pub(crate) fn verify(secret: &str, code: &str, now: u64, last_used_step: u64) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, &secret);
+
+    let current = now / STEP_SECONDS;
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .filter(|step| *step > last_used_step)
+        .find(|step| code_at(&key, *step) == code)
+}
+
+/// Fresh one-time recovery codes, formatted `xxxxx-xxxxx`.
+pub(crate) fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let alphabet = Uniform::from(0..RECOVERY_ALPHABET.len());
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| RECOVERY_ALPHABET[rng.sample(alphabet)] as char)
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are stored hashed. Case and separators are ignored so
+/// `ABCDE FGHJK` matches `abcde-fghjk`.
+pub(crate) fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    digest::digest(&digest::SHA256, normalized.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1 key, truncated to six digits
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        for (time, code) in [(59, "287082"), (1111111109, "081804"), (1234567890, "005924"), (2000000000, "279037")] {
+            assert_eq!(verify(&secret, code, time, 0), Some(time / STEP_SECONDS), "time {}", time);
+        }
+    }
+
+    #[test]
+    fn test_skew_and_replay() {
+        let secret = BASE32_NOPAD.encode(RFC_SECRET);
+        let step = 1111111109 / STEP_SECONDS;
+        // One step of drift either way is tolerated, two is not
+        assert!(verify(&secret, "081804", 1111111109 + STEP_SECONDS, 0).is_some());
+        assert!(verify(&secret, "081804", 1111111109 + 2 * STEP_SECONDS, 0).is_none());
+        // A code is not accepted again once its step was used
+        assert!(verify(&secret, "081804", 1111111109, step).is_none());
+        assert!(verify(&secret, "81804", 1111111109, 0).is_none());
+        assert!(verify(&secret, "abcdef", 1111111109, 0).is_none());
+    }
+
+    #[test]
+    fn test_provisioning_uri_escapes_labels() {
+        let uri = provisioning_uri("Clipman", "alice smith", "JBSWY3DPEHPK3PXP");
+        assert_eq!(
+            uri,
+            "otpauth://totp/Clipman:alice%20smith?secret=JBSWY3DPEHPK3PXP&issuer=Clipman&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+
+    #[test]
+    fn test_recovery_codes_are_unique_and_normalized() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+
+        let code = &codes[0];
+        assert_eq!(hash_recovery_code(code), hash_recovery_code(&code.to_uppercase().replace('-', " ")));
+        assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+    }
+}
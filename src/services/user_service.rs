@@ -1,177 +1,290 @@
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use std::collections::HashMap;
+use tokio::sync::OnceCell;
 use uuid::Uuid;
 use crate::error::{AppError, AppResult};
 use crate::config::Config;
 use argon2::{
     password_hash::{SaltString, PasswordHasher, PasswordVerifier},
-    Argon2, PasswordHash
+    Algorithm, Argon2, Params, PasswordHash, Version,
 };
-use serde::{Serialize, Deserialize};
 use crate::models::User;
 use crate::models::UserResponse;
-pub struct UserService {
+use crate::store::{Store, UserStore};
+use super::password_policy::PasswordPolicy;
+
+pub struct UserService<S: ?Sized = dyn Store> {
     config: Arc<Config>,
-    users: Arc<RwLock<HashMap<Uuid, User>>>,
-    usernames: Arc<RwLock<HashMap<String, Uuid>>>,  // For username lookups
+    store: Arc<S>,
+    policy: PasswordPolicy,
+    /// Checked against when a login names no existing user.
+    dummy_hash: OnceCell<String>,
 }
 
-impl UserService {
-    pub fn new(config: Arc<Config>) -> Self {
-        Self {
-            config,
-            users: Arc::new(RwLock::new(HashMap::new())),
-            usernames: Arc::new(RwLock::new(HashMap::new())),
+impl<S: UserStore + ?Sized> UserService<S> {
+    pub fn new(config: Arc<Config>, store: Arc<S>) -> Self {
+        let policy = PasswordPolicy::from_config(&config.user).expect("Failed to load password policy");
+        Self::with_policy(config, store, policy)
+    }
+
+    pub fn with_policy(config: Arc<Config>, store: Arc<S>, policy: PasswordPolicy) -> Self {
+        Self { config, store, policy, dummy_hash: OnceCell::new() }
+    }
+
+    /// Argon2id with the cost from `UserConfig`: `password_rounds` passes
+    /// over `memory_size` KiB.
+    fn hasher(&self) -> AppResult<Argon2<'static>> {
+        let params = Params::new(
+            self.config.user.memory_size,
+            self.config.user.password_rounds,
+            Params::DEFAULT_P_COST,
+            None,
+        )
+        .map_err(|e| AppError::InternalError(format!("Invalid password hashing parameters: {}", e)))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Runs on the blocking pool: at the configured cost a hash takes long
+    /// enough to stall every other task on an async worker.
+    async fn hash_password(&self, password: &str) -> AppResult<String> {
+        let hasher = self.hasher()?;
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || {
+            let salt = SaltString::generate(&mut rand::thread_rng());
+            hasher
+                .hash_password(password.as_bytes(), &salt)
+                .map(|hash| hash.to_string())
+                .map_err(|e| AppError::InternalError(format!("Password hashing failed: {}", e)))
+        })
+        .await
+        .map_err(|e| AppError::InternalError(format!("Password hashing task failed: {}", e)))?
+    }
+
+    /// Checks `password` against `hash` on the blocking pool, using the
+    /// parameters recorded in the hash itself.
+    async fn check_password(&self, hash: &str, password: &str) -> AppResult<bool> {
+        let hasher = self.hasher()?;
+        let (hash, password) = (hash.to_string(), password.to_string());
+        tokio::task::spawn_blocking(move || {
+            let parsed_hash = PasswordHash::new(&hash)
+                .map_err(|e| AppError::InternalError(format!("Invalid hash format: {}", e)))?;
+            Ok(hasher.verify_password(password.as_bytes(), &parsed_hash).is_ok())
+        })
+        .await
+        .map_err(|e| AppError::InternalError(format!("Password verification task failed: {}", e)))?
+    }
+
+    /// Whether `hash` was made with a different algorithm or cost than we
+    /// would use today.
+    fn needs_rehash(&self, hash: &PasswordHash) -> bool {
+        let current = &self.config.user;
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != current.memory_size
+            || params.t_cost() != current.password_rounds
+            || params.p_cost() != Params::DEFAULT_P_COST
+    }
+
+    fn validate_username(&self, username: &str) -> AppResult<()> {
+        if username.trim().is_empty() || username.trim() != username {
+            return Err(AppError::ValidationError(
+                "Username must not be empty or have surrounding whitespace".to_string(),
+            ));
         }
+        if username.chars().count() > self.config.user.max_username_length {
+            return Err(AppError::ValidationError(format!(
+                "Username must be at most {} characters",
+                self.config.user.max_username_length
+            )));
+        }
+        Ok(())
     }
 
     pub async fn register_user(&self, username: String, password: String) -> AppResult<User> {
-        // Validate password length
-        if password.len() < self.config.user.min_password_length {
-            return Err(AppError::ValidationError("Password too short".to_string()));
-        }
+        self.validate_username(&username)?;
+        self.policy.check(&password, &username)?;
 
         // Check if username already exists
-        {
-            let usernames = self.usernames.read().await;
-            if usernames.contains_key(&username) {
-                return Err(AppError::UserAlreadyExists(username));
-            }
+        if self.store.get_user_by_username(&username).await?.is_some() {
+            return Err(AppError::UserAlreadyExists(username));
         }
 
-        // Hash password
-        let salt = SaltString::generate(&mut rand::thread_rng());
-        let argon2 = Argon2::default();
-        let password_hash = argon2
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|e| AppError::InternalError(format!("Password hashing failed: {}", e)))?
-            .to_string();
+        let password_hash = self.hash_password(&password).await?;
 
         // Create user
         let user = User {
             id: Uuid::new_v4(),
-            username: username.clone(),
+            username,
             password_hash,
+            mfa: None,
             created_at: None,
             updated_at: None,
         };
 
-        // Store user
-        {
-            let mut users = self.users.write().await;
-            let mut usernames = self.usernames.write().await;
-            users.insert(user.id, user.clone());
-            usernames.insert(username, user.id);
-        }
-
-        Ok(user)
+        // Store user; the store rejects a username taken since the check above
+        self.store.insert_user(user).await
     }
 
     pub async fn list_users(&self) -> AppResult<Vec<UserResponse>> {
-        let users = self.users.read().await;
-        let mut user_list: Vec<UserResponse> = users
-            .values()
-            .cloned()
-            .map(UserResponse::from)
-            .collect();
-        
-        // Sort by username
-        user_list.sort_by(|a, b| a.username.cmp(&b.username));
-        Ok(user_list)
+        // Sorted by username
+        let users = self.store.list_users().await?;
+        Ok(users.into_iter().map(UserResponse::from).collect())
     }
 
-    pub async fn list_users_paginated(&self, page: u32, limit: u32) -> Vec<UserResponse> {
-        let users = self.users.read().await;
-
-        // Convert HashMap values to a vector
-        let mut user_list: Vec<UserResponse> = users
-            .values()
-            .cloned()
-            .map(UserResponse::from)
-            .collect();
-
-        // Sort by username
-        user_list.sort_by(|a, b| a.username.cmp(&b.username));
+    pub async fn list_users_paginated(&self, page: u32, limit: u32) -> AppResult<Vec<UserResponse>> {
+        let users = self.store.list_users().await?;
 
         // Apply pagination
-        let start = ((page - 1) * limit) as usize;
-        let end = (start + limit as usize).min(user_list.len());
-
-        user_list[start..end].to_vec()
+        let start = (page.max(1) - 1) as usize * limit as usize;
+        Ok(users
+            .into_iter()
+            .skip(start)
+            .take(limit as usize)
+            .map(UserResponse::from)
+            .collect())
     }
 
    
-    pub async fn user_count(&self) -> usize {
-        let users = self.users.read().await;
-        users.len()
+    pub async fn user_count(&self) -> AppResult<usize> {
+        self.store.count_users().await
     }
     
 
     pub async fn get_user_by_username(&self, username: &str) -> AppResult<User> {
-        let usernames = self.usernames.read().await;
-        let users = self.users.read().await;
-        
-        let user_id = usernames
-            .get(username)
-            .ok_or_else(|| AppError::ValidationError(format!("User {} not found", username)))?;
-        
-        users
-            .get(user_id)
-            .cloned()
-            .ok_or_else(|| AppError::UserNotFound(*user_id))
+        self.store
+            .get_user_by_username(username)
+            .await?
+            .ok_or_else(|| AppError::ValidationError(format!("User {} not found", username)))
+    }
+
+    /// Looks up `username` and checks `password`. An unknown username costs
+    /// the same Argon2 run as a wrong password, against a dummy hash made
+    /// with the current parameters, so response times do not reveal which
+    /// usernames exist.
+    pub async fn authenticate(&self, username: &str, password: &str) -> AppResult<Option<User>> {
+        match self.store.get_user_by_username(username).await? {
+            Some(user) => Ok(self.verify_password(&user, password).await?.then_some(user)),
+            None => {
+                let dummy_hash = self
+                    .dummy_hash
+                    .get_or_try_init(|| self.hash_password("not the password of any user"))
+                    .await?;
+                self.check_password(dummy_hash, password).await?;
+                Ok(None)
+            }
+        }
     }
 
+    /// Checks `password` against the user's hash. A correct password stored
+    /// under outdated parameters is rehashed with the current ones, so the
+    /// cost can be raised without forcing resets.
     pub async fn verify_password(&self, user: &User, password: &str) -> AppResult<bool> {
+        if !self.check_password(&user.password_hash, password).await? {
+            return Ok(false);
+        }
+
         let parsed_hash = PasswordHash::new(&user.password_hash)
             .map_err(|e| AppError::InternalError(format!("Invalid hash format: {}", e)))?;
-        
-        Ok(Argon2::default()
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok())
-    }
-
-    pub async fn get_user_by_id(&self, id: Uuid) -> AppResult<User> {
-        // TODO add storage logic later
-        Err(AppError::UserNotFound(id))
+        if self.needs_rehash(&parsed_hash) {
+            // The login already succeeded; a failed upgrade is retried next time
+            if let Err(e) = self.rehash_password(user, password).await {
+                tracing::warn!("Failed to rehash password for user {}: {}", user.id, e);
+            }
+        }
+        Ok(true)
     }
 
-    // pub async fn get_user_by_username(&self, username: &str) -> AppResult<User> {
-    //     // TODO add storage logic later
-    //     Err(AppError::ValidationError(format!("User {} not found", username)))
-    // }
+    async fn rehash_password(&self, user: &User, password: &str) -> AppResult<()> {
+        let password_hash = self.hash_password(password).await?;
+        // Re-read so changes made since `user` was loaded are not overwritten,
+        // and skip if the password itself changed in the meantime
+        let Some(mut current) = self.store.get_user(user.id).await? else {
+            return Ok(());
+        };
+        if current.password_hash != user.password_hash {
+            return Ok(());
+        }
 
-    // pub async fn update_user(&self, id: Uuid, new_username: Option<String>) -> AppResult<User> {
-    pub async fn update_user(&self, id: Uuid) -> AppResult<User> {
+        current.password_hash = password_hash;
+        self.store.update_user(current).await?;
+        tracing::info!("Upgraded password hash parameters for user {}", user.id);
+        Ok(())
+    }
 
-        // TODO add storage logic 
-        Err(AppError::UserNotFound(id))
+    pub async fn get_user_by_id(&self, id: Uuid) -> AppResult<User> {
+        self.store
+            .get_user(id)
+            .await?
+            .ok_or(AppError::UserNotFound(id))
     }
 
-    // pub async fn update_password(&self, id: Uuid, old_password: &str, new_password: &str) -> AppResult<()> {
-    pub async fn update_password(&self, id: Uuid, old_password: &str) -> AppResult<()> {
+    /// Renames the user. The store keeps the username index in step and
+    /// rejects names that are already taken.
+    pub async fn update_user(&self, id: Uuid, new_username: Option<String>) -> AppResult<User> {
+        let mut user = self.get_user_by_id(id).await?;
+        if let Some(username) = new_username {
+            if username != user.username {
+                self.validate_username(&username)?;
+                tracing::info!("Renaming user {} from {} to {}", id, user.username, username);
+                user.username = username;
+                user = self.store.update_user(user).await?;
+            }
+        }
+        Ok(user)
+    }
 
-        let user = self.get_user_by_id(id).await?;
-        
-        // Verify old password
-        self.verify_password(&user, old_password).await?;
-        
-        // Generate new hash
-        // let salt = SaltString::generate(&mut rand::thread_rng());
-        // let argon2 = Argon2::default();
-        
-        // let new_hash = argon2
-        //     .hash_password(new_password.as_bytes(), &salt)
-        //     .map_err(|e| AppError::InternalError(format!("Password hashing failed: {}", e)))?
-        //     .to_string();
+    /// Changes the password after checking the current one. The new one
+    /// has to pass the same policy as at registration.
+    pub async fn update_password(&self, id: Uuid, old_password: &str, new_password: &str) -> AppResult<()> {
+        let mut user = self.get_user_by_id(id).await?;
+        if !self.verify_password(&user, old_password).await? {
+            return Err(AppError::InvalidCredentials);
+        }
+        self.policy.check(new_password, &user.username)?;
 
-        // TODO add storage update logic
+        // verify_password may have rehashed; work from the stored user
+        user = self.get_user_by_id(id).await?;
+        user.password_hash = self.hash_password(new_password).await?;
+        self.store.update_user(user).await?;
+        tracing::info!(target: "security", "Password changed for user {}", id);
         Ok(())
     }
+}
 
+impl<S: Store + ?Sized> UserService<S> {
+    /// Deletes the user with everything they own. Sessions go first so the
+    /// user's tokens stop working before anything else disappears, and the
+    /// account itself goes last so a failure part way can be retried.
+    /// Open sockets are the caller's to close.
     pub async fn delete_user(&self, id: Uuid) -> AppResult<()> {
-        // We'll add storage logic later
-        Err(AppError::UserNotFound(id))
+        self.get_user_by_id(id).await?;
+
+        for family in self.store.list_user_token_families(id).await? {
+            if !family.revoked {
+                self.store.revoke_token_family(family.id).await?;
+            }
+        }
+        let clips = self.store.delete_user_clips(id).await?;
+        let blobs = self.store.delete_user_blobs(id).await?;
+        let devices = self.store.list_user_devices(id).await?;
+        for device in &devices {
+            self.store.delete_device(device.id).await?;
+        }
+        if !self.store.delete_user(id).await? {
+            return Err(AppError::UserNotFound(id));
+        }
+
+        tracing::info!(
+            target: "security",
+            "Deleted user {} with {} devices, {} clips and {} blobs",
+            id,
+            devices.len(),
+            clips,
+            blobs
+        );
+        Ok(())
     }
 }
 
@@ -180,6 +293,20 @@ impl UserService {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::{ClipboardData, Device, TokenFamily};
+    use crate::models::Blob;
+    use crate::store::{BlobStore, ClipStore, DeviceStore, MemoryStore, TokenStore};
+
+    fn create_test_service() -> UserService<MemoryStore> {
+        UserService::new(Arc::new(Config::default()), Arc::new(MemoryStore::new()))
+    }
+
+    fn service_with_cost(memory_size: u32, password_rounds: u32, store: &Arc<MemoryStore>) -> UserService<MemoryStore> {
+        let mut config = Config::default();
+        config.user.memory_size = memory_size;
+        config.user.password_rounds = password_rounds;
+        UserService::new(Arc::new(config), store.clone())
+    }
 
     #[test]
     fn test_user_serialization() {
@@ -196,4 +323,202 @@ mod tests {
         assert!(json.contains("id"));
         assert!(!json.contains("password_hash"));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_register_and_lookup_user() {
+        let service = create_test_service();
+        let user = service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+
+        let by_id = service.get_user_by_id(user.id).await.unwrap();
+        assert_eq!(by_id.username, "alice");
+        let by_name = service.get_user_by_username("alice").await.unwrap();
+        assert_eq!(by_name.id, user.id);
+        assert!(service.verify_password(&by_name, "Plum-Harbor-Sixty").await.unwrap());
+
+        let duplicate = service
+            .register_user("alice".to_string(), "Quiet-Lantern-42".to_string())
+            .await;
+        assert!(matches!(duplicate, Err(AppError::UserAlreadyExists(_))));
+    }
+
+    #[tokio::test]
+    async fn test_list_users_paginated() {
+        let service = create_test_service();
+        for name in ["carol", "alice", "bob"] {
+            service
+                .register_user(name.to_string(), "Plum-Harbor-Sixty".to_string())
+                .await
+                .unwrap();
+        }
+
+        let first_page = service.list_users_paginated(1, 2).await.unwrap();
+        let names: Vec<String> = first_page.into_iter().map(|u| u.username).collect();
+        assert_eq!(names, vec!["alice", "bob"]);
+
+        let second_page = service.list_users_paginated(2, 2).await.unwrap();
+        assert_eq!(second_page.len(), 1);
+        assert!(service.list_users_paginated(5, 2).await.unwrap().is_empty());
+        assert_eq!(service.user_count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_register_rejects_policy_violations() {
+        let service = create_test_service();
+        let rejected = service.register_user("asdf".to_string(), "asdf1".to_string()).await;
+        let Err(AppError::PasswordRejected(violations)) = rejected else {
+            panic!("expected a policy rejection, got {:?}", rejected);
+        };
+        let rules: Vec<&str> = violations.iter().map(|v| v.rule).collect();
+        assert_eq!(rules, vec!["min_length", "strength", "username"]);
+        assert!(service.get_user_by_username("asdf").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_update_password() {
+        let store = Arc::new(MemoryStore::new());
+        let service = service_with_cost(1024, 1, &store);
+        let user = service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+
+        let wrong_old = service.update_password(user.id, "wrong-password", "Quiet-Lantern-42").await;
+        assert!(matches!(wrong_old, Err(AppError::InvalidCredentials)));
+        let weak_new = service.update_password(user.id, "Plum-Harbor-Sixty", "password").await;
+        assert!(matches!(weak_new, Err(AppError::PasswordRejected(_))));
+
+        service
+            .update_password(user.id, "Plum-Harbor-Sixty", "Quiet-Lantern-42")
+            .await
+            .unwrap();
+        let updated = service.get_user_by_id(user.id).await.unwrap();
+        assert!(service.verify_password(&updated, "Quiet-Lantern-42").await.unwrap());
+        assert!(!service.verify_password(&updated, "Plum-Harbor-Sixty").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rename_user() {
+        let store = Arc::new(MemoryStore::new());
+        let service = service_with_cost(1024, 1, &store);
+        let alice = service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+        service
+            .register_user("bob".to_string(), "Quiet-Lantern-42".to_string())
+            .await
+            .unwrap();
+
+        let taken = service.update_user(alice.id, Some("bob".to_string())).await;
+        assert!(matches!(taken, Err(AppError::UserAlreadyExists(_))));
+        let blank = service.update_user(alice.id, Some(" ".to_string())).await;
+        assert!(matches!(blank, Err(AppError::ValidationError(_))));
+        let long = service.update_user(alice.id, Some("a".repeat(33))).await;
+        assert!(matches!(long, Err(AppError::ValidationError(_))));
+
+        let renamed = service.update_user(alice.id, Some("alicia".to_string())).await.unwrap();
+        assert_eq!(renamed.username, "alicia");
+        assert_eq!(service.get_user_by_username("alicia").await.unwrap().id, alice.id);
+        assert!(service.get_user_by_username("alice").await.is_err());
+        // Nothing to change is not an error
+        assert_eq!(service.update_user(alice.id, None).await.unwrap().username, "alicia");
+        assert!(matches!(service.update_user(Uuid::new_v4(), None).await, Err(AppError::UserNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_delete_user_cascades() {
+        let store = Arc::new(MemoryStore::new());
+        let service = service_with_cost(1024, 1, &store);
+        let alice = service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+        let bob = service
+            .register_user("bob".to_string(), "Quiet-Lantern-42".to_string())
+            .await
+            .unwrap();
+
+        for user_id in [alice.id, bob.id] {
+            let device = store.insert_device(Device::new("laptop".to_string(), user_id)).await.unwrap();
+            store.insert_clip(ClipboardData::new("hello".to_string(), device.id, user_id)).await.unwrap();
+            store.insert_blob(Blob::new(user_id, 1, 1)).await.unwrap();
+            store
+                .insert_token_family(TokenFamily::new(user_id, Some(device.id), Uuid::new_v4(), u64::MAX))
+                .await
+                .unwrap();
+        }
+
+        service.delete_user(alice.id).await.unwrap();
+        assert!(matches!(service.get_user_by_id(alice.id).await, Err(AppError::UserNotFound(_))));
+        assert!(service.get_user_by_username("alice").await.is_err());
+        assert!(store.list_user_devices(alice.id).await.unwrap().is_empty());
+        assert!(store.list_user_clips(alice.id).await.unwrap().is_empty());
+        assert_eq!(store.delete_user_blobs(alice.id).await.unwrap(), 0);
+        let families = store.list_user_token_families(alice.id).await.unwrap();
+        assert!(families.iter().all(|family| family.revoked));
+
+        // Bob is untouched
+        assert_eq!(store.list_user_devices(bob.id).await.unwrap().len(), 1);
+        assert_eq!(store.list_user_clips(bob.id).await.unwrap().len(), 1);
+        assert_eq!(store.list_blobs_created_before(u64::MAX).await.unwrap().len(), 1);
+        assert!(!store.list_user_token_families(bob.id).await.unwrap()[0].revoked);
+
+        assert!(matches!(service.delete_user(alice.id).await, Err(AppError::UserNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_hashes_use_configured_cost() {
+        let store = Arc::new(MemoryStore::new());
+        let service = service_with_cost(4096, 2, &store);
+        let user = service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+        assert!(user.password_hash.starts_with("$argon2id$v=19$m=4096,t=2,p=1$"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_usernames_still_run_argon2() {
+        let store = Arc::new(MemoryStore::new());
+        let service = service_with_cost(4096, 2, &store);
+        service
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+
+        assert!(service.authenticate("alice", "Plum-Harbor-Sixty").await.unwrap().is_some());
+        assert!(service.authenticate("alice", "wrong-password").await.unwrap().is_none());
+        assert!(service.dummy_hash.get().is_none());
+
+        assert!(service.authenticate("mallory", "Plum-Harbor-Sixty").await.unwrap().is_none());
+        let dummy_hash = service.dummy_hash.get().expect("unknown users are checked against a dummy hash");
+        assert!(dummy_hash.starts_with("$argon2id$v=19$m=4096,t=2,p=1$"));
+    }
+
+    #[tokio::test]
+    async fn test_outdated_hash_is_upgraded_on_login() {
+        let store = Arc::new(MemoryStore::new());
+        let old = service_with_cost(1024, 1, &store);
+        let user = old
+            .register_user("alice".to_string(), "Plum-Harbor-Sixty".to_string())
+            .await
+            .unwrap();
+
+        let current = service_with_cost(2048, 2, &store);
+        // Wrong passwords never trigger a rehash
+        assert!(!current.verify_password(&user, "wrong-password").await.unwrap());
+        assert_eq!(current.get_user_by_id(user.id).await.unwrap().password_hash, user.password_hash);
+
+        assert!(current.verify_password(&user, "Plum-Harbor-Sixty").await.unwrap());
+        let upgraded = current.get_user_by_id(user.id).await.unwrap();
+        assert!(upgraded.password_hash.starts_with("$argon2id$v=19$m=2048,t=2,p=1$"));
+        assert!(current.verify_password(&upgraded, "Plum-Harbor-Sixty").await.unwrap());
+
+        // Up-to-date hashes are left alone
+        assert!(current.verify_password(&upgraded, "Plum-Harbor-Sixty").await.unwrap());
+        assert_eq!(current.get_user_by_id(user.id).await.unwrap().password_hash, upgraded.password_hash);
+    }
+}
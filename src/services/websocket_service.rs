@@ -1,46 +1,1077 @@
-use axum::extract::ws::{WebSocket, Message};
-use std::sync::Arc;
-use tokio::sync::broadcast;
+use axum::extract::ws::{close_code, CloseFrame, WebSocket, Message};
+use futures::{SinkExt, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Notify, RwLock};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
 use crate::{
     config::Config,
     error::{AppError, AppResult},
-    models::ClipboardData,
+    models::{
+        ClipboardData, Subscription,
+        protocol::{ClientMessage, Envelope, ErrorCode, ServerMessage, PROTOCOL_VERSION},
+    },
 };
+use super::{ClipboardService, DeviceService};
+
+type Outbound = Envelope<ServerMessage>;
+
+struct Connection {
+    id: Uuid,
+    device_id: Option<Uuid>,
+    session_id: Option<Uuid>,
+    subscription: Subscription,
+    tx: mpsc::Sender<Outbound>,
+    // Signalled to make the socket send a close frame and hang up
+    close: Arc<Notify>,
+}
 
 pub struct WebSocketService {
     config: Arc<Config>,
-    tx: broadcast::Sender<ClipboardData>,
+    clipboard_service: Arc<ClipboardService>,
+    device_service: Arc<DeviceService>,
+    // user_id -> that user's live sockets
+    connections: RwLock<HashMap<Uuid, Vec<Connection>>>,
+    // device_id -> highest seq the device acknowledged. Kept in memory only;
+    // after a restart clients resume from the seq they track themselves.
+    acked: RwLock<HashMap<Uuid, u64>>,
+    // device_id -> formats it said hello with; devices not listed get all.
+    // Dropped when the device's last socket closes, so every connection
+    // starts from its own hello.
+    accepted: RwLock<HashMap<Uuid, Vec<String>>>,
+}
+
+fn now() -> u64 {
+    jsonwebtoken::get_current_timestamp()
+}
+
+fn is_online(connections: &[Connection], device_id: Uuid) -> bool {
+    connections.iter().any(|conn| conn.device_id == Some(device_id))
+}
+
+/// Whether a socket for `device_id` gets `data`. Sockets without a device
+/// cannot be targeted, so they only get clips addressed to everyone.
+fn receives(data: &ClipboardData, device_id: Option<Uuid>, subscription: &Subscription) -> bool {
+    match device_id {
+        Some(device_id) => data.is_visible_to(device_id, subscription),
+        None => data.targets.is_empty(),
+    }
+}
+
+/// Tells the user's other devices that `device_id` came online or went
+/// offline. Best effort: a full queue just misses the event.
+fn announce_presence(connections: &[Connection], device_id: Uuid, online: bool) {
+    let last_seen = now();
+    for conn in connections.iter().filter(|conn| conn.device_id != Some(device_id)) {
+        let _ = conn.tx.try_send(Envelope::new(ServerMessage::Presence { device_id, online, last_seen }));
+    }
 }
 
 impl WebSocketService {
-    pub fn new(config: Arc<Config>) -> Self {
-        let (tx, _) = broadcast::channel(config.websocket.channel_capacity);
-        Self { config, tx }
+    pub fn new(config: Arc<Config>, clipboard_service: Arc<ClipboardService>, device_service: Arc<DeviceService>) -> Self {
+        Self {
+            config,
+            clipboard_service,
+            device_service,
+            connections: RwLock::new(HashMap::new()),
+            acked: RwLock::new(HashMap::new()),
+            accepted: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Registers a live connection and returns its ID and outbound queue.
+    pub async fn register(
+        &self,
+        user_id: Uuid,
+        device_id: Option<Uuid>,
+        session_id: Option<Uuid>,
+    ) -> AppResult<(Uuid, mpsc::Receiver<Outbound>)> {
+        let (id, _, rx, _) = self.attach(user_id, device_id, session_id).await?;
+        Ok((id, rx))
+    }
+
+    async fn attach(
+        &self,
+        user_id: Uuid,
+        device_id: Option<Uuid>,
+        session_id: Option<Uuid>,
+    ) -> AppResult<(Uuid, mpsc::Sender<Outbound>, mpsc::Receiver<Outbound>, Arc<Notify>)> {
+        let subscription = self.subscription_of(device_id).await?;
+        let (tx, rx) = mpsc::channel(self.config.websocket.channel_capacity);
+        let id = Uuid::new_v4();
+        let close = Arc::new(Notify::new());
+
+        {
+            let mut connections = self.connections.write().await;
+            let user_connections = connections.entry(user_id).or_default();
+            let came_online = device_id.filter(|device_id| !is_online(user_connections, *device_id));
+            user_connections.push(Connection {
+                id,
+                device_id,
+                session_id,
+                subscription,
+                tx: tx.clone(),
+                close: close.clone(),
+            });
+            if let Some(device_id) = came_online {
+                announce_presence(user_connections, device_id, true);
+            }
+        }
+        if let Some(device_id) = device_id {
+            self.touch_device(device_id).await;
+        }
+
+        Ok((id, tx, rx, close))
+    }
+
+    pub async fn unregister(&self, user_id: Uuid, connection_id: Uuid) {
+        let device_id = {
+            let mut connections = self.connections.write().await;
+            let Some(user_connections) = connections.get_mut(&user_id) else {
+                return;
+            };
+            let Some(index) = user_connections.iter().position(|conn| conn.id == connection_id) else {
+                return;
+            };
+            let device_id = user_connections.remove(index).device_id;
+            if let Some(device_id) = device_id.filter(|device_id| !is_online(user_connections, *device_id)) {
+                announce_presence(user_connections, device_id, false);
+                self.accepted.write().await.remove(&device_id);
+            }
+            if user_connections.is_empty() {
+                connections.remove(&user_id);
+            }
+            device_id
+        };
+
+        if let Some(device_id) = device_id {
+            self.touch_device(device_id).await;
+        }
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<ClipboardData> {
-        self.tx.subscribe()
+    /// The stored subscription of `device_id`; everything for sockets
+    /// without a device, or whose device has been removed. Other failures
+    /// are returned rather than widening what the socket receives.
+    async fn subscription_of(&self, device_id: Option<Uuid>) -> AppResult<Subscription> {
+        let Some(device_id) = device_id else {
+            return Ok(Subscription::All);
+        };
+        match self.device_service.get_device(device_id).await {
+            Ok(device) => Ok(device.subscription),
+            Err(AppError::DeviceNotFound(_)) => Ok(Subscription::All),
+            Err(e) => Err(e),
+        }
     }
 
-    pub fn broadcast(&self, data: ClipboardData) -> AppResult<()> {
-        self.tx.send(data)
-            .map_err(|e| AppError::BroadcastError(e.to_string()))?;
-        Ok(())
+    /// Switches the live sockets of `device_id` to `subscription`. Callers
+    /// persist it through `DeviceService::set_subscription` first.
+    pub async fn apply_subscription(&self, user_id: Uuid, device_id: Uuid, subscription: &Subscription) {
+        let mut connections = self.connections.write().await;
+        let Some(user_connections) = connections.get_mut(&user_id) else {
+            return;
+        };
+        for conn in user_connections.iter_mut().filter(|conn| conn.device_id == Some(device_id)) {
+            conn.subscription = subscription.clone();
+        }
     }
 
-    pub async fn handle_connection(&self, mut socket: WebSocket) {//TODO make socket borrowable without mut
-        let mut rx = self.subscribe();
+    /// Strips the formats `device_id` did not ask for from outgoing clips.
+    async fn retain_accepted(&self, device_id: Option<Uuid>, clips: &mut [ClipboardData]) {
+        let Some(device_id) = device_id else { return };
+        if let Some(accept) = self.accepted.read().await.get(&device_id) {
+            for clip in clips {
+                clip.retain_formats(accept);
+            }
+        }
+    }
+
+    /// Records that the device was just seen. The device may have been
+    /// removed while connected, which is not worth more than a debug line.
+    async fn touch_device(&self, device_id: Uuid) {
+        if let Err(e) = self.device_service.update_device_status(device_id).await {
+            tracing::debug!("Could not update last seen of device {}: {}", device_id, e);
+        }
+    }
+
+    /// The user's devices that have at least one live socket.
+    pub async fn online_devices(&self, user_id: Uuid) -> HashSet<Uuid> {
+        self.connections
+            .read()
+            .await
+            .get(&user_id)
+            .map(|connections| connections.iter().filter_map(|conn| conn.device_id).collect())
+            .unwrap_or_default()
+    }
+
+    /// Closes every socket opened with tokens from `session_id`. Returns the
+    /// number of connections closed.
+    pub async fn disconnect_session(&self, user_id: Uuid, session_id: Uuid) -> usize {
+        self.disconnect_where(user_id, |conn| conn.session_id == Some(session_id)).await
+    }
+
+    /// Closes the sockets a removed device still has open.
+    pub async fn disconnect_device(&self, user_id: Uuid, device_id: Uuid) -> usize {
+        let closed = self.disconnect_where(user_id, |conn| conn.device_id == Some(device_id)).await;
+        self.accepted.write().await.remove(&device_id);
+        closed
+    }
+
+    /// Closes the user's sockets from every session but `keep`; with `None`
+    /// that is all of them.
+    pub async fn disconnect_other_sessions(&self, user_id: Uuid, keep: Option<Uuid>) -> usize {
+        self.disconnect_where(user_id, |conn| keep.is_none() || conn.session_id != keep).await
+    }
+
+    async fn disconnect_where<F>(&self, user_id: Uuid, matches: F) -> usize
+    where
+        F: Fn(&Connection) -> bool,
+    {
+        let mut connections = self.connections.write().await;
+        let Some(user_connections) = connections.get_mut(&user_id) else {
+            return 0;
+        };
 
-        let send_task = tokio::spawn(async move {
-            while let Ok(data) = rx.recv().await {
-                if let Ok(msg) = serde_json::to_string(&data) {
-                    if socket.send(Message::Text(msg)).await.is_err() {
+        let (closing, remaining): (Vec<Connection>, Vec<Connection>) =
+            user_connections.drain(..).partition(|conn| matches(conn));
+        *user_connections = remaining;
+        let gone_offline: HashSet<Uuid> = closing
+            .iter()
+            .filter_map(|conn| conn.device_id)
+            .filter(|device_id| !is_online(user_connections, *device_id))
+            .collect();
+        let mut accepted = self.accepted.write().await;
+        for device_id in gone_offline {
+            announce_presence(user_connections, device_id, false);
+            accepted.remove(&device_id);
+        }
+        drop(accepted);
+        if user_connections.is_empty() {
+            connections.remove(&user_id);
+        }
+
+        for conn in &closing {
+            // Stores a permit, so a socket that is mid-send still sees it
+            conn.close.notify_one();
+        }
+        closing.len()
+    }
+
+    pub async fn connection_count(&self, user_id: Uuid) -> usize {
+        self.connections
+            .read()
+            .await
+            .get(&user_id)
+            .map_or(0, Vec::len)
+    }
+
+    pub async fn acked_seq(&self, device_id: Uuid) -> Option<u64> {
+        self.acked.read().await.get(&device_id).copied()
+    }
+
+    /// Acks only move forward; a stale or reordered ack is ignored.
+    async fn record_ack(&self, device_id: Uuid, seq: u64) {
+        let mut acked = self.acked.write().await;
+        let entry = acked.entry(device_id).or_default();
+        *entry = (*entry).max(seq);
+    }
+
+    /// Delivers a clip to the owner's other connected devices that it is
+    /// addressed to and that subscribe to its sender. The device that
+    /// produced it never gets an echo. Returns the number of sockets
+    /// the clip was queued for.
+    pub async fn broadcast(&self, data: ClipboardData) -> AppResult<usize> {
+        let connections = self.connections.read().await;
+        let Some(user_connections) = connections.get(&data.user_id) else {
+            return Ok(0);
+        };
+
+        let accepted = self.accepted.read().await;
+        let mut delivered = 0;
+        for conn in user_connections {
+            if conn.device_id == Some(data.device_id) || !receives(&data, conn.device_id, &conn.subscription) {
+                continue;
+            }
+            let mut clip = data.clone();
+            if let Some(accept) = conn.device_id.and_then(|device_id| accepted.get(&device_id)) {
+                clip.retain_formats(accept);
+            }
+            match conn.tx.try_send(Envelope::new(ServerMessage::Clip(clip))) {
+                Ok(()) => delivered += 1,
+                // The client sees the gap in `seq` and resumes to fetch it
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::warn!("Dropping clip {} for slow connection {}", data.id, conn.id);
+                }
+                // The socket is shutting down and will unregister itself
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// Forwards every clip saved through `ClipboardService`, whether it came
+    /// in over REST or a socket, to the owner's connected devices. The task
+    /// ends once the service is dropped.
+    pub fn spawn_clipboard_pump(self: &Arc<Self>) -> JoinHandle<()> {
+        let service: Weak<Self> = Arc::downgrade(self);
+        let mut rx = self.clipboard_service.subscribe();
+
+        tokio::spawn(async move {
+            loop {
+                let data = match rx.recv().await {
+                    Ok(data) => data,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Clipboard pump lagged, {} clips left for clients to resume", skipped);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                let Some(service) = service.upgrade() else { break };
+                if let Err(e) = service.broadcast(data).await {
+                    tracing::error!("Failed to route clip: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Parses one text frame and produces the reply, if any. Malformed input
+    /// is answered with an `error` message rather than closing the socket.
+    pub async fn handle_text_frame(&self, user_id: Uuid, device_id: Option<Uuid>, text: &str) -> Option<Outbound> {
+        let envelope = match serde_json::from_str::<Envelope<ClientMessage>>(text) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                // Salvage the correlation id if the frame is at least JSON
+                let id = serde_json::from_str::<serde_json::Value>(text)
+                    .ok()
+                    .and_then(|value| value.get("id")?.as_str().map(str::to_string));
+                return Some(Envelope::reply_to(
+                    id,
+                    ServerMessage::error(ErrorCode::MalformedFrame, e.to_string()),
+                ));
+            }
+        };
+
+        if envelope.v != PROTOCOL_VERSION {
+            return Some(Envelope::reply_to(
+                envelope.id,
+                ServerMessage::error(
+                    ErrorCode::UnsupportedVersion,
+                    format!("Server speaks protocol version {}", PROTOCOL_VERSION),
+                ),
+            ));
+        }
+
+        let reply = self
+            .handle_message(user_id, device_id, envelope.message)
+            .await
+            .unwrap_or_else(|e| {
+                let code = match e {
+                    AppError::ValidationError(_) | AppError::InvalidClipboardData(_) => ErrorCode::InvalidClip,
+                    _ => ErrorCode::Internal,
+                };
+                Some(ServerMessage::error(code, e.to_string()))
+            });
+
+        reply.map(|message| Envelope::reply_to(envelope.id, message))
+    }
+
+    async fn handle_message(&self, user_id: Uuid, device_id: Option<Uuid>, message: ClientMessage) -> AppResult<Option<ServerMessage>> {
+        match message {
+            ClientMessage::Hello { client, accept } => {
+                tracing::debug!("Hello from {:?} for user {}", client, user_id);
+                if let Some(device_id) = device_id {
+                    let mut accepted = self.accepted.write().await;
+                    match accept.is_empty() {
+                        true => accepted.remove(&device_id),
+                        false => accepted.insert(device_id, accept),
+                    };
+                }
+                let acked_seq = match device_id {
+                    Some(device_id) => self.acked_seq(device_id).await,
+                    None => None,
+                };
+                Ok(Some(ServerMessage::Welcome {
+                    user_id,
+                    device_id,
+                    version: PROTOCOL_VERSION,
+                    last_seq: self.clipboard_service.latest_seq(user_id).await?,
+                    acked_seq,
+                }))
+            }
+            ClientMessage::ClipPush { content, formats, sent_at, targets } => {
+                let Some(device_id) = device_id else {
+                    return Ok(Some(ServerMessage::error(
+                        ErrorCode::DeviceRequired,
+                        "Log in with a device to push clips",
+                    )));
+                };
+
+                self.device_service.verify_devices(&targets, user_id).await?;
+                let mut data = ClipboardData::new(content, device_id, user_id);
+                data.sent_at = sent_at;
+                data.formats = formats;
+                data.targets = targets;
+                // Routing to other devices happens through the clipboard pump
+                let saved = self.clipboard_service.save_clipboard(data).await?;
+
+                Ok(Some(ServerMessage::ClipAck {
+                    clip_id: saved.id,
+                    received_at: saved.received_at,
+                    seq: saved.seq,
+                }))
+            }
+            ClientMessage::HistoryRequest { limit } => {
+                let max = self.config.app.history_size;
+                let limit = limit.unwrap_or(max).min(max);
+                let mut clips = self.clipboard_service.get_user_history(user_id, limit).await?;
+                let subscription = self.subscription_of(device_id).await?;
+                clips.retain(|data| receives(data, device_id, &subscription));
+                self.retain_accepted(device_id, &mut clips).await;
+                Ok(Some(ServerMessage::History { clips }))
+            }
+            ClientMessage::Ack { seq } => {
+                let Some(device_id) = device_id else {
+                    return Ok(Some(ServerMessage::error(
+                        ErrorCode::DeviceRequired,
+                        "Log in with a device to acknowledge clips",
+                    )));
+                };
+                self.record_ack(device_id, seq).await;
+                Ok(None)
+            }
+            ClientMessage::Resume { after_seq } => {
+                let after_seq = match (after_seq, device_id) {
+                    (Some(after_seq), _) => after_seq,
+                    (None, Some(device_id)) => self.acked_seq(device_id).await.unwrap_or(0),
+                    (None, None) => 0,
+                };
+
+                let limit = self.config.websocket.replay_limit.max(1);
+                let mut clips = self.clipboard_service.get_user_clips_after(user_id, after_seq).await?;
+                let more = clips.len() > limit;
+                clips.truncate(limit);
+                // Computed before dropping our own clips so paging still advances
+                let up_to = clips.last().map_or(after_seq, |data| data.seq);
+                let subscription = self.subscription_of(device_id).await?;
+                clips.retain(|data| Some(data.device_id) != device_id && receives(data, device_id, &subscription));
+                self.retain_accepted(device_id, &mut clips).await;
+
+                Ok(Some(ServerMessage::Replay { clips, up_to, more }))
+            }
+            ClientMessage::Subscribe(subscription) => {
+                let Some(device_id) = device_id else {
+                    return Ok(Some(ServerMessage::error(
+                        ErrorCode::DeviceRequired,
+                        "Log in with a device to subscribe",
+                    )));
+                };
+
+                let device = match self.device_service.set_subscription(device_id, user_id, subscription).await {
+                    Ok(device) => device,
+                    Err(AppError::ValidationError(message)) => {
+                        return Ok(Some(ServerMessage::error(ErrorCode::InvalidSubscription, message)));
+                    }
+                    Err(e) => return Err(e),
+                };
+                self.apply_subscription(user_id, device_id, &device.subscription).await;
+                Ok(Some(ServerMessage::Subscribed(device.subscription)))
+            }
+            ClientMessage::Ping => Ok(Some(ServerMessage::Pong)),
+        }
+    }
+
+    pub async fn handle_connection(&self, mut socket: WebSocket, user_id: Uuid, device_id: Option<Uuid>, session_id: Option<Uuid>) {
+        let (connection_id, tx, mut rx, close) = match self.attach(user_id, device_id, session_id).await {
+            Ok(attached) => attached,
+            Err(e) => {
+                tracing::warn!("Refusing connection for user {}: {}", user_id, e);
+                let frame = CloseFrame {
+                    code: close_code::ERROR,
+                    reason: "Try again later".into(),
+                };
+                let _ = socket.send(Message::Close(Some(frame))).await;
+                return;
+            }
+        };
+        let (mut sender, mut receiver) = socket.split();
+
+        let heartbeat_interval = self.config.websocket.heartbeat_interval.max(1);
+        // A client only answers pings, so anything shorter than two periods
+        // would drop idle but healthy sockets on the first missed tick
+        let heartbeat_timeout = self.config.websocket.heartbeat_timeout.max(2 * heartbeat_interval);
+        // Unix seconds of the last frame from the client, pongs included
+        let last_activity = Arc::new(AtomicU64::new(now()));
+        let send_activity = last_activity.clone();
+
+        let mut send_task = tokio::spawn(async move {
+            let period = Duration::from_secs(heartbeat_interval);
+            let mut heartbeat = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                tokio::select! {
+                    envelope = rx.recv() => {
+                        let Some(envelope) = envelope else { break };
+                        if let Ok(msg) = serde_json::to_string(&envelope) {
+                            if sender.send(Message::Text(msg)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    _ = close.notified() => {
+                        let frame = CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "Session revoked".into(),
+                        };
+                        let _ = sender.send(Message::Close(Some(frame))).await;
                         break;
                     }
+                    _ = heartbeat.tick() => {
+                        if now() >= send_activity.load(Ordering::Relaxed) + heartbeat_timeout {
+                            tracing::info!("Closing connection {} after missed heartbeats", connection_id);
+                            let frame = CloseFrame {
+                                code: close_code::AWAY,
+                                reason: "Heartbeat timeout".into(),
+                            };
+                            let _ = sender.send(Message::Close(Some(frame))).await;
+                            break;
+                        }
+                        if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                            break;
+                        }
+                    }
                 }
             }
         });
 
-        send_task.await.ok();
+        let recv_loop = async {
+            // attach() just recorded the device as seen
+            let mut last_touched = now();
+            while let Some(Ok(msg)) = receiver.next().await {
+                let now = now();
+                last_activity.store(now, Ordering::Relaxed);
+                // At most one last-seen write per heartbeat
+                if let Some(device_id) = device_id {
+                    if now >= last_touched + heartbeat_interval {
+                        self.touch_device(device_id).await;
+                        last_touched = now;
+                    }
+                }
+
+                let reply = match msg {
+                    Message::Text(text) => self.handle_text_frame(user_id, device_id, &text).await,
+                    Message::Binary(_) => Some(Envelope::new(ServerMessage::error(
+                        ErrorCode::UnsupportedFrame,
+                        "Binary frames are not supported",
+                    ))),
+                    Message::Close(_) => break,
+                    // Protocol-level pings are answered by axum; pongs
+                    // only matter as a sign of life
+                    Message::Ping(_) | Message::Pong(_) => None,
+                };
+
+                if let Some(reply) = reply {
+                    if tx.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        };
+
+        // Whichever side finishes first ends the connection
+        tokio::select! {
+            _ = &mut send_task => {}
+            _ = recv_loop => send_task.abort(),
+        }
+
+        self.unregister(user_id, connection_id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ClipFormat, Device};
+    use crate::store::{MemoryStore, Store};
+
+    fn create_test_service() -> Arc<WebSocketService> {
+        let config = Arc::new(Config::default());
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let device_service = Arc::new(DeviceService::new(config.clone(), store));
+        let service = Arc::new(WebSocketService::new(config, clipboard_service, device_service));
+        service.spawn_clipboard_pump();
+        service
+    }
+
+    // Presence events interleave with clips; these helpers skip them
+    async fn next_clip(rx: &mut mpsc::Receiver<Outbound>) -> ClipboardData {
+        loop {
+            let envelope = tokio::time::timeout(std::time::Duration::from_secs(1), rx.recv())
+                .await
+                .expect("timed out waiting for clip")
+                .unwrap();
+            match envelope.message {
+                ServerMessage::Clip(data) => return data,
+                ServerMessage::Presence { .. } => continue,
+                other => panic!("expected clip, got {:?}", other),
+            }
+        }
+    }
+
+    fn received_clip(rx: &mut mpsc::Receiver<Outbound>) -> Option<ClipboardData> {
+        loop {
+            match rx.try_recv().ok()?.message {
+                ServerMessage::Clip(data) => return Some(data),
+                ServerMessage::Presence { .. } => continue,
+                _ => return None,
+            }
+        }
+    }
+
+    fn presence_events(rx: &mut mpsc::Receiver<Outbound>) -> Vec<(Uuid, bool)> {
+        let mut events = Vec::new();
+        while let Ok(envelope) = rx.try_recv() {
+            if let ServerMessage::Presence { device_id, online, .. } = envelope.message {
+                events.push((device_id, online));
+            }
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn test_clips_reach_only_the_owners_other_devices() {
+        let service = create_test_service();
+        let alice = Uuid::new_v4();
+        let bob = Uuid::new_v4();
+        let alice_laptop = Uuid::new_v4();
+        let alice_phone = Uuid::new_v4();
+
+        let (_, mut laptop_rx) = service.register(alice, Some(alice_laptop), None).await.unwrap();
+        let (_, mut phone_rx) = service.register(alice, Some(alice_phone), None).await.unwrap();
+        let (_, mut bob_rx) = service.register(bob, Some(Uuid::new_v4()), None).await.unwrap();
+
+        let clip = ClipboardData::new("secret".to_string(), alice_laptop, alice);
+        assert_eq!(service.broadcast(clip.clone()).await.unwrap(), 1);
+
+        assert_eq!(received_clip(&mut phone_rx).unwrap().id, clip.id);
+        assert!(received_clip(&mut laptop_rx).is_none(), "sender must not get an echo");
+        assert!(bob_rx.try_recv().is_err(), "clips must not leak to other users");
+    }
+
+    #[tokio::test]
+    async fn test_many_users_are_isolated() {
+        let service = create_test_service();
+        let mut users = Vec::new();
+        for _ in 0..5 {
+            let user_id = Uuid::new_v4();
+            let sender = Uuid::new_v4();
+            let (_, rx) = service.register(user_id, Some(Uuid::new_v4()), None).await.unwrap();
+            users.push((user_id, sender, rx));
+        }
+
+        for (user_id, sender, _) in &users {
+            let clip = ClipboardData::new(format!("from {}", user_id), *sender, *user_id);
+            service.broadcast(clip).await.unwrap();
+        }
+
+        for (user_id, _, rx) in &mut users {
+            let received = received_clip(rx).unwrap();
+            assert_eq!(received.user_id, *user_id);
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unregister_stops_delivery() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (connection_id, _rx) = service.register(user_id, None, None).await.unwrap();
+        assert_eq!(service.connection_count(user_id).await, 1);
+
+        service.unregister(user_id, connection_id).await;
+        assert_eq!(service.connection_count(user_id).await, 0);
+
+        let clip = ClipboardData::new("late".to_string(), Uuid::new_v4(), user_id);
+        assert_eq!(service.broadcast(clip).await.unwrap(), 0);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_disconnect_session_closes_only_its_sockets() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let revoked = Uuid::new_v4();
+        let (_, mut revoked_rx) = service.register(user_id, Some(Uuid::new_v4()), Some(revoked)).await.unwrap();
+        let (_, mut kept_rx) = service.register(user_id, Some(Uuid::new_v4()), Some(Uuid::new_v4())).await.unwrap();
+
+        assert_eq!(service.disconnect_session(Uuid::new_v4(), revoked).await, 0);
+        assert_eq!(service.disconnect_session(user_id, revoked).await, 1);
+        assert_eq!(service.connection_count(user_id).await, 1);
+        // The closed socket's queue has no senders left
+        presence_events(&mut revoked_rx);
+        assert!(revoked_rx.recv().await.is_none());
+
+        let clip = ClipboardData::new("still here".to_string(), Uuid::new_v4(), user_id);
+        assert_eq!(service.broadcast(clip).await.unwrap(), 1);
+        assert!(received_clip(&mut kept_rx).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_other_sessions() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let current = Uuid::new_v4();
+        service.register(user_id, Some(Uuid::new_v4()), Some(current)).await.unwrap();
+        service.register(user_id, Some(Uuid::new_v4()), Some(Uuid::new_v4())).await.unwrap();
+        service.register(user_id, None, None).await.unwrap();
+
+        assert_eq!(service.disconnect_other_sessions(user_id, Some(current)).await, 2);
+        assert_eq!(service.connection_count(user_id).await, 1);
+        assert_eq!(service.disconnect_other_sessions(user_id, None).await, 1);
+        assert_eq!(service.connection_count(user_id).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_device() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let removed = Uuid::new_v4();
+        service.register(user_id, Some(removed), None).await.unwrap();
+        service.register(user_id, Some(removed), None).await.unwrap();
+        service.register(user_id, Some(Uuid::new_v4()), None).await.unwrap();
+
+        assert_eq!(service.disconnect_device(Uuid::new_v4(), removed).await, 0);
+        assert_eq!(service.disconnect_device(user_id, removed).await, 2);
+        assert_eq!(service.connection_count(user_id).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_presence_follows_first_and_last_socket() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let laptop = Uuid::new_v4();
+        let phone = Uuid::new_v4();
+        let (_, mut laptop_rx) = service.register(user_id, Some(laptop), None).await.unwrap();
+
+        let (first, _phone_rx) = service.register(user_id, Some(phone), None).await.unwrap();
+        let (second, _) = service.register(user_id, Some(phone), None).await.unwrap();
+        // Someone else's devices are never announced
+        service.register(Uuid::new_v4(), Some(Uuid::new_v4()), None).await.unwrap();
+        assert_eq!(presence_events(&mut laptop_rx), vec![(phone, true)]);
+        assert_eq!(service.online_devices(user_id).await, HashSet::from([laptop, phone]));
+
+        // The phone stays online until its last socket closes
+        service.unregister(user_id, first).await;
+        assert!(presence_events(&mut laptop_rx).is_empty());
+        service.unregister(user_id, second).await;
+        assert_eq!(presence_events(&mut laptop_rx), vec![(phone, false)]);
+        assert_eq!(service.online_devices(user_id).await, HashSet::from([laptop]));
+
+        // Forced disconnects are announced too
+        service.register(user_id, Some(phone), None).await.unwrap();
+        service.disconnect_device(user_id, phone).await;
+        assert_eq!(presence_events(&mut laptop_rx), vec![(phone, true), (phone, false)]);
+    }
+
+    #[tokio::test]
+    async fn test_connecting_updates_last_seen() {
+        let config = Arc::new(Config::default());
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let device_service = Arc::new(DeviceService::new(config.clone(), store.clone()));
+        let service = WebSocketService::new(config, clipboard_service, device_service);
+
+        let user_id = Uuid::new_v4();
+        let mut device = store.insert_device(Device::new("laptop".to_string(), user_id)).await.unwrap();
+        device.last_seen = 0;
+        store.update_device(device.clone()).await.unwrap();
+
+        let (connection_id, _) = service.register(user_id, Some(device.id), None).await.unwrap();
+        let seen = store.get_device(device.id).await.unwrap().unwrap().last_seen;
+        assert!(seen > 0);
+
+        let mut device = store.get_device(device.id).await.unwrap().unwrap();
+        device.last_seen = 0;
+        store.update_device(device.clone()).await.unwrap();
+        service.unregister(user_id, connection_id).await;
+        assert!(store.get_device(device.id).await.unwrap().unwrap().last_seen > 0);
+    }
+
+    #[tokio::test]
+    async fn test_targets_and_subscriptions_filter_delivery() {
+        let config = Arc::new(Config::default());
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let device_service = Arc::new(DeviceService::new(config.clone(), store.clone()));
+        let service = Arc::new(WebSocketService::new(config, clipboard_service, device_service));
+        service.spawn_clipboard_pump();
+
+        let user_id = Uuid::new_v4();
+        let mut devices = Vec::new();
+        for name in ["laptop", "phone", "tablet"] {
+            devices.push(store.insert_device(Device::new(name.to_string(), user_id)).await.unwrap().id);
+        }
+        let (laptop, phone, tablet) = (devices[0], devices[1], devices[2]);
+        let (_, mut phone_rx) = service.register(user_id, Some(phone), None).await.unwrap();
+        let (_, mut tablet_rx) = service.register(user_id, Some(tablet), None).await.unwrap();
+        let (_, mut browser_rx) = service.register(user_id, None, None).await.unwrap();
+
+        // Addressed to the phone only
+        let push = format!(r#"{{"v":1,"type":"clip_push","data":{{"content":"for phone","targets":["{}"]}}}}"#, phone);
+        service.handle_text_frame(user_id, Some(laptop), &push).await.unwrap();
+        assert_eq!(next_clip(&mut phone_rx).await.content, "for phone");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(received_clip(&mut tablet_rx).is_none());
+        assert!(received_clip(&mut browser_rx).is_none());
+
+        // Only the sender's own devices can be addressed
+        let stranger = store.insert_device(Device::new("stranger".to_string(), Uuid::new_v4())).await.unwrap().id;
+        let push = format!(r#"{{"v":1,"type":"clip_push","data":{{"content":"astray","targets":["{}"]}}}}"#, stranger);
+        let reply = service.handle_text_frame(user_id, Some(laptop), &push).await.unwrap();
+        assert!(matches!(reply.message, ServerMessage::Error { .. }), "got {:?}", reply.message);
+
+        // The tablet stops listening to the laptop, which sticks across reconnects
+        let subscribe = format!(r#"{{"v":1,"type":"subscribe","data":{{"mode":"except","devices":["{}"]}}}}"#, laptop);
+        let reply = service.handle_text_frame(user_id, Some(tablet), &subscribe).await.unwrap();
+        assert!(matches!(reply.message, ServerMessage::Subscribed(Subscription::Except(ref d)) if d == &vec![laptop]));
+        let (_, mut tablet_rx) = service.register(user_id, Some(tablet), None).await.unwrap();
+
+        let push = r#"{"v":1,"type":"clip_push","data":{"content":"for all"}}"#;
+        service.handle_text_frame(user_id, Some(laptop), push).await.unwrap();
+        assert_eq!(next_clip(&mut phone_rx).await.content, "for all");
+        assert_eq!(next_clip(&mut browser_rx).await.content, "for all");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(received_clip(&mut tablet_rx).is_none());
+
+        // Replays apply the same rules
+        let resume = r#"{"v":1,"type":"resume","data":{"after_seq":0}}"#;
+        let reply = service.handle_text_frame(user_id, Some(tablet), resume).await.unwrap();
+        let ServerMessage::Replay { clips, up_to, .. } = reply.message else {
+            panic!("expected replay, got {:?}", reply.message);
+        };
+        assert!(clips.is_empty());
+        assert_eq!(up_to, 2);
+
+        let subscribe = format!(r#"{{"v":1,"type":"subscribe","data":{{"mode":"only","devices":["{}"]}}}}"#, Uuid::new_v4());
+        let reply = service.handle_text_frame(user_id, Some(tablet), &subscribe).await.unwrap();
+        assert!(matches!(reply.message, ServerMessage::Error { code: ErrorCode::InvalidSubscription, .. }));
+    }
+
+    #[tokio::test]
+    async fn test_receivers_get_only_formats_they_accept() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (laptop, phone) = (Uuid::new_v4(), Uuid::new_v4());
+        let (_, mut phone_rx) = service.register(user_id, Some(phone), None).await.unwrap();
+        let (_, mut browser_rx) = service.register(user_id, None, None).await.unwrap();
+
+        let hello = r#"{"v":1,"type":"hello","data":{"accept":["image/*"]}}"#;
+        service.handle_text_frame(user_id, Some(phone), hello).await.unwrap();
+
+        let push = r#"{"v":1,"type":"clip_push","data":{"content":"cat","formats":[
+            {"mime_type":"text/html","data":"PGI+Y2F0PC9iPg=="},
+            {"mime_type":"image/png","data":"iVBORw=="}]}}"#;
+        let reply = service.handle_text_frame(user_id, Some(laptop), push).await.unwrap();
+        assert!(matches!(reply.message, ServerMessage::ClipAck { .. }), "{:?}", reply.message);
+
+        let received = next_clip(&mut phone_rx).await;
+        assert_eq!(received.content, "cat");
+        assert_eq!(received.formats, vec![ClipFormat::new("image/png", vec![0x89, b'P', b'N', b'G'])]);
+        assert_eq!(next_clip(&mut browser_rx).await.formats.len(), 2);
+
+        let history = r#"{"v":1,"type":"history_request","data":{}}"#;
+        let reply = service.handle_text_frame(user_id, Some(phone), history).await.unwrap();
+        let ServerMessage::History { clips } = reply.message else {
+            panic!("expected history, got {:?}", reply.message);
+        };
+        assert_eq!(clips[0].formats.len(), 1);
+
+        // Saying hello without a list asks for everything again
+        let hello = r#"{"v":1,"type":"hello","data":{}}"#;
+        service.handle_text_frame(user_id, Some(phone), hello).await.unwrap();
+        let reply = service.handle_text_frame(user_id, Some(phone), history).await.unwrap();
+        assert!(matches!(reply.message, ServerMessage::History { ref clips } if clips[0].formats.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn test_accept_lists_end_with_the_last_socket() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (phone, tablet) = (Uuid::new_v4(), Uuid::new_v4());
+        let hello = r#"{"v":1,"type":"hello","data":{"accept":["image/*"]}}"#;
+
+        let (first, _) = service.register(user_id, Some(phone), None).await.unwrap();
+        let (second, _) = service.register(user_id, Some(phone), None).await.unwrap();
+        service.handle_text_frame(user_id, Some(phone), hello).await.unwrap();
+        service.unregister(user_id, first).await;
+        assert!(service.accepted.read().await.contains_key(&phone));
+        service.unregister(user_id, second).await;
+        assert!(service.accepted.read().await.is_empty());
+
+        // Removing a device forgets it too
+        service.register(user_id, Some(tablet), None).await.unwrap();
+        service.handle_text_frame(user_id, Some(tablet), hello).await.unwrap();
+        service.disconnect_device(user_id, tablet).await;
+        assert!(service.accepted.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_clip_push_is_saved_acked_and_routed() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let laptop = Uuid::new_v4();
+        let (_, mut phone_rx) = service.register(user_id, Some(Uuid::new_v4()), None).await.unwrap();
+
+        let frame = r#"{"v":1,"id":"p1","type":"clip_push","data":{"content":"copied","sent_at":5}}"#;
+        let reply = service.handle_text_frame(user_id, Some(laptop), frame).await.unwrap();
+        assert_eq!(reply.id.as_deref(), Some("p1"));
+        let ServerMessage::ClipAck { clip_id, seq, .. } = reply.message else {
+            panic!("expected clip_ack, got {:?}", reply.message);
+        };
+
+        let stored = service.clipboard_service.get_clipboard(clip_id).await.unwrap();
+        assert_eq!(stored.content, "copied");
+        assert_eq!(stored.device_id, laptop);
+        assert_eq!(stored.sent_at, 5);
+        assert_eq!(stored.seq, seq);
+        assert_eq!(next_clip(&mut phone_rx).await.id, clip_id);
+    }
+
+    #[tokio::test]
+    async fn test_clips_saved_outside_sockets_are_routed() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let (_, mut phone_rx) = service.register(user_id, Some(Uuid::new_v4()), None).await.unwrap();
+
+        let data = ClipboardData::new("from rest".to_string(), Uuid::new_v4(), user_id);
+        let saved = service.clipboard_service.save_clipboard(data).await.unwrap();
+
+        assert_eq!(next_clip(&mut phone_rx).await.id, saved.id);
+    }
+
+    #[tokio::test]
+    async fn test_history_hello_and_ping() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let device_id = Some(Uuid::new_v4());
+
+        let hello = service.handle_text_frame(user_id, device_id, r#"{"v":1,"type":"hello","data":{}}"#).await.unwrap();
+        assert!(matches!(hello.message, ServerMessage::Welcome { version: PROTOCOL_VERSION, .. }));
+
+        for i in 0..3 {
+            let frame = format!(r#"{{"v":1,"type":"clip_push","data":{{"content":"clip {}"}}}}"#, i);
+            service.handle_text_frame(user_id, device_id, &frame).await.unwrap();
+        }
+        let history = service
+            .handle_text_frame(user_id, device_id, r#"{"v":1,"type":"history_request","data":{"limit":2}}"#)
+            .await
+            .unwrap();
+        assert!(matches!(history.message, ServerMessage::History { ref clips } if clips.len() == 2));
+
+        let pong = service.handle_text_frame(user_id, device_id, r#"{"v":1,"type":"ping"}"#).await.unwrap();
+        assert!(matches!(pong.message, ServerMessage::Pong));
+    }
+
+    #[tokio::test]
+    async fn test_resume_replays_clips_after_last_ack() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+        let laptop = Uuid::new_v4();
+        let phone = Some(Uuid::new_v4());
+
+        for i in 0..4 {
+            let data = ClipboardData::new(format!("clip {}", i), laptop, user_id);
+            service.clipboard_service.save_clipboard(data).await.unwrap();
+        }
+
+        let ack = service.handle_text_frame(user_id, phone, r#"{"v":1,"type":"ack","data":{"seq":2}}"#).await;
+        assert!(ack.is_none(), "acks are not answered");
+        // A stale ack must not move the cursor backwards
+        service.handle_text_frame(user_id, phone, r#"{"v":1,"type":"ack","data":{"seq":1}}"#).await;
+
+        let hello = service.handle_text_frame(user_id, phone, r#"{"v":1,"type":"hello","data":{}}"#).await.unwrap();
+        assert!(matches!(
+            hello.message,
+            ServerMessage::Welcome { last_seq: 4, acked_seq: Some(2), .. }
+        ));
+
+        let reply = service.handle_text_frame(user_id, phone, r#"{"v":1,"type":"resume","data":{}}"#).await.unwrap();
+        let ServerMessage::Replay { clips, up_to, more } = reply.message else {
+            panic!("expected replay, got {:?}", reply.message);
+        };
+        let seqs: Vec<u64> = clips.iter().map(|data| data.seq).collect();
+        assert_eq!(seqs, vec![3, 4]);
+        assert_eq!(up_to, 4);
+        assert!(!more);
+    }
+
+    #[tokio::test]
+    async fn test_resume_pages_and_skips_own_clips() {
+        let mut config = Config::default();
+        config.websocket.replay_limit = 2;
+        let config = Arc::new(config);
+        let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let device_service = Arc::new(DeviceService::new(config.clone(), store));
+        let service = WebSocketService::new(config, clipboard_service.clone(), device_service);
+
+        let user_id = Uuid::new_v4();
+        let laptop = Uuid::new_v4();
+        let phone = Uuid::new_v4();
+        for (i, device_id) in [laptop, phone, laptop].into_iter().enumerate() {
+            let data = ClipboardData::new(format!("clip {}", i), device_id, user_id);
+            clipboard_service.save_clipboard(data).await.unwrap();
+        }
+
+        let frame = r#"{"v":1,"type":"resume","data":{"after_seq":0}}"#;
+        let reply = service.handle_text_frame(user_id, Some(phone), frame).await.unwrap();
+        let ServerMessage::Replay { clips, up_to, more } = reply.message else {
+            panic!("expected replay, got {:?}", reply.message);
+        };
+        assert_eq!(clips.len(), 1, "the phone's own clip is not replayed");
+        assert_eq!(up_to, 2);
+        assert!(more);
+
+        let frame = format!(r#"{{"v":1,"type":"resume","data":{{"after_seq":{}}}}}"#, up_to);
+        let reply = service.handle_text_frame(user_id, Some(phone), &frame).await.unwrap();
+        assert!(matches!(
+            reply.message,
+            ServerMessage::Replay { ref clips, up_to: 3, more: false } if clips.len() == 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_bad_frames_get_structured_errors() {
+        let service = create_test_service();
+        let user_id = Uuid::new_v4();
+
+        let error_code = |reply: Option<Outbound>| match reply.unwrap().message {
+            ServerMessage::Error { code, .. } => code,
+            other => panic!("expected error, got {:?}", other),
+        };
+
+        let reply = service.handle_text_frame(user_id, None, "not json").await;
+        assert_eq!(error_code(reply), ErrorCode::MalformedFrame);
+
+        let reply = service.handle_text_frame(user_id, None, r#"{"v":1,"id":"x","type":"teleport"}"#).await;
+        assert_eq!(reply.as_ref().unwrap().id.as_deref(), Some("x"));
+        assert_eq!(error_code(reply), ErrorCode::MalformedFrame);
+
+        let reply = service.handle_text_frame(user_id, None, r#"{"v":99,"type":"ping"}"#).await;
+        assert_eq!(error_code(reply), ErrorCode::UnsupportedVersion);
+
+        let push = r#"{"v":1,"type":"clip_push","data":{"content":"x"}}"#;
+        let reply = service.handle_text_frame(user_id, None, push).await;
+        assert_eq!(error_code(reply), ErrorCode::DeviceRequired);
+
+        let ack = r#"{"v":1,"type":"ack","data":{"seq":1}}"#;
+        let reply = service.handle_text_frame(user_id, None, ack).await;
+        assert_eq!(error_code(reply), ErrorCode::DeviceRequired);
+
+        let huge = format!(r#"{{"v":1,"type":"clip_push","data":{{"content":"{}"}}}}"#, "x".repeat(2 * 1024 * 1024));
+        let reply = service.handle_text_frame(user_id, Some(Uuid::new_v4()), &huge).await;
+        assert_eq!(error_code(reply), ErrorCode::InvalidClip);
+    }
+}
@@ -1,33 +1,65 @@
 use std::sync::Arc;
 
-use crate::services::{UserService, AuthService, WebSocketService, DeviceService};
+use crate::services::{UserService, AuthService, WebSocketService, DeviceService, ClipboardService, MaintenanceService, MfaService, ThrottleService, PairingService, BlobService};
 use crate::config::Config;
+use crate::store::{self, Store};
 
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<Config>,
     pub user_service: Arc<UserService>,
     pub auth_service: Arc<AuthService>,
+    pub mfa_service: Arc<MfaService>,
+    pub throttle_service: Arc<ThrottleService>,
+    pub pairing_service: Arc<PairingService>,
     pub device_service: Arc<DeviceService>,
+    pub clipboard_service: Arc<ClipboardService>,
+    pub blob_service: Arc<BlobService>,
     pub ws_service: Arc<WebSocketService>, 
+    pub maintenance_service: Arc<MaintenanceService>,
 }
 
 impl AppState {
     pub async fn new(config: Config) -> Self {
         let config = Arc::new(config);
+        let store: Arc<dyn Store> = store::open_store(&config.storage)
+            .await
+            .expect("Failed to open storage backend");
         
         // Initialize services
-        let user_service = Arc::new(UserService::new(config.clone()));
-        let auth_service = Arc::new(AuthService::new(config.clone()));
-        let device_service = Arc::new(DeviceService::new(config.clone()));
-        let ws_service = Arc::new(WebSocketService::new(config.clone()));
+        let user_service = Arc::new(UserService::new(config.clone(), store.clone()));
+        let auth_service = Arc::new(AuthService::new(config.clone(), store.clone()));
+        let mfa_service = Arc::new(MfaService::new(config.clone(), store.clone()));
+        let throttle_service = Arc::new(ThrottleService::new(config.clone()));
+        let pairing_service = Arc::new(PairingService::new(config.clone()));
+        let device_service = Arc::new(DeviceService::new(config.clone(), store.clone()));
+        let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+        let blob_service = Arc::new(BlobService::new(config.clone(), store.clone()));
+        let ws_service = Arc::new(WebSocketService::new(
+            config.clone(),
+            clipboard_service.clone(),
+            device_service.clone(),
+        ));
+        ws_service.spawn_clipboard_pump();
+        let maintenance_service = Arc::new(MaintenanceService::new(
+            config.clone(),
+            clipboard_service.clone(),
+            auth_service.clone(),
+            blob_service.clone(),
+        ));
 
         Self {
             config,
             user_service,
             auth_service,
+            mfa_service,
+            throttle_service,
+            pairing_service,
             device_service,
+            clipboard_service,
+            blob_service,
             ws_service,
+            maintenance_service,
         }
     }
 }
\ No newline at end of file
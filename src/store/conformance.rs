@@ -0,0 +1,416 @@
+//! Behaviour every `Store` backend must share. Backends call `run_all` from
+//! their own test module with a fresh, empty store.
+
+use uuid::Uuid;
+use crate::{
+    error::AppError,
+    models::{User, MfaSettings, Device, Subscription, ClipboardData, ClipFormat, Blob, TokenFamily},
+};
+use super::Store;
+
+pub async fn run_all(store: &dyn Store) {
+    users(store).await;
+    devices(store).await;
+    clips(store).await;
+    clip_seqs(store).await;
+    payloads(store).await;
+    blobs(store).await;
+    blob_refs(store).await;
+    tokens(store).await;
+    token_families(store).await;
+}
+
+pub async fn users(store: &dyn Store) {
+    let alice = store
+        .insert_user(User::new("conf-alice".to_string(), "hash-a".to_string()))
+        .await
+        .unwrap();
+    let bob = store
+        .insert_user(User::new("conf-bob".to_string(), "hash-b".to_string()))
+        .await
+        .unwrap();
+
+    let duplicate = store
+        .insert_user(User::new("conf-alice".to_string(), "hash-c".to_string()))
+        .await;
+    assert!(matches!(duplicate, Err(AppError::UserAlreadyExists(_))));
+
+    let fetched = store.get_user(alice.id).await.unwrap().unwrap();
+    assert_eq!(fetched.username, "conf-alice");
+    assert_eq!(fetched.password_hash, "hash-a");
+    let by_name = store.get_user_by_username("conf-bob").await.unwrap().unwrap();
+    assert_eq!(by_name.id, bob.id);
+    assert!(store.get_user(Uuid::new_v4()).await.unwrap().is_none());
+    assert!(store.get_user_by_username("nobody").await.unwrap().is_none());
+
+    let listed = store.list_users().await.unwrap();
+    let names: Vec<&str> = listed.iter().map(|u| u.username.as_str()).collect();
+    assert_eq!(names, vec!["conf-alice", "conf-bob"]);
+    assert_eq!(store.count_users().await.unwrap(), 2);
+
+    // Renames keep the username index consistent
+    let mut renamed = alice.clone();
+    renamed.username = "conf-carol".to_string();
+    store.update_user(renamed).await.unwrap();
+    assert!(store.get_user_by_username("conf-alice").await.unwrap().is_none());
+    assert_eq!(
+        store.get_user_by_username("conf-carol").await.unwrap().unwrap().id,
+        alice.id
+    );
+
+    let mut clash = bob.clone();
+    clash.username = "conf-carol".to_string();
+    assert!(matches!(
+        store.update_user(clash).await,
+        Err(AppError::UserAlreadyExists(_))
+    ));
+
+    // Two-factor settings round-trip
+    let mut enrolled = bob.clone();
+    enrolled.mfa = Some(MfaSettings {
+        totp_secret: "JBSWY3DPEHPK3PXP".to_string(),
+        enabled: true,
+        recovery_code_hashes: vec!["ab".to_string(), "cd".to_string()],
+        last_used_step: 42,
+    });
+    store.update_user(enrolled.clone()).await.unwrap();
+    assert_eq!(store.get_user(bob.id).await.unwrap().unwrap().mfa, enrolled.mfa);
+    assert!(fetched.mfa.is_none());
+
+    let ghost = User::new("conf-ghost".to_string(), "hash".to_string());
+    assert!(matches!(
+        store.update_user(ghost).await,
+        Err(AppError::UserNotFound(_))
+    ));
+
+    assert!(store.delete_user(alice.id).await.unwrap());
+    assert!(!store.delete_user(alice.id).await.unwrap());
+    assert!(store.get_user_by_username("conf-carol").await.unwrap().is_none());
+    assert_eq!(store.count_users().await.unwrap(), 1);
+}
+
+pub async fn devices(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let mut first = Device::new("laptop".to_string(), user_id);
+    first.created_at = 100;
+    let mut second = Device::new("phone".to_string(), user_id);
+    second.created_at = 200;
+    let other = Device::new("stranger".to_string(), Uuid::new_v4());
+
+    store.insert_device(second.clone()).await.unwrap();
+    store.insert_device(first.clone()).await.unwrap();
+    store.insert_device(other.clone()).await.unwrap();
+
+    let listed = store.list_user_devices(user_id).await.unwrap();
+    let ids: Vec<Uuid> = listed.iter().map(|d| d.id).collect();
+    assert_eq!(ids, vec![first.id, second.id]);
+
+    let mut renamed = first.clone();
+    renamed.name = "work laptop".to_string();
+    renamed.subscription = Subscription::Except(vec![second.id]);
+    store.update_device(renamed).await.unwrap();
+    let fetched = store.get_device(first.id).await.unwrap().unwrap();
+    assert_eq!(fetched.name, "work laptop");
+    assert_eq!(fetched.subscription, Subscription::Except(vec![second.id]));
+    assert_eq!(
+        store.get_device(second.id).await.unwrap().unwrap().subscription,
+        Subscription::All
+    );
+
+    // Touching moves only last_seen, and never backwards
+    let seen = first.last_seen + 500;
+    assert!(store.touch_device(first.id, seen).await.unwrap());
+    assert!(store.touch_device(first.id, seen - 100).await.unwrap());
+    let touched = store.get_device(first.id).await.unwrap().unwrap();
+    assert_eq!(touched.last_seen, seen);
+    assert_eq!(touched.name, "work laptop");
+    assert!(!store.touch_device(Uuid::new_v4(), 500).await.unwrap());
+
+    let ghost = Device::new("ghost".to_string(), user_id);
+    assert!(matches!(
+        store.update_device(ghost).await,
+        Err(AppError::DeviceNotFound(_))
+    ));
+
+    assert!(store.delete_device(first.id).await.unwrap());
+    assert!(!store.delete_device(first.id).await.unwrap());
+    assert!(store.get_device(first.id).await.unwrap().is_none());
+    assert_eq!(store.list_user_devices(user_id).await.unwrap().len(), 1);
+}
+
+pub async fn clips(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let other_user = Uuid::new_v4();
+    let device_id = Uuid::new_v4();
+
+    for (i, received_at) in [10u64, 30, 20].into_iter().enumerate() {
+        let mut data = ClipboardData::new(format!("clip {}", i), device_id, user_id);
+        data.received_at = received_at;
+        store.insert_clip(data).await.unwrap();
+    }
+    let mut foreign = ClipboardData::new("foreign".to_string(), Uuid::new_v4(), other_user);
+    foreign.received_at = 5;
+    foreign.targets = vec![device_id];
+    foreign.formats = vec![ClipFormat::new("image/png", vec![0, 159, 255])];
+    let foreign = store.insert_clip(foreign).await.unwrap();
+
+    let listed = store.list_user_clips(user_id).await.unwrap();
+    let order: Vec<u64> = listed.iter().map(|d| d.received_at).collect();
+    assert_eq!(order, vec![30, 20, 10]);
+    assert_eq!(store.list_device_clips(device_id).await.unwrap().len(), 3);
+
+    let fetched = store.get_clip(foreign.id).await.unwrap().unwrap();
+    assert_eq!(fetched.content, "foreign");
+    assert_eq!(fetched.targets, vec![device_id]);
+    assert_eq!(fetched.formats, foreign.formats);
+    assert!(listed.iter().all(|data| data.targets.is_empty() && data.formats.is_empty()));
+
+    assert_eq!(store.delete_clips_received_before(20).await.unwrap(), 2);
+    assert!(store.get_clip(foreign.id).await.unwrap().is_none());
+    assert_eq!(store.list_user_clips(user_id).await.unwrap().len(), 2);
+
+    let newest = listed[0].id;
+    assert!(store.delete_clip(newest).await.unwrap());
+    assert!(!store.delete_clip(newest).await.unwrap());
+
+    assert_eq!(store.delete_user_clips(user_id).await.unwrap(), 1);
+    assert!(store.list_user_clips(user_id).await.unwrap().is_empty());
+}
+
+pub async fn blobs(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let mut old = Blob::new(user_id, 5, 4);
+    old.created_at = 10;
+    let old = store.insert_blob(old).await.unwrap();
+    let mut other = Blob::new(Uuid::new_v4(), 3, 4);
+    other.created_at = 10;
+    let other = store.insert_blob(other).await.unwrap();
+    assert!(store.get_blob(old.id).await.unwrap().unwrap().received.is_empty());
+
+    // Chunks may arrive out of order and be re-sent
+    store.put_blob_chunk(old.id, 1, vec![5]).await.unwrap();
+    store.put_blob_chunk(old.id, 0, vec![0; 4]).await.unwrap();
+    let blob = store.put_blob_chunk(old.id, 1, vec![4]).await.unwrap();
+    assert_eq!(blob.received, vec![0, 1]);
+    assert_eq!(store.get_blob_chunk(old.id, 1).await.unwrap(), Some(vec![4]));
+    assert_eq!(store.get_blob_chunk(old.id, 2).await.unwrap(), None);
+    assert!(matches!(
+        store.put_blob_chunk(Uuid::new_v4(), 0, vec![1]).await,
+        Err(AppError::BlobNotFound(_))
+    ));
+
+    assert!(store.complete_blob(old.id, 20).await.unwrap());
+    assert!(!store.complete_blob(Uuid::new_v4(), 20).await.unwrap());
+    let fetched = store.get_blob(old.id).await.unwrap().unwrap();
+    assert_eq!(fetched.completed_at, Some(20));
+    assert_eq!(fetched.received, vec![0, 1]);
+    assert!(matches!(
+        store.put_blob_chunk(old.id, 0, vec![9; 4]).await,
+        Err(AppError::BlobComplete(_))
+    ));
+    assert_eq!(store.get_blob_chunk(old.id, 0).await.unwrap(), Some(vec![0; 4]));
+
+    let fresh = store.insert_blob(Blob::new(user_id, 1, 4)).await.unwrap();
+    let user_blobs = store.list_user_blobs(user_id).await.unwrap();
+    assert_eq!(user_blobs.iter().map(|b| b.id).collect::<Vec<_>>(), vec![old.id, fresh.id]);
+    assert_eq!(user_blobs[0].received, vec![0, 1]);
+    let mut stale: Vec<Uuid> = store.list_blobs_created_before(11).await.unwrap().iter().map(|b| b.id).collect();
+    stale.sort();
+    let mut expected = vec![old.id, other.id];
+    expected.sort();
+    assert_eq!(stale, expected);
+
+    assert!(store.delete_blob(old.id).await.unwrap());
+    assert!(!store.delete_blob(old.id).await.unwrap());
+    assert_eq!(store.get_blob_chunk(old.id, 0).await.unwrap(), None);
+
+    store.put_blob_chunk(fresh.id, 0, vec![1]).await.unwrap();
+    assert_eq!(store.delete_user_blobs(user_id).await.unwrap(), 1);
+    assert!(store.get_blob(fresh.id).await.unwrap().is_none());
+    assert_eq!(store.get_blob_chunk(fresh.id, 0).await.unwrap(), None);
+    assert!(store.get_blob(other.id).await.unwrap().is_some());
+}
+
+pub async fn blob_refs(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let blob = store.insert_blob(Blob::new(user_id, 1, 4)).await.unwrap();
+    store.complete_blob(blob.id, 1).await.unwrap();
+
+    let mut dangling = ClipboardData::new(String::new(), Uuid::new_v4(), user_id);
+    dangling.formats = vec![ClipFormat::from_blob("image/png", Uuid::new_v4())];
+    assert!(matches!(store.insert_clip(dangling).await, Err(AppError::BlobNotFound(_))));
+    assert!(store.list_user_clips(user_id).await.unwrap().is_empty());
+
+    // A referenced blob stays until its last clip is gone
+    let mut clip = ClipboardData::new(String::new(), Uuid::new_v4(), user_id);
+    clip.formats = vec![ClipFormat::from_blob("image/png", blob.id)];
+    let clip = store.insert_clip(clip).await.unwrap();
+    assert!(matches!(store.delete_blob(blob.id).await, Err(AppError::BlobInUse(_))));
+    assert!(store.get_blob(blob.id).await.unwrap().is_some());
+
+    assert!(store.delete_clip(clip.id).await.unwrap());
+    assert!(store.delete_blob(blob.id).await.unwrap());
+}
+
+pub async fn clip_seqs(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let other_user = Uuid::new_v4();
+    let device_id = Uuid::new_v4();
+
+    let mut seqs = Vec::new();
+    for i in 0..3 {
+        let data = ClipboardData::new(format!("seq {}", i), device_id, user_id);
+        seqs.push(store.insert_clip(data).await.unwrap().seq);
+    }
+    assert_eq!(seqs, vec![1, 2, 3]);
+
+    // Every user has their own sequence
+    let foreign = ClipboardData::new("foreign".to_string(), device_id, other_user);
+    assert_eq!(store.insert_clip(foreign).await.unwrap().seq, 1);
+
+    let after = store.list_user_clips_after(user_id, 1).await.unwrap();
+    let order: Vec<u64> = after.iter().map(|d| d.seq).collect();
+    assert_eq!(order, vec![2, 3]);
+    assert!(store.list_user_clips_after(user_id, 3).await.unwrap().is_empty());
+
+    // Deleting the newest clips must not hand their numbers out again
+    assert_eq!(store.delete_user_clips(user_id).await.unwrap(), 3);
+    let data = ClipboardData::new("after delete".to_string(), device_id, user_id);
+    assert_eq!(store.insert_clip(data).await.unwrap().seq, 4);
+
+    // Explicit sequence numbers are kept and move the counter forward
+    let mut imported = ClipboardData::new("imported".to_string(), device_id, user_id);
+    imported.seq = 10;
+    assert_eq!(store.insert_clip(imported).await.unwrap().seq, 10);
+    let data = ClipboardData::new("next".to_string(), device_id, user_id);
+    assert_eq!(store.insert_clip(data).await.unwrap().seq, 11);
+
+    store.delete_user_clips(user_id).await.unwrap();
+    store.delete_user_clips(other_user).await.unwrap();
+}
+
+pub async fn payloads(store: &dyn Store) {
+    let (user_id, other_user) = (Uuid::new_v4(), Uuid::new_v4());
+    let (laptop, phone) = (Uuid::new_v4(), Uuid::new_v4());
+    let content = format!("https://example.com/{}", Uuid::new_v4());
+
+    let mut first = ClipboardData::new(content.clone(), laptop, user_id);
+    first.received_at = 1000;
+    let first = store.insert_clip(first).await.unwrap();
+    assert_eq!(first.content_hash, first.payload_hash());
+    assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 1);
+
+    // Copying it again bumps the existing entry
+    let mut again = ClipboardData::new(content.clone(), phone, user_id);
+    again.received_at = 2000;
+    again.targets = vec![laptop];
+    let again = store.insert_clip(again).await.unwrap();
+    assert_eq!(again.id, first.id);
+    assert!(again.seq > first.seq);
+    let history = store.list_user_clips(user_id).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content, content);
+    assert_eq!((history[0].received_at, history[0].device_id), (2000, phone));
+    assert_eq!(history[0].targets, vec![laptop]);
+    assert_eq!(store.list_user_clips_after(user_id, first.seq).await.unwrap().len(), 1);
+    assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 1);
+
+    // Another user's copy shares the payload under its own entry
+    let mut shared = ClipboardData::new(content.clone(), Uuid::new_v4(), other_user);
+    shared.received_at = 1500;
+    let shared = store.insert_clip(shared).await.unwrap();
+    assert_ne!(shared.id, first.id);
+    assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 2);
+
+    // Other formats make a different payload
+    let mut richer = ClipboardData::new(content.clone(), laptop, user_id);
+    richer.formats = vec![ClipFormat::new("text/html", "<a>link</a>")];
+    richer.received_at = 3000;
+    let richer = store.insert_clip(richer).await.unwrap();
+    assert_ne!(richer.id, first.id);
+    assert_eq!(store.list_user_clips(user_id).await.unwrap().len(), 2);
+
+    // The payload outlives all but its last reference
+    assert!(store.delete_clip(first.id).await.unwrap());
+    assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 1);
+    assert_eq!(store.get_clip(shared.id).await.unwrap().unwrap().content, content);
+    store.delete_clips_received_before(1501).await.unwrap();
+    assert!(store.get_clip(shared.id).await.unwrap().is_none());
+    assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 0);
+
+    // A freed payload can be stored again
+    let fresh = store.insert_clip(ClipboardData::new(content.clone(), laptop, user_id)).await.unwrap();
+    assert_eq!(store.get_clip(fresh.id).await.unwrap().unwrap().content, content);
+    assert_eq!(store.payload_refs(&fresh.content_hash).await.unwrap(), 1);
+
+    assert_eq!(store.delete_user_clips(user_id).await.unwrap(), 2);
+    assert_eq!(store.payload_refs(&fresh.content_hash).await.unwrap(), 0);
+    assert_eq!(store.payload_refs(&richer.content_hash).await.unwrap(), 0);
+}
+
+pub async fn tokens(store: &dyn Store) {
+    assert!(store.revoke_token("short-lived", 100).await.unwrap());
+    assert!(store.revoke_token("long-lived", 1_000).await.unwrap());
+    assert!(!store.revoke_token("long-lived", 1_000).await.unwrap());
+
+    assert!(store.is_token_revoked("short-lived").await.unwrap());
+    assert!(!store.is_token_revoked("never-revoked").await.unwrap());
+
+    assert_eq!(store.prune_revoked_tokens(500).await.unwrap(), 1);
+    assert!(!store.is_token_revoked("short-lived").await.unwrap());
+    assert!(store.is_token_revoked("long-lived").await.unwrap());
+}
+
+pub async fn token_families(store: &dyn Store) {
+    let user_id = Uuid::new_v4();
+    let first_jti = Uuid::new_v4();
+    let mut family = TokenFamily::new(user_id, Some(Uuid::new_v4()), first_jti, 100);
+    family.created_at = 10;
+    family.last_used_at = 10;
+    family.ip_address = Some("203.0.113.7".to_string());
+    family.user_agent = Some("clipman-android/1.0".to_string());
+    let family = store.insert_token_family(family).await.unwrap();
+
+    let fetched = store.get_token_family(family.id).await.unwrap().unwrap();
+    assert_eq!(fetched.user_id, user_id);
+    assert_eq!(fetched.device_id, family.device_id);
+    assert_eq!(fetched.ip_address.as_deref(), Some("203.0.113.7"));
+    assert_eq!(fetched.user_agent.as_deref(), Some("clipman-android/1.0"));
+    assert!(!fetched.revoked);
+    assert!(store.get_token_family(Uuid::new_v4()).await.unwrap().is_none());
+
+    // Last-used times only move forward
+    store.touch_token_family(family.id, 50).await.unwrap();
+    store.touch_token_family(family.id, 40).await.unwrap();
+    store.touch_token_family(Uuid::new_v4(), 40).await.unwrap();
+    assert_eq!(store.get_token_family(family.id).await.unwrap().unwrap().last_used_at, 50);
+
+    // Only the current token can move the family forward
+    let second_jti = Uuid::new_v4();
+    assert!(store.rotate_token_family(family.id, first_jti, second_jti, 200).await.unwrap());
+    assert!(!store.rotate_token_family(family.id, first_jti, Uuid::new_v4(), 300).await.unwrap());
+    let rotated = store.get_token_family(family.id).await.unwrap().unwrap();
+    assert_eq!(rotated.current_jti, second_jti);
+    assert_eq!(rotated.expires_at, 200);
+
+    assert!(store.revoke_token_family(family.id).await.unwrap());
+    assert!(!store.revoke_token_family(Uuid::new_v4()).await.unwrap());
+    assert!(store.get_token_family(family.id).await.unwrap().unwrap().revoked);
+    assert!(!store.rotate_token_family(family.id, second_jti, Uuid::new_v4(), 300).await.unwrap());
+
+    let mut long_lived = TokenFamily::new(user_id, None, Uuid::new_v4(), 1_000);
+    long_lived.created_at = 20;
+    let long_lived = store.insert_token_family(long_lived).await.unwrap();
+    store
+        .insert_token_family(TokenFamily::new(Uuid::new_v4(), None, Uuid::new_v4(), 1_000))
+        .await
+        .unwrap();
+
+    let listed = store.list_user_token_families(user_id).await.unwrap();
+    let ids: Vec<Uuid> = listed.iter().map(|f| f.id).collect();
+    assert_eq!(ids, vec![family.id, long_lived.id]);
+
+    assert_eq!(store.prune_token_families(500).await.unwrap(), 1);
+    assert!(store.get_token_family(family.id).await.unwrap().is_none());
+    assert!(store.get_token_family(long_lived.id).await.unwrap().is_some());
+}
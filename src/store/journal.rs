@@ -0,0 +1,1200 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    models::{User, MfaSettings, Device, ClipboardData, ClipFormat, Blob, TokenFamily},
+};
+use super::{MemoryStore, UserStore, DeviceStore, ClipStore, BlobStore, TokenStore};
+
+const JOURNAL_FILE: &str = "journal.log";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+// Each journal record is framed as `[len: u32 LE][crc32: u32 LE][json]`.
+// A record whose frame is short or whose checksum does not match marks the
+// end of the usable journal; everything from there on is discarded.
+const HEADER_LEN: usize = 8;
+
+/// `User` skips `password_hash` when serialized, so persist a full copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct UserRecord {
+    id: Uuid,
+    username: String,
+    password_hash: String,
+    #[serde(default)]
+    mfa: Option<MfaSettings>,
+    created_at: Option<DateTime<Utc>>,
+    updated_at: Option<DateTime<Utc>>,
+}
+
+impl From<User> for UserRecord {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            password_hash: user.password_hash,
+            mfa: user.mfa,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+        }
+    }
+}
+
+impl From<UserRecord> for User {
+    fn from(record: UserRecord) -> Self {
+        Self {
+            id: record.id,
+            username: record.username,
+            password_hash: record.password_hash,
+            mfa: record.mfa,
+            created_at: record.created_at,
+            updated_at: record.updated_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BlobChunkRecord {
+    blob_id: Uuid,
+    index: u32,
+    #[serde(with = "crate::models::base64_data")]
+    data: Vec<u8>,
+}
+
+/// A clip payload, written once per content hash. Clip records and snapshot
+/// clips leave their own payload fields empty and refer to it by
+/// `content_hash`; older ones carry the payload inline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PayloadRecord {
+    hash: String,
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    formats: Vec<ClipFormat>,
+}
+
+/// Payloads seen so far while loading, by hash.
+type Payloads = HashMap<String, PayloadRecord>;
+
+/// Fills a clip record back in with its payload. A record that still has
+/// its payload inline adds it to `payloads` for later records to refer to.
+fn hydrate(mut data: ClipboardData, payloads: &mut Payloads) -> AppResult<ClipboardData> {
+    if let Some(payload) = payloads.get(&data.content_hash) {
+        data.content = payload.content.clone();
+        data.formats = payload.formats.clone();
+        return Ok(data);
+    }
+    let hash = data.payload_hash();
+    if !data.content_hash.is_empty() && data.content_hash != hash {
+        return Err(AppError::DatabaseError(format!(
+            "Clip {} refers to unknown payload {}",
+            data.id, data.content_hash
+        )));
+    }
+    payloads.insert(hash.clone(), PayloadRecord {
+        hash,
+        content: data.content.clone(),
+        formats: data.formats.clone(),
+    });
+    Ok(data)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", content = "data", rename_all = "snake_case")]
+enum Mutation {
+    InsertUser(UserRecord),
+    UpdateUser(UserRecord),
+    DeleteUser(Uuid),
+    InsertDevice(Device),
+    UpdateDevice(Device),
+    TouchDevice { id: Uuid, last_seen: u64 },
+    DeleteDevice(Uuid),
+    InsertPayload(PayloadRecord),
+    InsertClip(ClipboardData),
+    DeleteClip(Uuid),
+    DeleteUserClips(Uuid),
+    DeleteClipsReceivedBefore(u64),
+    InsertBlob(Blob),
+    PutBlobChunk(BlobChunkRecord),
+    CompleteBlob { id: Uuid, completed_at: u64 },
+    DeleteBlob(Uuid),
+    DeleteUserBlobs(Uuid),
+    RevokeToken { token: String, expires_at: u64 },
+    PruneRevokedTokens(u64),
+    InsertTokenFamily(TokenFamily),
+    RotateTokenFamily { id: Uuid, expected_jti: Uuid, new_jti: Uuid, expires_at: u64 },
+    RevokeTokenFamily(Uuid),
+    PruneTokenFamilies(u64),
+    TouchTokenFamily { id: Uuid, last_used_at: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    seq: u64,
+    mutation: Mutation,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Snapshot {
+    /// Sequence number of the last journal record folded into this snapshot.
+    last_seq: u64,
+    users: Vec<UserRecord>,
+    devices: Vec<Device>,
+    clips: Vec<ClipboardData>,
+    #[serde(default)]
+    payloads: Vec<PayloadRecord>,
+    /// Per-user clip sequence counters; they outlive the clips themselves.
+    #[serde(default)]
+    clip_seqs: Vec<(Uuid, u64)>,
+    #[serde(default)]
+    blobs: Vec<Blob>,
+    #[serde(default)]
+    blob_chunks: Vec<BlobChunkRecord>,
+    revoked_tokens: Vec<(String, u64)>,
+    #[serde(default)]
+    token_families: Vec<TokenFamily>,
+}
+
+struct Journal {
+    dir: PathBuf,
+    file: File,
+    /// Length of the journal up to the end of the last intact record.
+    len: u64,
+    /// Set when a failed append could not be cut back off the file; later
+    /// records would land behind the torn one, so writes are refused.
+    broken: bool,
+    next_seq: u64,
+    records_since_snapshot: usize,
+}
+
+/// Log-structured store for deployments without a database. State lives in a
+/// `MemoryStore`; every mutation is appended to `journal.log` and fsynced
+/// before it is applied, so readers never see a change the disk lacks. On
+/// open the latest `snapshot.json` is loaded and newer journal records are
+/// replayed on top of it. Once `compact_threshold` records have accumulated
+/// the state is written to a fresh snapshot and the journal is truncated.
+pub struct JournalStore {
+    state: MemoryStore,
+    journal: Mutex<Journal>,
+    compact_threshold: usize,
+}
+
+fn io_err(context: &str, e: std::io::Error) -> AppError {
+    AppError::DatabaseError(format!("{}: {}", context, e))
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn encode_record(record: &Record) -> AppResult<Vec<u8>> {
+    let payload = serde_json::to_vec(record)
+        .map_err(|e| AppError::InternalError(format!("Journal encoding failed: {}", e)))?;
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decodes records from the start of `bytes`. Returns the records and the
+/// offset just past the last intact one.
+fn decode_records(bytes: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while bytes.len() - offset >= HEADER_LEN {
+        let header = &bytes[offset..offset + HEADER_LEN];
+        let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(header[4..8].try_into().unwrap());
+
+        let start = offset + HEADER_LEN;
+        let Some(payload) = bytes.get(start..start + len) else { break };
+        if crc32(payload) != checksum {
+            break;
+        }
+        let Ok(record) = serde_json::from_slice::<Record>(payload) else { break };
+
+        records.push(record);
+        offset = start + len;
+    }
+
+    (records, offset)
+}
+
+impl JournalStore {
+    pub async fn open(dir: impl AsRef<Path>, compact_threshold: usize) -> AppResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| io_err("Failed to create journal directory", e))?;
+
+        let state = MemoryStore::new();
+
+        let snapshot = match tokio::fs::read(dir.join(SNAPSHOT_FILE)).await {
+            Ok(bytes) => serde_json::from_slice::<Snapshot>(&bytes)
+                .map_err(|e| AppError::DatabaseError(format!("Corrupt snapshot: {}", e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(io_err("Failed to read snapshot", e)),
+        };
+        let last_seq = snapshot.last_seq;
+        let mut payloads = Payloads::new();
+        Self::restore(&state, snapshot, &mut payloads).await?;
+
+        let journal_path = dir.join(JOURNAL_FILE);
+        let bytes = match tokio::fs::read(&journal_path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(io_err("Failed to read journal", e)),
+        };
+        let (records, valid_len) = decode_records(&bytes);
+
+        let mut next_seq = last_seq + 1;
+        let mut records_since_snapshot = 0;
+        for record in records {
+            // Records already folded into the snapshot survive if we crashed
+            // between writing the snapshot and truncating the journal.
+            if record.seq <= last_seq {
+                continue;
+            }
+            if let Err(e) = Self::apply(&state, record.mutation, &mut payloads).await {
+                tracing::warn!("Skipping journal record {}: {}", record.seq, e);
+            }
+            next_seq = record.seq + 1;
+            records_since_snapshot += 1;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&journal_path)
+            .await
+            .map_err(|e| io_err("Failed to open journal", e))?;
+
+        if valid_len < bytes.len() {
+            tracing::warn!(
+                "Discarding {} bytes of truncated or corrupt journal tail",
+                bytes.len() - valid_len
+            );
+            file.set_len(valid_len as u64)
+                .await
+                .map_err(|e| io_err("Failed to truncate journal", e))?;
+            file.sync_all()
+                .await
+                .map_err(|e| io_err("Failed to sync journal", e))?;
+        }
+
+        Ok(Self {
+            state,
+            journal: Mutex::new(Journal {
+                dir,
+                file,
+                len: valid_len as u64,
+                broken: false,
+                next_seq,
+                records_since_snapshot,
+            }),
+            compact_threshold: compact_threshold.max(1),
+        })
+    }
+
+    async fn restore(state: &MemoryStore, snapshot: Snapshot, payloads: &mut Payloads) -> AppResult<()> {
+        for user in snapshot.users {
+            state.insert_user(user.into()).await?;
+        }
+        for device in snapshot.devices {
+            state.insert_device(device).await?;
+        }
+        // Complete blobs refuse chunks, so they are only completed once their
+        // chunks are back in
+        let mut completed = Vec::new();
+        for mut blob in snapshot.blobs {
+            if let Some(completed_at) = blob.completed_at.take() {
+                completed.push((blob.id, completed_at));
+            }
+            state.insert_blob(blob).await?;
+        }
+        for chunk in snapshot.blob_chunks {
+            state.put_blob_chunk(chunk.blob_id, chunk.index, chunk.data).await?;
+        }
+        for (id, completed_at) in completed {
+            state.complete_blob(id, completed_at).await?;
+        }
+        // Clips go in after the blobs they refer to. Older snapshots may hold
+        // clips whose blob was deleted under them; those cannot be served.
+        for payload in snapshot.payloads {
+            payloads.insert(payload.hash.clone(), payload);
+        }
+        for clip in snapshot.clips {
+            let id = clip.id;
+            let restored = match hydrate(clip, payloads) {
+                Ok(clip) => state.insert_clip(clip).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = restored {
+                tracing::warn!("Skipping snapshot clip {}: {}", id, e);
+            }
+        }
+        for (user_id, seq) in snapshot.clip_seqs {
+            state.restore_clip_seq(user_id, seq).await;
+        }
+        for (token, expires_at) in snapshot.revoked_tokens {
+            state.revoke_token(&token, expires_at).await?;
+        }
+        for family in snapshot.token_families {
+            state.insert_token_family(family).await?;
+        }
+        Ok(())
+    }
+
+    async fn apply(state: &MemoryStore, mutation: Mutation, payloads: &mut Payloads) -> AppResult<()> {
+        match mutation {
+            Mutation::InsertUser(user) => { state.insert_user(user.into()).await?; }
+            Mutation::UpdateUser(user) => { state.update_user(user.into()).await?; }
+            Mutation::DeleteUser(id) => { state.delete_user(id).await?; }
+            Mutation::InsertDevice(device) => { state.insert_device(device).await?; }
+            Mutation::UpdateDevice(device) => { state.update_device(device).await?; }
+            Mutation::TouchDevice { id, last_seen } => { state.touch_device(id, last_seen).await?; }
+            Mutation::DeleteDevice(id) => { state.delete_device(id).await?; }
+            Mutation::InsertPayload(payload) => { payloads.insert(payload.hash.clone(), payload); }
+            Mutation::InsertClip(data) => { state.insert_clip(hydrate(data, payloads)?).await?; }
+            Mutation::DeleteClip(id) => { state.delete_clip(id).await?; }
+            Mutation::DeleteUserClips(user_id) => { state.delete_user_clips(user_id).await?; }
+            Mutation::DeleteClipsReceivedBefore(cutoff) => { state.delete_clips_received_before(cutoff).await?; }
+            Mutation::InsertBlob(blob) => { state.insert_blob(blob).await?; }
+            Mutation::PutBlobChunk(chunk) => { state.put_blob_chunk(chunk.blob_id, chunk.index, chunk.data).await?; }
+            Mutation::CompleteBlob { id, completed_at } => { state.complete_blob(id, completed_at).await?; }
+            Mutation::DeleteBlob(id) => { state.delete_blob(id).await?; }
+            Mutation::DeleteUserBlobs(user_id) => { state.delete_user_blobs(user_id).await?; }
+            Mutation::RevokeToken { token, expires_at } => { state.revoke_token(&token, expires_at).await?; }
+            Mutation::PruneRevokedTokens(now) => { state.prune_revoked_tokens(now).await?; }
+            Mutation::InsertTokenFamily(family) => { state.insert_token_family(family).await?; }
+            Mutation::RotateTokenFamily { id, expected_jti, new_jti, expires_at } => {
+                state.rotate_token_family(id, expected_jti, new_jti, expires_at).await?;
+            }
+            Mutation::RevokeTokenFamily(id) => { state.revoke_token_family(id).await?; }
+            Mutation::PruneTokenFamilies(now) => { state.prune_token_families(now).await?; }
+            Mutation::TouchTokenFamily { id, last_used_at } => { state.touch_token_family(id, last_used_at).await?; }
+        }
+        Ok(())
+    }
+
+    /// Writes a mutation to the journal; see `write_all`.
+    async fn write(&self, journal: &mut Journal, mutation: Mutation) -> AppResult<()> {
+        self.write_all(journal, vec![mutation]).await
+    }
+
+    /// Appends mutations with a single sync, so a snapshot never falls
+    /// between them. Callers hold the journal lock, check that the mutations
+    /// will apply, write them, and only then apply them to `state`.
+    ///
+    /// A compaction that has come due runs first, while `state` matches the
+    /// journal exactly; it only logs on failure, since nothing is lost by
+    /// keeping the longer journal.
+    async fn write_all(&self, journal: &mut Journal, mutations: Vec<Mutation>) -> AppResult<()> {
+        if journal.broken {
+            return Err(AppError::DatabaseError(
+                "Journal is read-only after a failed append; restart to recover".to_string(),
+            ));
+        }
+        if journal.records_since_snapshot >= self.compact_threshold {
+            if let Err(e) = self.compact_locked(journal).await {
+                tracing::warn!("Journal compaction failed: {}", e);
+            }
+        }
+
+        let mut frames = Vec::new();
+        let count = mutations.len();
+        for (offset, mutation) in mutations.into_iter().enumerate() {
+            let record = Record { seq: journal.next_seq + offset as u64, mutation };
+            frames.extend(encode_record(&record)?);
+        }
+
+        let file = &mut journal.file;
+        let written = async {
+            file.write_all(&frames).await?;
+            // `File` buffers writes and only reports their errors on flush
+            file.flush().await?;
+            file.sync_data().await
+        }
+        .await;
+        if let Err(e) = written {
+            // Cut off whatever part of the frames made it to the file
+            let (file, len) = (&mut journal.file, journal.len);
+            let truncated = async {
+                file.set_len(len).await?;
+                file.sync_all().await
+            }
+            .await;
+            if let Err(e) = truncated {
+                tracing::error!("Failed to roll back journal append, refusing further writes: {}", e);
+                journal.broken = true;
+            }
+            return Err(io_err("Failed to append to journal", e));
+        }
+        journal.len += frames.len() as u64;
+        journal.next_seq += count as u64;
+        journal.records_since_snapshot += count;
+        Ok(())
+    }
+
+    /// Folds the journal into a new snapshot and truncates it.
+    pub async fn compact(&self) -> AppResult<()> {
+        let mut journal = self.journal.lock().await;
+        self.compact_locked(&mut journal).await
+    }
+
+    async fn compact_locked(&self, journal: &mut Journal) -> AppResult<()> {
+        let contents = self.state.contents().await;
+        let snapshot = Snapshot {
+            last_seq: journal.next_seq - 1,
+            users: contents.users.into_iter().map(UserRecord::from).collect(),
+            devices: contents.devices,
+            clips: contents.clips,
+            payloads: contents
+                .payloads
+                .into_iter()
+                .map(|(hash, content, formats)| PayloadRecord { hash, content, formats })
+                .collect(),
+            clip_seqs: contents.clip_seqs,
+            blobs: contents.blobs,
+            blob_chunks: contents
+                .blob_chunks
+                .into_iter()
+                .map(|(blob_id, index, data)| BlobChunkRecord { blob_id, index, data })
+                .collect(),
+            revoked_tokens: contents.revoked_tokens,
+            token_families: contents.token_families,
+        };
+        let bytes = serde_json::to_vec(&snapshot)
+            .map_err(|e| AppError::InternalError(format!("Snapshot encoding failed: {}", e)))?;
+
+        // Write-then-rename so a crash never leaves a half-written snapshot
+        let tmp_path = journal.dir.join(format!("{}.tmp", SNAPSHOT_FILE));
+        let mut tmp = File::create(&tmp_path)
+            .await
+            .map_err(|e| io_err("Failed to create snapshot", e))?;
+        tmp.write_all(&bytes)
+            .await
+            .map_err(|e| io_err("Failed to write snapshot", e))?;
+        tmp.flush()
+            .await
+            .map_err(|e| io_err("Failed to write snapshot", e))?;
+        tmp.sync_all()
+            .await
+            .map_err(|e| io_err("Failed to sync snapshot", e))?;
+        tokio::fs::rename(&tmp_path, journal.dir.join(SNAPSHOT_FILE))
+            .await
+            .map_err(|e| io_err("Failed to install snapshot", e))?;
+
+        journal.file
+            .set_len(0)
+            .await
+            .map_err(|e| io_err("Failed to truncate journal", e))?;
+        journal.len = 0;
+        journal.file
+            .sync_all()
+            .await
+            .map_err(|e| io_err("Failed to sync journal", e))?;
+        journal.records_since_snapshot = 0;
+
+        tracing::info!("Compacted journal into snapshot at seq {}", snapshot.last_seq);
+        Ok(())
+    }
+}
+
+// Every mutator below holds the journal lock from its checks until the
+// change is applied: it rejects or skips mutations that would fail or change
+// nothing, writes the rest, and only then applies them to `state`.
+
+#[async_trait]
+impl UserStore for JournalStore {
+    async fn insert_user(&self, user: User) -> AppResult<User> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_user_by_username(&user.username).await?.is_some() {
+            return Err(AppError::UserAlreadyExists(user.username));
+        }
+        self.write(&mut journal, Mutation::InsertUser(user.clone().into())).await?;
+        self.state.insert_user(user).await
+    }
+
+    async fn get_user(&self, id: Uuid) -> AppResult<Option<User>> {
+        self.state.get_user(id).await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> AppResult<Option<User>> {
+        self.state.get_user_by_username(username).await
+    }
+
+    async fn list_users(&self) -> AppResult<Vec<User>> {
+        self.state.list_users().await
+    }
+
+    async fn count_users(&self) -> AppResult<usize> {
+        self.state.count_users().await
+    }
+
+    async fn update_user(&self, user: User) -> AppResult<User> {
+        let mut journal = self.journal.lock().await;
+        let existing = self.state.get_user(user.id).await?.ok_or(AppError::UserNotFound(user.id))?;
+        if existing.username != user.username && self.state.get_user_by_username(&user.username).await?.is_some() {
+            return Err(AppError::UserAlreadyExists(user.username));
+        }
+        self.write(&mut journal, Mutation::UpdateUser(user.clone().into())).await?;
+        self.state.update_user(user).await
+    }
+
+    async fn delete_user(&self, id: Uuid) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_user(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::DeleteUser(id)).await?;
+        self.state.delete_user(id).await
+    }
+}
+
+#[async_trait]
+impl DeviceStore for JournalStore {
+    async fn insert_device(&self, device: Device) -> AppResult<Device> {
+        let mut journal = self.journal.lock().await;
+        self.write(&mut journal, Mutation::InsertDevice(device.clone())).await?;
+        self.state.insert_device(device).await
+    }
+
+    async fn get_device(&self, id: Uuid) -> AppResult<Option<Device>> {
+        self.state.get_device(id).await
+    }
+
+    async fn list_user_devices(&self, user_id: Uuid) -> AppResult<Vec<Device>> {
+        self.state.list_user_devices(user_id).await
+    }
+
+    async fn update_device(&self, device: Device) -> AppResult<Device> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_device(device.id).await?.is_none() {
+            return Err(AppError::DeviceNotFound(device.id));
+        }
+        self.write(&mut journal, Mutation::UpdateDevice(device.clone())).await?;
+        self.state.update_device(device).await
+    }
+
+    async fn touch_device(&self, id: Uuid, last_seen: u64) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_device(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::TouchDevice { id, last_seen }).await?;
+        self.state.touch_device(id, last_seen).await
+    }
+
+    async fn delete_device(&self, id: Uuid) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_device(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::DeleteDevice(id)).await?;
+        self.state.delete_device(id).await
+    }
+}
+
+#[async_trait]
+impl ClipStore for JournalStore {
+    async fn insert_clip(&self, mut data: ClipboardData) -> AppResult<ClipboardData> {
+        let mut journal = self.journal.lock().await;
+        for blob_id in data.formats.iter().filter_map(|format| format.blob_id) {
+            if self.state.get_blob(blob_id).await?.is_none() {
+                return Err(AppError::BlobNotFound(blob_id));
+            }
+        }
+
+        // The payload is journaled only when no stored clip holds it yet;
+        // the clip record refers to it by hash either way. Replay assigns
+        // the record the same sequence number and entry the store does now.
+        data.content_hash = data.payload_hash();
+        let mut mutations = Vec::new();
+        if self.state.payload_refs(&data.content_hash).await? == 0 {
+            mutations.push(Mutation::InsertPayload(PayloadRecord {
+                hash: data.content_hash.clone(),
+                content: data.content.clone(),
+                formats: data.formats.clone(),
+            }));
+        }
+        mutations.push(Mutation::InsertClip(ClipboardData {
+            content: String::new(),
+            formats: Vec::new(),
+            ..data.clone()
+        }));
+        self.write_all(&mut journal, mutations).await?;
+        self.state.insert_clip(data).await
+    }
+
+    async fn get_clip(&self, id: Uuid) -> AppResult<Option<ClipboardData>> {
+        self.state.get_clip(id).await
+    }
+
+    async fn list_user_clips(&self, user_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        self.state.list_user_clips(user_id).await
+    }
+
+    async fn list_user_clips_after(&self, user_id: Uuid, after_seq: u64) -> AppResult<Vec<ClipboardData>> {
+        self.state.list_user_clips_after(user_id, after_seq).await
+    }
+
+    async fn list_device_clips(&self, device_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        self.state.list_device_clips(device_id).await
+    }
+
+    async fn delete_clip(&self, id: Uuid) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_clip(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::DeleteClip(id)).await?;
+        self.state.delete_clip(id).await
+    }
+
+    async fn delete_user_clips(&self, user_id: Uuid) -> AppResult<usize> {
+        let mut journal = self.journal.lock().await;
+        if self.state.list_user_clips(user_id).await?.is_empty() {
+            return Ok(0);
+        }
+        self.write(&mut journal, Mutation::DeleteUserClips(user_id)).await?;
+        self.state.delete_user_clips(user_id).await
+    }
+
+    async fn delete_clips_received_before(&self, cutoff: u64) -> AppResult<usize> {
+        let mut journal = self.journal.lock().await;
+        if self.state.count_clips_received_before(cutoff).await == 0 {
+            return Ok(0);
+        }
+        self.write(&mut journal, Mutation::DeleteClipsReceivedBefore(cutoff)).await?;
+        self.state.delete_clips_received_before(cutoff).await
+    }
+
+    async fn payload_refs(&self, content_hash: &str) -> AppResult<usize> {
+        self.state.payload_refs(content_hash).await
+    }
+}
+
+#[async_trait]
+impl BlobStore for JournalStore {
+    async fn insert_blob(&self, blob: Blob) -> AppResult<Blob> {
+        let mut journal = self.journal.lock().await;
+        self.write(&mut journal, Mutation::InsertBlob(blob.clone())).await?;
+        self.state.insert_blob(blob).await
+    }
+
+    async fn get_blob(&self, id: Uuid) -> AppResult<Option<Blob>> {
+        self.state.get_blob(id).await
+    }
+
+    async fn list_user_blobs(&self, user_id: Uuid) -> AppResult<Vec<Blob>> {
+        self.state.list_user_blobs(user_id).await
+    }
+
+    async fn put_blob_chunk(&self, id: Uuid, index: u32, data: Vec<u8>) -> AppResult<Blob> {
+        let mut journal = self.journal.lock().await;
+        let blob = self.state.get_blob(id).await?.ok_or(AppError::BlobNotFound(id))?;
+        if blob.is_complete() {
+            return Err(AppError::BlobComplete(id));
+        }
+        self.write(&mut journal, Mutation::PutBlobChunk(BlobChunkRecord { blob_id: id, index, data: data.clone() }))
+            .await?;
+        self.state.put_blob_chunk(id, index, data).await
+    }
+
+    async fn get_blob_chunk(&self, id: Uuid, index: u32) -> AppResult<Option<Vec<u8>>> {
+        self.state.get_blob_chunk(id, index).await
+    }
+
+    async fn complete_blob(&self, id: Uuid, completed_at: u64) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_blob(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::CompleteBlob { id, completed_at }).await?;
+        self.state.complete_blob(id, completed_at).await
+    }
+
+    async fn list_blobs_created_before(&self, cutoff: u64) -> AppResult<Vec<Blob>> {
+        self.state.list_blobs_created_before(cutoff).await
+    }
+
+    async fn delete_blob(&self, id: Uuid) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_blob(id).await?.is_none() {
+            return Ok(false);
+        }
+        if self.state.blob_in_use(id).await {
+            return Err(AppError::BlobInUse(id));
+        }
+        self.write(&mut journal, Mutation::DeleteBlob(id)).await?;
+        self.state.delete_blob(id).await
+    }
+
+    async fn delete_user_blobs(&self, user_id: Uuid) -> AppResult<usize> {
+        let mut journal = self.journal.lock().await;
+        if self.state.list_user_blobs(user_id).await?.is_empty() {
+            return Ok(0);
+        }
+        self.write(&mut journal, Mutation::DeleteUserBlobs(user_id)).await?;
+        self.state.delete_user_blobs(user_id).await
+    }
+}
+
+#[async_trait]
+impl TokenStore for JournalStore {
+    async fn revoke_token(&self, token: &str, expires_at: u64) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.is_token_revoked(token).await? {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::RevokeToken { token: token.to_string(), expires_at }).await?;
+        self.state.revoke_token(token, expires_at).await
+    }
+
+    async fn is_token_revoked(&self, token: &str) -> AppResult<bool> {
+        self.state.is_token_revoked(token).await
+    }
+
+    async fn prune_revoked_tokens(&self, now: u64) -> AppResult<usize> {
+        let mut journal = self.journal.lock().await;
+        if self.state.count_expired_revoked_tokens(now).await == 0 {
+            return Ok(0);
+        }
+        self.write(&mut journal, Mutation::PruneRevokedTokens(now)).await?;
+        self.state.prune_revoked_tokens(now).await
+    }
+
+    async fn insert_token_family(&self, family: TokenFamily) -> AppResult<TokenFamily> {
+        let mut journal = self.journal.lock().await;
+        self.write(&mut journal, Mutation::InsertTokenFamily(family.clone())).await?;
+        self.state.insert_token_family(family).await
+    }
+
+    async fn get_token_family(&self, id: Uuid) -> AppResult<Option<TokenFamily>> {
+        self.state.get_token_family(id).await
+    }
+
+    async fn list_user_token_families(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>> {
+        self.state.list_user_token_families(user_id).await
+    }
+
+    async fn touch_token_family(&self, id: Uuid, last_used_at: u64) -> AppResult<()> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_token_family(id).await?.is_none() {
+            return Ok(());
+        }
+        self.write(&mut journal, Mutation::TouchTokenFamily { id, last_used_at }).await?;
+        self.state.touch_token_family(id, last_used_at).await
+    }
+
+    async fn rotate_token_family(&self, id: Uuid, expected_jti: Uuid, new_jti: Uuid, expires_at: u64) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        let current = self
+            .state
+            .get_token_family(id)
+            .await?
+            .is_some_and(|family| !family.revoked && family.current_jti == expected_jti);
+        if !current {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::RotateTokenFamily { id, expected_jti, new_jti, expires_at }).await?;
+        self.state.rotate_token_family(id, expected_jti, new_jti, expires_at).await
+    }
+
+    async fn revoke_token_family(&self, id: Uuid) -> AppResult<bool> {
+        let mut journal = self.journal.lock().await;
+        if self.state.get_token_family(id).await?.is_none() {
+            return Ok(false);
+        }
+        self.write(&mut journal, Mutation::RevokeTokenFamily(id)).await?;
+        self.state.revoke_token_family(id).await
+    }
+
+    async fn prune_token_families(&self, now: u64) -> AppResult<usize> {
+        let mut journal = self.journal.lock().await;
+        if self.state.count_expired_token_families(now).await == 0 {
+            return Ok(0);
+        }
+        self.write(&mut journal, Mutation::PruneTokenFamilies(now)).await?;
+        self.state.prune_token_families(now).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    fn temp_journal_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("clipman-journal-{}", Uuid::new_v4()))
+    }
+
+    async fn journal_len(dir: &Path) -> u64 {
+        tokio::fs::metadata(dir.join(JOURNAL_FILE)).await.unwrap().len()
+    }
+
+    #[test]
+    fn test_crc32_reference_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[tokio::test]
+    async fn test_journal_store_conformance() {
+        let dir = temp_journal_dir();
+        conformance::run_all(&JournalStore::open(&dir, 1000).await.unwrap()).await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_replay_restores_state() {
+        let dir = temp_journal_dir();
+        let user = User::new("journaled".to_string(), "hash".to_string());
+        let device = Device::new("laptop".to_string(), user.id);
+        let kept = ClipboardData::new("kept".to_string(), device.id, user.id);
+        let deleted = ClipboardData::new("deleted".to_string(), device.id, user.id);
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.insert_user(user.clone()).await.unwrap();
+            store.insert_device(device.clone()).await.unwrap();
+            store.insert_clip(kept.clone()).await.unwrap();
+            store.insert_clip(deleted.clone()).await.unwrap();
+            store.delete_clip(deleted.id).await.unwrap();
+        }
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let loaded = store.get_user_by_username("journaled").await.unwrap().unwrap();
+        assert_eq!(loaded.password_hash, "hash");
+        assert!(store.get_device(device.id).await.unwrap().is_some());
+        let clips = store.list_user_clips(user.id).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].id, kept.id);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_truncated_tail_is_skipped_and_repaired() {
+        let dir = temp_journal_dir();
+        let user_id = Uuid::new_v4();
+        let first = ClipboardData::new("first".to_string(), Uuid::new_v4(), user_id);
+        let second = ClipboardData::new("second".to_string(), Uuid::new_v4(), user_id);
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.insert_clip(first.clone()).await.unwrap();
+        }
+        let intact_len = journal_len(&dir).await;
+
+        // Simulate a crash halfway through writing the second record
+        let frame = encode_record(&Record { seq: 2, mutation: Mutation::InsertClip(second) }).unwrap();
+        let mut file = OpenOptions::new().append(true).open(dir.join(JOURNAL_FILE)).await.unwrap();
+        file.write_all(&frame[..frame.len() / 2]).await.unwrap();
+        drop(file);
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let clips = store.list_user_clips(user_id).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].id, first.id);
+        assert_eq!(journal_len(&dir).await, intact_len);
+
+        // New writes land after the last intact record and survive a reopen
+        let third = ClipboardData::new("third".to_string(), Uuid::new_v4(), user_id);
+        store.insert_clip(third).await.unwrap();
+        drop(store);
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.list_user_clips(user_id).await.unwrap().len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_record_stops_replay() {
+        let dir = temp_journal_dir();
+        let user_id = Uuid::new_v4();
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            for i in 0..3 {
+                let data = ClipboardData::new(format!("clip {}", i), Uuid::new_v4(), user_id);
+                store.insert_clip(data).await.unwrap();
+            }
+        }
+
+        // Flip a byte in the last record's payload
+        let path = dir.join(JOURNAL_FILE);
+        let mut bytes = tokio::fs::read(&path).await.unwrap();
+        let last = bytes.len() - 2;
+        bytes[last] ^= 0xFF;
+        tokio::fs::write(&path, &bytes).await.unwrap();
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.list_user_clips(user_id).await.unwrap().len(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_compaction_writes_snapshot_and_truncates_journal() {
+        let dir = temp_journal_dir();
+        let user_id = Uuid::new_v4();
+
+        {
+            let store = JournalStore::open(&dir, 3).await.unwrap();
+            for i in 0..4 {
+                let data = ClipboardData::new(format!("clip {}", i), Uuid::new_v4(), user_id);
+                store.insert_clip(data).await.unwrap();
+            }
+            store.revoke_token("revoked", u64::MAX).await.unwrap();
+        }
+
+        assert!(dir.join(SNAPSHOT_FILE).exists());
+        // Each clip with a new payload takes two records; the four clips'
+        // eight were folded into snapshots and only the revocation remains
+        let (records, _) = decode_records(&tokio::fs::read(dir.join(JOURNAL_FILE)).await.unwrap());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].seq, 9);
+
+        let store = JournalStore::open(&dir, 3).await.unwrap();
+        assert_eq!(store.list_user_clips(user_id).await.unwrap().len(), 4);
+        assert!(store.is_token_revoked("revoked").await.unwrap());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_clip_seqs_survive_deletion_and_compaction() {
+        let dir = temp_journal_dir();
+        let user_id = Uuid::new_v4();
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            for i in 0..3 {
+                let data = ClipboardData::new(format!("clip {}", i), Uuid::new_v4(), user_id);
+                store.insert_clip(data).await.unwrap();
+            }
+            store.delete_user_clips(user_id).await.unwrap();
+            store.compact().await.unwrap();
+        }
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let data = ClipboardData::new("fresh".to_string(), Uuid::new_v4(), user_id);
+        assert_eq!(store.insert_clip(data).await.unwrap().seq, 4);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_repeat_copies_replay_as_one_entry() {
+        let dir = temp_journal_dir();
+        let user_id = Uuid::new_v4();
+        let device_id = Uuid::new_v4();
+
+        let first = {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            let first = store
+                .insert_clip(ClipboardData::new("same".to_string(), device_id, user_id))
+                .await
+                .unwrap();
+            store.compact().await.unwrap();
+            store.insert_clip(ClipboardData::new("same".to_string(), device_id, user_id)).await.unwrap();
+            first
+        };
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let clips = store.list_user_clips(user_id).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!((clips[0].id, clips[0].seq), (first.id, 2));
+        assert_eq!(store.payload_refs(&first.content_hash).await.unwrap(), 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_failed_append_changes_nothing() {
+        let dir = temp_journal_dir();
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let kept = store.insert_user(User::new("kept".to_string(), "hash".to_string())).await.unwrap();
+        let len = journal_len(&dir).await;
+
+        // A read-only handle fails the append and cannot be truncated either
+        store.journal.lock().await.file = File::open(dir.join(JOURNAL_FILE)).await.unwrap();
+        let lost = User::new("lost".to_string(), "hash".to_string());
+        assert!(store.insert_user(lost.clone()).await.is_err());
+        assert!(store.get_user(lost.id).await.unwrap().is_none());
+        assert!(store.get_user_by_username("lost").await.unwrap().is_none());
+
+        // Unsure what reached the file, the store refuses to write behind it
+        let refused = store.insert_user(User::new("later".to_string(), "hash".to_string())).await;
+        assert!(matches!(refused, Err(AppError::DatabaseError(_))), "{:?}", refused);
+        assert_eq!(store.count_users().await.unwrap(), 1);
+        drop(store);
+
+        assert_eq!(journal_len(&dir).await, len);
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.list_users().await.unwrap().iter().map(|u| u.id).collect::<Vec<_>>(), vec![kept.id]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_failed_compaction_keeps_the_write() {
+        let dir = temp_journal_dir();
+        let store = JournalStore::open(&dir, 1).await.unwrap();
+        store.revoke_token("first", u64::MAX).await.unwrap();
+
+        // A directory where the snapshot is staged makes compaction fail
+        tokio::fs::create_dir_all(dir.join(format!("{}.tmp", SNAPSHOT_FILE))).await.unwrap();
+        assert!(store.revoke_token("second", u64::MAX).await.unwrap());
+        drop(store);
+
+        let store = JournalStore::open(&dir, 1).await.unwrap();
+        assert!(store.is_token_revoked("first").await.unwrap());
+        assert!(store.is_token_revoked("second").await.unwrap());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_payloads_are_journaled_once() {
+        let dir = temp_journal_dir();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let content = "x".repeat(64 * 1024);
+        let copy = |user_id| ClipboardData::new(content.clone(), Uuid::new_v4(), user_id);
+
+        let hash = {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            let first = store.insert_clip(copy(alice)).await.unwrap();
+            for user_id in [alice, alice, bob] {
+                store.insert_clip(copy(user_id)).await.unwrap();
+            }
+            assert!(journal_len(&dir).await < 2 * content.len() as u64);
+
+            store.compact().await.unwrap();
+            let snapshot = tokio::fs::metadata(dir.join(SNAPSHOT_FILE)).await.unwrap().len();
+            assert!(snapshot < 2 * content.len() as u64);
+            store.insert_clip(copy(bob)).await.unwrap();
+            first.content_hash
+        };
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.payload_refs(&hash).await.unwrap(), 2);
+        for user_id in [alice, bob] {
+            let clips = store.list_user_clips(user_id).await.unwrap();
+            assert_eq!(clips.len(), 1);
+            assert_eq!(clips[0].content, content);
+        }
+
+        // The last reference going frees the payload for good
+        store.delete_user_clips(alice).await.unwrap();
+        store.delete_user_clips(bob).await.unwrap();
+        drop(store);
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.payload_refs(&hash).await.unwrap(), 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_inline_clip_records_still_replay() {
+        let dir = temp_journal_dir();
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // Written before payloads were journaled on their own
+        let legacy = ClipboardData::new("old".to_string(), Uuid::new_v4(), alice);
+        let frame = encode_record(&Record { seq: 1, mutation: Mutation::InsertClip(legacy.clone()) }).unwrap();
+        tokio::fs::write(dir.join(JOURNAL_FILE), frame).await.unwrap();
+
+        let hash = {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            let shared = store
+                .insert_clip(ClipboardData::new("old".to_string(), Uuid::new_v4(), bob))
+                .await
+                .unwrap();
+            shared.content_hash
+        };
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.payload_refs(&hash).await.unwrap(), 2);
+        assert_eq!(store.get_clip(legacy.id).await.unwrap().unwrap().content, "old");
+        assert_eq!(store.list_user_clips(bob).await.unwrap()[0].content, "old");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_blobs_survive_replay_and_compaction() {
+        let dir = temp_journal_dir();
+        let blob = Blob::new(Uuid::new_v4(), 6, 4);
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.insert_blob(blob.clone()).await.unwrap();
+            store.put_blob_chunk(blob.id, 0, vec![0, 1, 2, 255]).await.unwrap();
+        }
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.compact().await.unwrap();
+            store.put_blob_chunk(blob.id, 1, vec![7, 8]).await.unwrap();
+            store.complete_blob(blob.id, 30).await.unwrap();
+            store.compact().await.unwrap();
+        }
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        let restored = store.get_blob(blob.id).await.unwrap().unwrap();
+        assert_eq!(restored.received, vec![0, 1]);
+        assert_eq!(restored.completed_at, Some(30));
+        assert_eq!(store.get_blob_chunk(blob.id, 0).await.unwrap(), Some(vec![0, 1, 2, 255]));
+        assert_eq!(store.get_blob_chunk(blob.id, 1).await.unwrap(), Some(vec![7, 8]));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_crash_between_snapshot_and_truncate() {
+        let dir = temp_journal_dir();
+        let user = User::new("once".to_string(), "hash".to_string());
+
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.insert_user(user.clone()).await.unwrap();
+        }
+        let journal = tokio::fs::read(dir.join(JOURNAL_FILE)).await.unwrap();
+
+        // Compact, then put the old journal back as if truncation never happened
+        {
+            let store = JournalStore::open(&dir, 1000).await.unwrap();
+            store.compact().await.unwrap();
+        }
+        tokio::fs::write(dir.join(JOURNAL_FILE), &journal).await.unwrap();
+
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.count_users().await.unwrap(), 1);
+
+        // Sequence numbers continue after the snapshot
+        let other = User::new("twice".to_string(), "hash".to_string());
+        store.insert_user(other).await.unwrap();
+        drop(store);
+        let store = JournalStore::open(&dir, 1000).await.unwrap();
+        assert_eq!(store.count_users().await.unwrap(), 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,582 @@
+use async_trait::async_trait;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    models::{User, Device, ClipboardData, ClipFormat, Blob, TokenFamily},
+};
+use super::{UserStore, DeviceStore, ClipStore, BlobStore, TokenStore};
+
+#[derive(Default)]
+struct UserTables {
+    users: HashMap<Uuid, User>,
+    usernames: HashMap<String, Uuid>,  // For username lookups
+}
+
+/// A payload shared by every clip with its content hash.
+struct Payload {
+    content: String,
+    formats: Vec<ClipFormat>,
+    refs: usize,
+}
+
+#[derive(Default)]
+struct ClipTables {
+    clips: HashMap<Uuid, ClipboardData>,  // Payload fields left empty
+    payloads: HashMap<String, Payload>,
+    by_payload: HashMap<(Uuid, String), Uuid>,  // (user_id, content_hash) -> clip id
+    last_seqs: HashMap<Uuid, u64>,  // user_id -> highest seq handed out
+}
+
+impl ClipTables {
+    /// Fills a stored clip back in with its payload.
+    fn hydrate(&self, data: &ClipboardData) -> ClipboardData {
+        let mut data = data.clone();
+        if let Some(payload) = self.payloads.get(&data.content_hash) {
+            data.content = payload.content.clone();
+            data.formats = payload.formats.clone();
+        }
+        data
+    }
+
+    fn remove(&mut self, id: Uuid) -> bool {
+        let Some(data) = self.clips.remove(&id) else {
+            return false;
+        };
+        self.by_payload.remove(&(data.user_id, data.content_hash.clone()));
+        if let Some(payload) = self.payloads.get_mut(&data.content_hash) {
+            payload.refs -= 1;
+            if payload.refs == 0 {
+                self.payloads.remove(&data.content_hash);
+            }
+        }
+        true
+    }
+
+    /// Whether any stored payload has a format held in blob `id`.
+    fn refers_to_blob(&self, id: Uuid) -> bool {
+        self.payloads
+            .values()
+            .any(|payload| payload.formats.iter().any(|format| format.blob_id == Some(id)))
+    }
+
+    fn remove_where(&mut self, predicate: impl Fn(&ClipboardData) -> bool) -> usize {
+        let ids: Vec<Uuid> = self
+            .clips
+            .values()
+            .filter(|data| predicate(data))
+            .map(|data| data.id)
+            .collect();
+        for id in &ids {
+            self.remove(*id);
+        }
+        ids.len()
+    }
+}
+
+#[derive(Default)]
+struct BlobTables {
+    blobs: HashMap<Uuid, Blob>,
+    chunks: HashMap<(Uuid, u32), Vec<u8>>,
+}
+
+/// Process-local store backed by hash maps. Everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryStore {
+    users: RwLock<UserTables>,
+    devices: RwLock<HashMap<Uuid, Device>>,
+    clips: RwLock<ClipTables>,
+    blobs: RwLock<BlobTables>,
+    revoked_tokens: RwLock<HashMap<String, u64>>,
+    token_families: RwLock<HashMap<Uuid, TokenFamily>>,
+}
+
+/// Full copy of a `MemoryStore`, used by backends that persist it wholesale.
+#[derive(Default)]
+pub(crate) struct MemoryContents {
+    pub users: Vec<User>,
+    pub devices: Vec<Device>,
+    pub clips: Vec<ClipboardData>,  // Payload fields left empty
+    pub payloads: Vec<(String, String, Vec<ClipFormat>)>,  // (content_hash, content, formats)
+    pub clip_seqs: Vec<(Uuid, u64)>,
+    pub blobs: Vec<Blob>,
+    pub blob_chunks: Vec<(Uuid, u32, Vec<u8>)>,
+    pub revoked_tokens: Vec<(String, u64)>,
+    pub token_families: Vec<TokenFamily>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) async fn contents(&self) -> MemoryContents {
+        let clip_tables = self.clips.read().await;
+        let blob_tables = self.blobs.read().await;
+        MemoryContents {
+            users: self.users.read().await.users.values().cloned().collect(),
+            devices: self.devices.read().await.values().cloned().collect(),
+            clips: clip_tables.clips.values().cloned().collect(),
+            payloads: clip_tables
+                .payloads
+                .iter()
+                .map(|(hash, payload)| (hash.clone(), payload.content.clone(), payload.formats.clone()))
+                .collect(),
+            clip_seqs: clip_tables
+                .last_seqs
+                .iter()
+                .map(|(user_id, seq)| (*user_id, *seq))
+                .collect(),
+            blobs: blob_tables.blobs.values().cloned().collect(),
+            blob_chunks: blob_tables
+                .chunks
+                .iter()
+                .map(|((id, index), data)| (*id, *index, data.clone()))
+                .collect(),
+            revoked_tokens: self
+                .revoked_tokens
+                .read()
+                .await
+                .iter()
+                .map(|(token, expires_at)| (token.clone(), *expires_at))
+                .collect(),
+            token_families: self.token_families.read().await.values().cloned().collect(),
+        }
+    }
+
+    /// Raises a user's sequence counter to at least `seq`, so numbers handed
+    /// out before the clips were deleted are never reused.
+    pub(crate) async fn restore_clip_seq(&self, user_id: Uuid, seq: u64) {
+        let mut tables = self.clips.write().await;
+        let last_seq = tables.last_seqs.entry(user_id).or_default();
+        *last_seq = (*last_seq).max(seq);
+    }
+
+    // What a bulk mutation would remove, so the journal can skip writing
+    // mutations that change nothing
+
+    pub(crate) async fn count_clips_received_before(&self, cutoff: u64) -> usize {
+        let tables = self.clips.read().await;
+        tables.clips.values().filter(|data| data.received_at < cutoff).count()
+    }
+
+    pub(crate) async fn count_expired_revoked_tokens(&self, now: u64) -> usize {
+        let revoked = self.revoked_tokens.read().await;
+        revoked.values().filter(|expires_at| **expires_at <= now).count()
+    }
+
+    pub(crate) async fn count_expired_token_families(&self, now: u64) -> usize {
+        let families = self.token_families.read().await;
+        families.values().filter(|family| family.expires_at <= now).count()
+    }
+
+    /// Whether a clip refers to blob `id`, which keeps `delete_blob` from
+    /// removing it.
+    pub(crate) async fn blob_in_use(&self, id: Uuid) -> bool {
+        self.clips.read().await.refers_to_blob(id)
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryStore {
+    async fn insert_user(&self, user: User) -> AppResult<User> {
+        let mut tables = self.users.write().await;
+        if tables.usernames.contains_key(&user.username) {
+            return Err(AppError::UserAlreadyExists(user.username));
+        }
+        tables.usernames.insert(user.username.clone(), user.id);
+        tables.users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn get_user(&self, id: Uuid) -> AppResult<Option<User>> {
+        Ok(self.users.read().await.users.get(&id).cloned())
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> AppResult<Option<User>> {
+        let tables = self.users.read().await;
+        Ok(tables
+            .usernames
+            .get(username)
+            .and_then(|id| tables.users.get(id))
+            .cloned())
+    }
+
+    async fn list_users(&self) -> AppResult<Vec<User>> {
+        let tables = self.users.read().await;
+        let mut users: Vec<User> = tables.users.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        Ok(users)
+    }
+
+    async fn count_users(&self) -> AppResult<usize> {
+        Ok(self.users.read().await.users.len())
+    }
+
+    async fn update_user(&self, user: User) -> AppResult<User> {
+        let mut tables = self.users.write().await;
+        let old_username = tables
+            .users
+            .get(&user.id)
+            .map(|existing| existing.username.clone())
+            .ok_or(AppError::UserNotFound(user.id))?;
+
+        if old_username != user.username {
+            if tables.usernames.contains_key(&user.username) {
+                return Err(AppError::UserAlreadyExists(user.username));
+            }
+            tables.usernames.remove(&old_username);
+            tables.usernames.insert(user.username.clone(), user.id);
+        }
+
+        tables.users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn delete_user(&self, id: Uuid) -> AppResult<bool> {
+        let mut tables = self.users.write().await;
+        match tables.users.remove(&id) {
+            Some(user) => {
+                tables.usernames.remove(&user.username);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl DeviceStore for MemoryStore {
+    async fn insert_device(&self, device: Device) -> AppResult<Device> {
+        self.devices.write().await.insert(device.id, device.clone());
+        Ok(device)
+    }
+
+    async fn get_device(&self, id: Uuid) -> AppResult<Option<Device>> {
+        Ok(self.devices.read().await.get(&id).cloned())
+    }
+
+    async fn list_user_devices(&self, user_id: Uuid) -> AppResult<Vec<Device>> {
+        let devices = self.devices.read().await;
+        let mut user_devices: Vec<Device> = devices
+            .values()
+            .filter(|device| device.user_id == user_id)
+            .cloned()
+            .collect();
+        user_devices.sort_by_key(|device| device.created_at);
+        Ok(user_devices)
+    }
+
+    async fn update_device(&self, device: Device) -> AppResult<Device> {
+        let mut devices = self.devices.write().await;
+        if !devices.contains_key(&device.id) {
+            return Err(AppError::DeviceNotFound(device.id));
+        }
+        devices.insert(device.id, device.clone());
+        Ok(device)
+    }
+
+    async fn touch_device(&self, id: Uuid, last_seen: u64) -> AppResult<bool> {
+        match self.devices.write().await.get_mut(&id) {
+            Some(device) => {
+                device.last_seen = device.last_seen.max(last_seen);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn delete_device(&self, id: Uuid) -> AppResult<bool> {
+        Ok(self.devices.write().await.remove(&id).is_some())
+    }
+}
+
+#[async_trait]
+impl ClipStore for MemoryStore {
+    async fn insert_clip(&self, mut data: ClipboardData) -> AppResult<ClipboardData> {
+        let mut tables = self.clips.write().await;
+        {
+            // Lock order is clips, then blobs, as in `delete_blob`
+            let blob_tables = self.blobs.read().await;
+            if let Some(blob_id) = data
+                .formats
+                .iter()
+                .filter_map(|format| format.blob_id)
+                .find(|blob_id| !blob_tables.blobs.contains_key(blob_id))
+            {
+                return Err(AppError::BlobNotFound(blob_id));
+            }
+        }
+        data.content_hash = data.payload_hash();
+        let last_seq = tables.last_seqs.entry(data.user_id).or_default();
+        if data.seq == 0 {
+            data.seq = *last_seq + 1;
+        }
+        *last_seq = (*last_seq).max(data.seq);
+
+        let key = (data.user_id, data.content_hash.clone());
+        match tables.by_payload.get(&key) {
+            Some(existing) => data.id = *existing,
+            None => {
+                tables.remove(data.id);
+                tables
+                    .payloads
+                    .entry(data.content_hash.clone())
+                    .or_insert_with(|| Payload {
+                        content: data.content.clone(),
+                        formats: data.formats.clone(),
+                        refs: 0,
+                    })
+                    .refs += 1;
+                tables.by_payload.insert(key, data.id);
+            }
+        }
+        let stored = ClipboardData {
+            content: String::new(),
+            formats: Vec::new(),
+            ..data.clone()
+        };
+        tables.clips.insert(data.id, stored);
+        Ok(data)
+    }
+
+    async fn get_clip(&self, id: Uuid) -> AppResult<Option<ClipboardData>> {
+        let tables = self.clips.read().await;
+        Ok(tables.clips.get(&id).map(|data| tables.hydrate(data)))
+    }
+
+    async fn list_user_clips(&self, user_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        let tables = self.clips.read().await;
+        let mut user_clips: Vec<ClipboardData> = tables
+            .clips
+            .values()
+            .filter(|data| data.user_id == user_id)
+            .map(|data| tables.hydrate(data))
+            .collect();
+        user_clips.sort_by_key(|data| Reverse(data.received_at));
+        Ok(user_clips)
+    }
+
+    async fn list_user_clips_after(&self, user_id: Uuid, after_seq: u64) -> AppResult<Vec<ClipboardData>> {
+        let tables = self.clips.read().await;
+        let mut user_clips: Vec<ClipboardData> = tables
+            .clips
+            .values()
+            .filter(|data| data.user_id == user_id && data.seq > after_seq)
+            .map(|data| tables.hydrate(data))
+            .collect();
+        user_clips.sort_by_key(|data| data.seq);
+        Ok(user_clips)
+    }
+
+    async fn list_device_clips(&self, device_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        let tables = self.clips.read().await;
+        let mut device_clips: Vec<ClipboardData> = tables
+            .clips
+            .values()
+            .filter(|data| data.device_id == device_id)
+            .map(|data| tables.hydrate(data))
+            .collect();
+        device_clips.sort_by_key(|data| Reverse(data.received_at));
+        Ok(device_clips)
+    }
+
+    async fn delete_clip(&self, id: Uuid) -> AppResult<bool> {
+        Ok(self.clips.write().await.remove(id))
+    }
+
+    async fn delete_user_clips(&self, user_id: Uuid) -> AppResult<usize> {
+        Ok(self.clips.write().await.remove_where(|data| data.user_id == user_id))
+    }
+
+    async fn delete_clips_received_before(&self, cutoff: u64) -> AppResult<usize> {
+        Ok(self.clips.write().await.remove_where(|data| data.received_at < cutoff))
+    }
+
+    async fn payload_refs(&self, content_hash: &str) -> AppResult<usize> {
+        Ok(self
+            .clips
+            .read()
+            .await
+            .payloads
+            .get(content_hash)
+            .map_or(0, |payload| payload.refs))
+    }
+}
+
+#[async_trait]
+impl BlobStore for MemoryStore {
+    async fn insert_blob(&self, blob: Blob) -> AppResult<Blob> {
+        self.blobs.write().await.blobs.insert(blob.id, blob.clone());
+        Ok(blob)
+    }
+
+    async fn get_blob(&self, id: Uuid) -> AppResult<Option<Blob>> {
+        Ok(self.blobs.read().await.blobs.get(&id).cloned())
+    }
+
+    async fn list_user_blobs(&self, user_id: Uuid) -> AppResult<Vec<Blob>> {
+        let tables = self.blobs.read().await;
+        let mut user_blobs: Vec<Blob> = tables
+            .blobs
+            .values()
+            .filter(|blob| blob.user_id == user_id)
+            .cloned()
+            .collect();
+        user_blobs.sort_by_key(|blob| blob.created_at);
+        Ok(user_blobs)
+    }
+
+    async fn put_blob_chunk(&self, id: Uuid, index: u32, data: Vec<u8>) -> AppResult<Blob> {
+        let mut tables = self.blobs.write().await;
+        let blob = tables.blobs.get_mut(&id).ok_or(AppError::BlobNotFound(id))?;
+        if blob.is_complete() {
+            return Err(AppError::BlobComplete(id));
+        }
+        if let Err(position) = blob.received.binary_search(&index) {
+            blob.received.insert(position, index);
+        }
+        let blob = blob.clone();
+        tables.chunks.insert((id, index), data);
+        Ok(blob)
+    }
+
+    async fn get_blob_chunk(&self, id: Uuid, index: u32) -> AppResult<Option<Vec<u8>>> {
+        Ok(self.blobs.read().await.chunks.get(&(id, index)).cloned())
+    }
+
+    async fn complete_blob(&self, id: Uuid, completed_at: u64) -> AppResult<bool> {
+        match self.blobs.write().await.blobs.get_mut(&id) {
+            Some(blob) => {
+                blob.completed_at = Some(completed_at);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn list_blobs_created_before(&self, cutoff: u64) -> AppResult<Vec<Blob>> {
+        let tables = self.blobs.read().await;
+        Ok(tables
+            .blobs
+            .values()
+            .filter(|blob| blob.created_at < cutoff)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_blob(&self, id: Uuid) -> AppResult<bool> {
+        let clip_tables = self.clips.read().await;
+        let mut tables = self.blobs.write().await;
+        if !tables.blobs.contains_key(&id) {
+            return Ok(false);
+        }
+        if clip_tables.refers_to_blob(id) {
+            return Err(AppError::BlobInUse(id));
+        }
+        tables.blobs.remove(&id);
+        tables.chunks.retain(|(blob_id, _), _| *blob_id != id);
+        Ok(true)
+    }
+
+    async fn delete_user_blobs(&self, user_id: Uuid) -> AppResult<usize> {
+        let mut tables = self.blobs.write().await;
+        let initial_len = tables.blobs.len();
+        tables.blobs.retain(|_, blob| blob.user_id != user_id);
+        let BlobTables { blobs, chunks } = &mut *tables;
+        chunks.retain(|(blob_id, _), _| blobs.contains_key(blob_id));
+        Ok(initial_len - tables.blobs.len())
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryStore {
+    async fn revoke_token(&self, token: &str, expires_at: u64) -> AppResult<bool> {
+        let mut revoked = self.revoked_tokens.write().await;
+        if revoked.contains_key(token) {
+            return Ok(false);
+        }
+        revoked.insert(token.to_string(), expires_at);
+        Ok(true)
+    }
+
+    async fn is_token_revoked(&self, token: &str) -> AppResult<bool> {
+        Ok(self.revoked_tokens.read().await.contains_key(token))
+    }
+
+    async fn prune_revoked_tokens(&self, now: u64) -> AppResult<usize> {
+        let mut revoked = self.revoked_tokens.write().await;
+        let initial_len = revoked.len();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        Ok(initial_len - revoked.len())
+    }
+
+    async fn insert_token_family(&self, family: TokenFamily) -> AppResult<TokenFamily> {
+        self.token_families.write().await.insert(family.id, family.clone());
+        Ok(family)
+    }
+
+    async fn get_token_family(&self, id: Uuid) -> AppResult<Option<TokenFamily>> {
+        Ok(self.token_families.read().await.get(&id).cloned())
+    }
+
+    async fn list_user_token_families(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>> {
+        let families = self.token_families.read().await;
+        let mut user_families: Vec<TokenFamily> = families
+            .values()
+            .filter(|family| family.user_id == user_id)
+            .cloned()
+            .collect();
+        user_families.sort_by_key(|family| family.created_at);
+        Ok(user_families)
+    }
+
+    async fn touch_token_family(&self, id: Uuid, last_used_at: u64) -> AppResult<()> {
+        if let Some(family) = self.token_families.write().await.get_mut(&id) {
+            family.last_used_at = family.last_used_at.max(last_used_at);
+        }
+        Ok(())
+    }
+
+    async fn rotate_token_family(&self, id: Uuid, expected_jti: Uuid, new_jti: Uuid, expires_at: u64) -> AppResult<bool> {
+        let mut families = self.token_families.write().await;
+        match families.get_mut(&id) {
+            Some(family) if !family.revoked && family.current_jti == expected_jti => {
+                family.current_jti = new_jti;
+                family.expires_at = expires_at;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn revoke_token_family(&self, id: Uuid) -> AppResult<bool> {
+        match self.token_families.write().await.get_mut(&id) {
+            Some(family) => {
+                family.revoked = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn prune_token_families(&self, now: u64) -> AppResult<usize> {
+        let mut families = self.token_families.write().await;
+        let initial_len = families.len();
+        families.retain(|_, family| family.expires_at > now);
+        Ok(initial_len - families.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    #[tokio::test]
+    async fn test_memory_store_conformance() {
+        conformance::run_all(&MemoryStore::new()).await;
+    }
+}
@@ -0,0 +1,145 @@
+mod memory;
+mod sqlite;
+mod journal;
+
+#[cfg(test)]
+pub(crate) mod conformance;
+
+use async_trait::async_trait;
+use std::sync::Arc;
+use uuid::Uuid;
+use crate::{
+    config::{StorageBackend, StorageConfig},
+    error::AppResult,
+    models::{User, Device, ClipboardData, Blob, TokenFamily},
+};
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+pub use journal::JournalStore;
+
+// Storage backends implement every trait below; services only depend on the
+// slice they need, so a backend can be swapped without touching handlers.
+
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    /// Inserts a new user, failing with `UserAlreadyExists` if the username is taken.
+    async fn insert_user(&self, user: User) -> AppResult<User>;
+    async fn get_user(&self, id: Uuid) -> AppResult<Option<User>>;
+    async fn get_user_by_username(&self, username: &str) -> AppResult<Option<User>>;
+    /// Returns all users sorted by username.
+    async fn list_users(&self) -> AppResult<Vec<User>>;
+    async fn count_users(&self) -> AppResult<usize>;
+    /// Replaces a stored user, keeping the username index in sync.
+    async fn update_user(&self, user: User) -> AppResult<User>;
+    /// Returns `false` if the user did not exist.
+    async fn delete_user(&self, id: Uuid) -> AppResult<bool>;
+}
+
+#[async_trait]
+pub trait DeviceStore: Send + Sync {
+    async fn insert_device(&self, device: Device) -> AppResult<Device>;
+    async fn get_device(&self, id: Uuid) -> AppResult<Option<Device>>;
+    /// Returns a user's devices, oldest first.
+    async fn list_user_devices(&self, user_id: Uuid) -> AppResult<Vec<Device>>;
+    async fn update_device(&self, device: Device) -> AppResult<Device>;
+    /// Moves `last_seen` forward to `last_seen`, leaving the rest of the
+    /// device alone. Returns `false` if the device did not exist.
+    async fn touch_device(&self, id: Uuid, last_seen: u64) -> AppResult<bool>;
+    /// Returns `false` if the device did not exist.
+    async fn delete_device(&self, id: Uuid) -> AppResult<bool>;
+}
+
+#[async_trait]
+pub trait ClipStore: Send + Sync {
+    /// Inserts a clip. A `seq` of 0 is replaced with the user's next sequence
+    /// number; sequence numbers never go backwards, even after deletions.
+    ///
+    /// The payload is stored once per `content_hash`, which the store sets.
+    /// If the user already has a clip with the same payload, that entry is
+    /// bumped instead: it keeps its id but takes the new clip's device,
+    /// timestamps, targets and sequence number.
+    ///
+    /// Fails with `BlobNotFound` if a format names a blob that does not
+    /// exist, so a blob cannot be deleted out from under a clip being saved.
+    async fn insert_clip(&self, data: ClipboardData) -> AppResult<ClipboardData>;
+    async fn get_clip(&self, id: Uuid) -> AppResult<Option<ClipboardData>>;
+    /// Returns a user's clips, newest first.
+    async fn list_user_clips(&self, user_id: Uuid) -> AppResult<Vec<ClipboardData>>;
+    /// Returns a user's clips with `seq` greater than `after_seq`, oldest first.
+    async fn list_user_clips_after(&self, user_id: Uuid, after_seq: u64) -> AppResult<Vec<ClipboardData>>;
+    /// Returns a device's clips, newest first.
+    async fn list_device_clips(&self, device_id: Uuid) -> AppResult<Vec<ClipboardData>>;
+    /// Returns `false` if the clip did not exist.
+    async fn delete_clip(&self, id: Uuid) -> AppResult<bool>;
+    /// Returns the number of clips removed.
+    async fn delete_user_clips(&self, user_id: Uuid) -> AppResult<usize>;
+    /// Removes clips received strictly before `cutoff` (unix seconds).
+    async fn delete_clips_received_before(&self, cutoff: u64) -> AppResult<usize>;
+    /// Number of clips sharing the payload with `content_hash`. The payload
+    /// is freed when this drops to 0.
+    async fn payload_refs(&self, content_hash: &str) -> AppResult<usize>;
+}
+
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    async fn insert_blob(&self, blob: Blob) -> AppResult<Blob>;
+    async fn get_blob(&self, id: Uuid) -> AppResult<Option<Blob>>;
+    /// Returns a user's blobs, finished or not, oldest first.
+    async fn list_user_blobs(&self, user_id: Uuid) -> AppResult<Vec<Blob>>;
+    /// Stores chunk `index`, replacing an earlier upload of it, and returns
+    /// the blob with the chunk recorded. Fails with `BlobNotFound`, or with
+    /// `BlobComplete` once the blob has been completed.
+    async fn put_blob_chunk(&self, id: Uuid, index: u32, data: Vec<u8>) -> AppResult<Blob>;
+    async fn get_blob_chunk(&self, id: Uuid, index: u32) -> AppResult<Option<Vec<u8>>>;
+    /// Returns `false` if the blob did not exist.
+    async fn complete_blob(&self, id: Uuid, completed_at: u64) -> AppResult<bool>;
+    /// Returns blobs created strictly before `cutoff` (unix seconds).
+    async fn list_blobs_created_before(&self, cutoff: u64) -> AppResult<Vec<Blob>>;
+    /// Removes a blob with its chunks. Fails with `BlobInUse` while a clip
+    /// refers to it; returns `false` if it did not exist.
+    async fn delete_blob(&self, id: Uuid) -> AppResult<bool>;
+    /// Removes all of a user's blobs, referenced or not. Returns the number
+    /// of blobs removed.
+    async fn delete_user_blobs(&self, user_id: Uuid) -> AppResult<usize>;
+}
+
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// Blacklists a token until `expires_at` (unix seconds). Returns `false`
+    /// if it already was, so exactly one caller gets to spend a token.
+    async fn revoke_token(&self, token: &str, expires_at: u64) -> AppResult<bool>;
+    async fn is_token_revoked(&self, token: &str) -> AppResult<bool>;
+    /// Drops blacklist entries whose token has expired by `now`.
+    async fn prune_revoked_tokens(&self, now: u64) -> AppResult<usize>;
+    async fn insert_token_family(&self, family: TokenFamily) -> AppResult<TokenFamily>;
+    async fn get_token_family(&self, id: Uuid) -> AppResult<Option<TokenFamily>>;
+    /// Returns a user's families, revoked or not, oldest first.
+    async fn list_user_token_families(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>>;
+    /// Records that a token from the family was used at `last_used_at`.
+    async fn touch_token_family(&self, id: Uuid, last_used_at: u64) -> AppResult<()>;
+    /// Moves a live family from `expected_jti` to `new_jti`. Returns `false`,
+    /// changing nothing, if the family is revoked, missing, or has already
+    /// moved on, so two racing refreshes cannot both succeed.
+    async fn rotate_token_family(&self, id: Uuid, expected_jti: Uuid, new_jti: Uuid, expires_at: u64) -> AppResult<bool>;
+    /// Returns `false` if the family did not exist.
+    async fn revoke_token_family(&self, id: Uuid) -> AppResult<bool>;
+    /// Drops families whose last refresh token has expired by `now`.
+    async fn prune_token_families(&self, now: u64) -> AppResult<usize>;
+}
+
+pub trait Store: UserStore + DeviceStore + ClipStore + BlobStore + TokenStore {}
+
+impl<T: UserStore + DeviceStore + ClipStore + BlobStore + TokenStore> Store for T {}
+
+/// Opens the backend selected in `StorageConfig`.
+pub async fn open_store(config: &StorageConfig) -> AppResult<Arc<dyn Store>> {
+    let store: Arc<dyn Store> = match config.backend {
+        StorageBackend::Memory => Arc::new(MemoryStore::new()),
+        StorageBackend::Sqlite => Arc::new(SqliteStore::open(&config.database_path)?),
+        StorageBackend::Journal => Arc::new(
+            JournalStore::open(&config.journal_dir, config.journal_compact_threshold).await?,
+        ),
+    };
+    Ok(store)
+}
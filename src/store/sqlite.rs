@@ -0,0 +1,1090 @@
+use async_trait::async_trait;
+use rusqlite::{params, Connection, OptionalExtension, Row, ToSql, TransactionBehavior};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+use crate::{
+    error::{AppError, AppResult},
+    models::{User, MfaSettings, Device, Subscription, ClipboardData, Blob, TokenFamily},
+};
+use super::{UserStore, DeviceStore, ClipStore, BlobStore, TokenStore};
+
+/// Schema migrations, applied in order. The number of applied migrations is
+/// tracked in `PRAGMA user_version`; never edit an entry once released, only
+/// append new ones.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE users (
+        id            BLOB PRIMARY KEY,
+        username      TEXT NOT NULL,
+        password_hash TEXT NOT NULL,
+        created_at    TEXT,
+        updated_at    TEXT
+    );
+    CREATE UNIQUE INDEX idx_users_username ON users (username);
+
+    CREATE TABLE devices (
+        id         BLOB PRIMARY KEY,
+        name       TEXT NOT NULL,
+        user_id    BLOB NOT NULL,
+        last_seen  INTEGER NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX idx_devices_user ON devices (user_id);
+
+    CREATE TABLE clips (
+        id          BLOB PRIMARY KEY,
+        content     TEXT NOT NULL,
+        device_id   BLOB NOT NULL,
+        user_id     BLOB NOT NULL,
+        sent_at     INTEGER NOT NULL,
+        received_at INTEGER NOT NULL
+    );
+    CREATE INDEX idx_clips_user ON clips (user_id, received_at);
+    CREATE INDEX idx_clips_device ON clips (device_id, received_at);
+
+    CREATE TABLE revoked_tokens (
+        token      TEXT PRIMARY KEY,
+        expires_at INTEGER NOT NULL
+    );",
+    // 2: per-user clip sequence numbers, backfilled in arrival order
+    "ALTER TABLE clips ADD COLUMN seq INTEGER NOT NULL DEFAULT 0;
+    UPDATE clips SET seq = (
+        SELECT COUNT(*) FROM clips AS earlier
+        WHERE earlier.user_id = clips.user_id
+          AND (earlier.received_at < clips.received_at
+               OR (earlier.received_at = clips.received_at AND earlier.rowid <= clips.rowid))
+    );
+    CREATE INDEX idx_clips_user_seq ON clips (user_id, seq);
+
+    CREATE TABLE clip_seqs (
+        user_id  BLOB PRIMARY KEY,
+        last_seq INTEGER NOT NULL
+    );
+    INSERT INTO clip_seqs (user_id, last_seq)
+        SELECT user_id, MAX(seq) FROM clips GROUP BY user_id;",
+    // 3: refresh token families
+    "CREATE TABLE token_families (
+        id          BLOB PRIMARY KEY,
+        user_id     BLOB NOT NULL,
+        device_id   BLOB,
+        current_jti BLOB NOT NULL,
+        created_at  INTEGER NOT NULL,
+        expires_at  INTEGER NOT NULL,
+        revoked     INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX idx_token_families_user ON token_families (user_id);",
+    // 4: session metadata on token families
+    "ALTER TABLE token_families ADD COLUMN ip_address TEXT;
+    ALTER TABLE token_families ADD COLUMN user_agent TEXT;
+    ALTER TABLE token_families ADD COLUMN last_used_at INTEGER NOT NULL DEFAULT 0;
+    UPDATE token_families SET last_used_at = created_at;",
+    // 5: two-factor settings, stored as JSON
+    "ALTER TABLE users ADD COLUMN mfa TEXT;",
+    // 6: device subscriptions and clip targets, stored as JSON; NULL for
+    // subscribed to everything and untargeted
+    "ALTER TABLE devices ADD COLUMN subscription TEXT;
+    ALTER TABLE clips ADD COLUMN targets TEXT;",
+    // 7: clip formats besides plain text, as JSON with base64 data; NULL
+    // for plain-text clips
+    "ALTER TABLE clips ADD COLUMN formats TEXT;",
+    // 8: chunked blobs; which chunks arrived is read off blob_chunks
+    "CREATE TABLE blobs (
+        id           BLOB PRIMARY KEY,
+        user_id      BLOB NOT NULL,
+        size         INTEGER NOT NULL,
+        chunk_size   INTEGER NOT NULL,
+        completed_at INTEGER,
+        created_at   INTEGER NOT NULL
+    );
+    CREATE INDEX idx_blobs_user ON blobs (user_id);
+    CREATE INDEX idx_blobs_created ON blobs (created_at);
+
+    CREATE TABLE blob_chunks (
+        blob_id BLOB NOT NULL,
+        idx     INTEGER NOT NULL,
+        data    BLOB NOT NULL,
+        PRIMARY KEY (blob_id, idx)
+    );",
+    // 9: clip payloads stored once per content hash and shared by
+    // reference; older clips keep theirs inline, with a NULL content_hash
+    "CREATE TABLE payloads (
+        hash    TEXT PRIMARY KEY,
+        content TEXT NOT NULL,
+        formats TEXT,
+        refs    INTEGER NOT NULL
+    );
+    ALTER TABLE clips ADD COLUMN content_hash TEXT;
+    CREATE INDEX idx_clips_payload ON clips (user_id, content_hash);",
+];
+
+/// Clip rows with their payload filled in.
+const SELECT_CLIPS: &str = "SELECT clips.id, clips.device_id, clips.user_id, clips.sent_at,
+        clips.received_at, clips.seq, clips.targets, clips.content_hash,
+        COALESCE(payloads.content, clips.content) AS content,
+        COALESCE(payloads.formats, clips.formats) AS formats
+    FROM clips LEFT JOIN payloads ON payloads.hash = clips.content_hash";
+
+/// Whether any payload, or any clip still holding its payload inline, has a
+/// format held in blob `?1` (the hyphenated UUID, as it appears in JSON).
+const BLOB_REFERENCED: &str = "SELECT EXISTS (
+        SELECT 1 FROM payloads, json_each(payloads.formats)
+        WHERE json_extract(json_each.value, '$.blob_id') = ?1
+    ) OR EXISTS (
+        SELECT 1 FROM clips, json_each(clips.formats)
+        WHERE clips.content_hash IS NULL AND json_extract(json_each.value, '$.blob_id') = ?1
+    )";
+
+fn db_err(e: rusqlite::Error) -> AppError {
+    AppError::DatabaseError(e.to_string())
+}
+
+/// Store backed by a single SQLite file. Calls run on the blocking pool so a
+/// slow disk never stalls the async runtime.
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> AppResult<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| AppError::DatabaseError(format!("Failed to create {}: {}", parent.display(), e)))?;
+        }
+
+        let conn = Connection::open(path).map_err(db_err)?;
+        conn.pragma_update(None, "journal_mode", "WAL").map_err(db_err)?;
+        Self::from_connection(conn)
+    }
+
+    pub fn open_in_memory() -> AppResult<Self> {
+        Self::from_connection(Connection::open_in_memory().map_err(db_err)?)
+    }
+
+    fn from_connection(mut conn: Connection) -> AppResult<Self> {
+        conn.busy_timeout(std::time::Duration::from_secs(5)).map_err(db_err)?;
+        Self::migrate(&mut conn).map_err(db_err)?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+        let applied: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(applied) {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+            tracing::info!("Applied database migration {}", index + 1);
+        }
+
+        Ok(())
+    }
+
+    pub async fn schema_version(&self) -> AppResult<usize> {
+        self.call(|conn| {
+            conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn call<T, F>(&self, f: F) -> AppResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> AppResult<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn
+                .lock()
+                .map_err(|e| AppError::LockError(e.to_string()))?;
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| AppError::InternalError(format!("Database task failed: {}", e)))?
+    }
+}
+
+fn to_json<T: Serialize>(value: &T) -> AppResult<String> {
+    serde_json::to_string(value).map_err(|e| AppError::DatabaseError(e.to_string()))
+}
+
+/// Reads a nullable JSON column.
+fn json_from_row<T: DeserializeOwned>(row: &Row, column: &str) -> rusqlite::Result<Option<T>> {
+    let Some(json) = row.get::<_, Option<String>>(column)? else {
+        return Ok(None);
+    };
+    serde_json::from_str(&json).map(Some).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+fn mfa_to_json(mfa: &Option<MfaSettings>) -> AppResult<Option<String>> {
+    mfa.as_ref().map(to_json).transpose()
+}
+
+fn subscription_to_json(subscription: &Subscription) -> AppResult<Option<String>> {
+    match subscription {
+        Subscription::All => Ok(None),
+        subscription => to_json(subscription).map(Some),
+    }
+}
+
+/// Empty lists are stored as NULL.
+fn list_to_json<T: Serialize>(items: &[T]) -> AppResult<Option<String>> {
+    match items.is_empty() {
+        true => Ok(None),
+        false => to_json(&items).map(Some),
+    }
+}
+
+fn user_from_row(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get("id")?,
+        username: row.get("username")?,
+        password_hash: row.get("password_hash")?,
+        mfa: json_from_row(row, "mfa")?,
+        created_at: row.get("created_at")?,
+        updated_at: row.get("updated_at")?,
+    })
+}
+
+fn device_from_row(row: &Row) -> rusqlite::Result<Device> {
+    Ok(Device {
+        id: row.get("id")?,
+        name: row.get("name")?,
+        user_id: row.get("user_id")?,
+        last_seen: row.get::<_, i64>("last_seen")? as u64,
+        created_at: row.get::<_, i64>("created_at")? as u64,
+        subscription: json_from_row(row, "subscription")?.unwrap_or_default(),
+    })
+}
+
+fn clip_from_row(row: &Row) -> rusqlite::Result<ClipboardData> {
+    Ok(ClipboardData {
+        id: row.get("id")?,
+        content: row.get("content")?,
+        device_id: row.get("device_id")?,
+        user_id: row.get("user_id")?,
+        sent_at: row.get::<_, i64>("sent_at")? as u64,
+        received_at: row.get::<_, i64>("received_at")? as u64,
+        seq: row.get::<_, i64>("seq")? as u64,
+        targets: json_from_row(row, "targets")?.unwrap_or_default(),
+        formats: json_from_row(row, "formats")?.unwrap_or_default(),
+        content_hash: row.get::<_, Option<String>>("content_hash")?.unwrap_or_default(),
+    })
+}
+
+/// Deletes the clips matching `condition` and drops their references,
+/// freeing payloads no clip uses any more.
+fn delete_clips_where(conn: &mut Connection, condition: &str, param: impl ToSql) -> rusqlite::Result<usize> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let hashes: Vec<String> = tx
+        .prepare(&format!("SELECT content_hash FROM clips WHERE content_hash IS NOT NULL AND {}", condition))?
+        .query_map([&param], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    let deleted = tx.execute(&format!("DELETE FROM clips WHERE {}", condition), [&param])?;
+    for hash in &hashes {
+        tx.prepare_cached("UPDATE payloads SET refs = refs - 1 WHERE hash = ?1")?
+            .execute([hash])?;
+        tx.prepare_cached("DELETE FROM payloads WHERE hash = ?1 AND refs <= 0")?
+            .execute([hash])?;
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// Reads a `blobs` row; `received` is filled in by `load_received`.
+fn blob_from_row(row: &Row) -> rusqlite::Result<Blob> {
+    Ok(Blob {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        size: row.get::<_, i64>("size")? as u64,
+        chunk_size: row.get::<_, i64>("chunk_size")? as u64,
+        received: Vec::new(),
+        completed_at: row.get::<_, Option<i64>>("completed_at")?.map(|at| at as u64),
+        created_at: row.get::<_, i64>("created_at")? as u64,
+    })
+}
+
+fn load_received(conn: &Connection, blob: &mut Blob) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare_cached("SELECT idx FROM blob_chunks WHERE blob_id = ?1 ORDER BY idx")?;
+    blob.received = stmt
+        .query_map([blob.id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(())
+}
+
+fn token_family_from_row(row: &Row) -> rusqlite::Result<TokenFamily> {
+    Ok(TokenFamily {
+        id: row.get("id")?,
+        user_id: row.get("user_id")?,
+        device_id: row.get("device_id")?,
+        current_jti: row.get("current_jti")?,
+        created_at: row.get::<_, i64>("created_at")? as u64,
+        expires_at: row.get::<_, i64>("expires_at")? as u64,
+        revoked: row.get("revoked")?,
+        ip_address: row.get("ip_address")?,
+        user_agent: row.get("user_agent")?,
+        last_used_at: row.get::<_, i64>("last_used_at")? as u64,
+    })
+}
+
+#[async_trait]
+impl UserStore for SqliteStore {
+    async fn insert_user(&self, user: User) -> AppResult<User> {
+        let mfa = mfa_to_json(&user.mfa)?;
+        self.call(move |conn| {
+            // The user row and its username index entry commit together
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(db_err)?;
+            let taken = tx
+                .query_row("SELECT 1 FROM users WHERE username = ?1", [&user.username], |_| Ok(()))
+                .optional()
+                .map_err(db_err)?
+                .is_some();
+            if taken {
+                return Err(AppError::UserAlreadyExists(user.username));
+            }
+
+            tx.execute(
+                "INSERT INTO users (id, username, password_hash, mfa, created_at, updated_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![user.id, user.username, user.password_hash, mfa, user.created_at, user.updated_at],
+            )
+            .map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn get_user(&self, id: Uuid) -> AppResult<Option<User>> {
+        self.call(move |conn| {
+            conn.query_row("SELECT * FROM users WHERE id = ?1", [id], user_from_row)
+                .optional()
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn get_user_by_username(&self, username: &str) -> AppResult<Option<User>> {
+        let username = username.to_string();
+        self.call(move |conn| {
+            conn.query_row("SELECT * FROM users WHERE username = ?1", [username], user_from_row)
+                .optional()
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn list_users(&self) -> AppResult<Vec<User>> {
+        self.call(|conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM users ORDER BY username")
+                .map_err(db_err)?;
+            let users = stmt
+                .query_map([], user_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            users
+        })
+        .await
+    }
+
+    async fn count_users(&self) -> AppResult<usize> {
+        self.call(|conn| {
+            conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn update_user(&self, user: User) -> AppResult<User> {
+        let mfa = mfa_to_json(&user.mfa)?;
+        self.call(move |conn| {
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(db_err)?;
+            let owner: Option<Uuid> = tx
+                .query_row("SELECT id FROM users WHERE username = ?1", [&user.username], |row| row.get(0))
+                .optional()
+                .map_err(db_err)?;
+            if owner.is_some_and(|owner| owner != user.id) {
+                return Err(AppError::UserAlreadyExists(user.username));
+            }
+
+            let updated = tx
+                .execute(
+                    "UPDATE users
+                     SET username = ?2, password_hash = ?3, mfa = ?4, created_at = ?5, updated_at = ?6
+                     WHERE id = ?1",
+                    params![user.id, user.username, user.password_hash, mfa, user.created_at, user.updated_at],
+                )
+                .map_err(db_err)?;
+            if updated == 0 {
+                return Err(AppError::UserNotFound(user.id));
+            }
+            tx.commit().map_err(db_err)?;
+            Ok(user)
+        })
+        .await
+    }
+
+    async fn delete_user(&self, id: Uuid) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute("DELETE FROM users WHERE id = ?1", [id])
+                .map(|deleted| deleted > 0)
+                .map_err(db_err)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl DeviceStore for SqliteStore {
+    async fn insert_device(&self, device: Device) -> AppResult<Device> {
+        let subscription = subscription_to_json(&device.subscription)?;
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO devices (id, name, user_id, last_seen, created_at, subscription)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    device.id,
+                    device.name,
+                    device.user_id,
+                    device.last_seen as i64,
+                    device.created_at as i64,
+                    subscription
+                ],
+            )
+            .map_err(db_err)?;
+            Ok(device)
+        })
+        .await
+    }
+
+    async fn get_device(&self, id: Uuid) -> AppResult<Option<Device>> {
+        self.call(move |conn| {
+            conn.query_row("SELECT * FROM devices WHERE id = ?1", [id], device_from_row)
+                .optional()
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn list_user_devices(&self, user_id: Uuid) -> AppResult<Vec<Device>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM devices WHERE user_id = ?1 ORDER BY created_at")
+                .map_err(db_err)?;
+            let devices = stmt
+                .query_map([user_id], device_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            devices
+        })
+        .await
+    }
+
+    async fn update_device(&self, device: Device) -> AppResult<Device> {
+        let subscription = subscription_to_json(&device.subscription)?;
+        self.call(move |conn| {
+            let updated = conn
+                .execute(
+                    "UPDATE devices
+                     SET name = ?2, user_id = ?3, last_seen = ?4, created_at = ?5, subscription = ?6
+                     WHERE id = ?1",
+                    params![
+                        device.id,
+                        device.name,
+                        device.user_id,
+                        device.last_seen as i64,
+                        device.created_at as i64,
+                        subscription
+                    ],
+                )
+                .map_err(db_err)?;
+            if updated == 0 {
+                return Err(AppError::DeviceNotFound(device.id));
+            }
+            Ok(device)
+        })
+        .await
+    }
+
+    async fn touch_device(&self, id: Uuid, last_seen: u64) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE devices SET last_seen = MAX(last_seen, ?2) WHERE id = ?1",
+                params![id, last_seen as i64],
+            )
+            .map(|updated| updated > 0)
+            .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn delete_device(&self, id: Uuid) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute("DELETE FROM devices WHERE id = ?1", [id])
+                .map(|deleted| deleted > 0)
+                .map_err(db_err)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl ClipStore for SqliteStore {
+    async fn insert_clip(&self, mut data: ClipboardData) -> AppResult<ClipboardData> {
+        data.content_hash = data.payload_hash();
+        let targets = list_to_json(&data.targets)?;
+        let formats = list_to_json(&data.formats)?;
+        self.call(move |conn| {
+            // Claiming the sequence number and storing the clip commit together
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(db_err)?;
+            let last_seq: i64 = tx
+                .query_row(
+                    "INSERT INTO clip_seqs (user_id, last_seq) VALUES (?1, MAX(?2, 1))
+                     ON CONFLICT (user_id) DO UPDATE
+                     SET last_seq = CASE WHEN ?2 = 0 THEN last_seq + 1 ELSE MAX(last_seq, ?2) END
+                     RETURNING last_seq",
+                    params![data.user_id, data.seq as i64],
+                    |row| row.get(0),
+                )
+                .map_err(db_err)?;
+            if data.seq == 0 {
+                data.seq = last_seq as u64;
+            }
+            for blob_id in data.formats.iter().filter_map(|format| format.blob_id) {
+                let exists: bool = tx
+                    .query_row("SELECT EXISTS (SELECT 1 FROM blobs WHERE id = ?1)", [blob_id], |row| row.get(0))
+                    .map_err(db_err)?;
+                if !exists {
+                    return Err(AppError::BlobNotFound(blob_id));
+                }
+            }
+
+            let existing: Option<Uuid> = tx
+                .query_row(
+                    "SELECT id FROM clips WHERE user_id = ?1 AND content_hash = ?2",
+                    params![data.user_id, data.content_hash],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_err)?;
+            match existing {
+                Some(id) => {
+                    data.id = id;
+                    tx.execute(
+                        "UPDATE clips SET device_id = ?2, sent_at = ?3, received_at = ?4, seq = ?5, targets = ?6
+                         WHERE id = ?1",
+                        params![
+                            data.id,
+                            data.device_id,
+                            data.sent_at as i64,
+                            data.received_at as i64,
+                            data.seq as i64,
+                            targets
+                        ],
+                    )
+                    .map_err(db_err)?;
+                }
+                None => {
+                    tx.execute(
+                        "INSERT INTO payloads (hash, content, formats, refs) VALUES (?1, ?2, ?3, 1)
+                         ON CONFLICT (hash) DO UPDATE SET refs = refs + 1",
+                        params![data.content_hash, data.content, formats],
+                    )
+                    .map_err(db_err)?;
+                    tx.execute(
+                        "INSERT INTO clips (id, content, device_id, user_id, sent_at, received_at, seq, targets, content_hash)
+                         VALUES (?1, '', ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                        params![
+                            data.id,
+                            data.device_id,
+                            data.user_id,
+                            data.sent_at as i64,
+                            data.received_at as i64,
+                            data.seq as i64,
+                            targets,
+                            data.content_hash
+                        ],
+                    )
+                    .map_err(db_err)?;
+                }
+            }
+            tx.commit().map_err(db_err)?;
+            Ok(data)
+        })
+        .await
+    }
+
+    async fn get_clip(&self, id: Uuid) -> AppResult<Option<ClipboardData>> {
+        self.call(move |conn| {
+            conn.query_row(&format!("{} WHERE clips.id = ?1", SELECT_CLIPS), [id], clip_from_row)
+                .optional()
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn list_user_clips(&self, user_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare(&format!("{} WHERE clips.user_id = ?1 ORDER BY clips.received_at DESC", SELECT_CLIPS))
+                .map_err(db_err)?;
+            let clips = stmt
+                .query_map([user_id], clip_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            clips
+        })
+        .await
+    }
+
+    async fn list_user_clips_after(&self, user_id: Uuid, after_seq: u64) -> AppResult<Vec<ClipboardData>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare(&format!("{} WHERE clips.user_id = ?1 AND clips.seq > ?2 ORDER BY clips.seq", SELECT_CLIPS))
+                .map_err(db_err)?;
+            let clips = stmt
+                .query_map(params![user_id, after_seq as i64], clip_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            clips
+        })
+        .await
+    }
+
+    async fn list_device_clips(&self, device_id: Uuid) -> AppResult<Vec<ClipboardData>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare(&format!("{} WHERE clips.device_id = ?1 ORDER BY clips.received_at DESC", SELECT_CLIPS))
+                .map_err(db_err)?;
+            let clips = stmt
+                .query_map([device_id], clip_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            clips
+        })
+        .await
+    }
+
+    async fn delete_clip(&self, id: Uuid) -> AppResult<bool> {
+        self.call(move |conn| {
+            delete_clips_where(conn, "id = ?1", id)
+                .map(|deleted| deleted > 0)
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn delete_user_clips(&self, user_id: Uuid) -> AppResult<usize> {
+        self.call(move |conn| delete_clips_where(conn, "user_id = ?1", user_id).map_err(db_err))
+            .await
+    }
+
+    async fn delete_clips_received_before(&self, cutoff: u64) -> AppResult<usize> {
+        self.call(move |conn| delete_clips_where(conn, "received_at < ?1", cutoff as i64).map_err(db_err))
+            .await
+    }
+
+    async fn payload_refs(&self, content_hash: &str) -> AppResult<usize> {
+        let content_hash = content_hash.to_string();
+        self.call(move |conn| {
+            conn.query_row("SELECT refs FROM payloads WHERE hash = ?1", [content_hash], |row| row.get(0))
+                .optional()
+                .map(|refs| refs.unwrap_or(0))
+                .map_err(db_err)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl BlobStore for SqliteStore {
+    async fn insert_blob(&self, blob: Blob) -> AppResult<Blob> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO blobs (id, user_id, size, chunk_size, completed_at, created_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    blob.id,
+                    blob.user_id,
+                    blob.size as i64,
+                    blob.chunk_size as i64,
+                    blob.completed_at.map(|at| at as i64),
+                    blob.created_at as i64
+                ],
+            )
+            .map_err(db_err)?;
+            Ok(blob)
+        })
+        .await
+    }
+
+    async fn get_blob(&self, id: Uuid) -> AppResult<Option<Blob>> {
+        self.call(move |conn| {
+            let Some(mut blob) = conn
+                .query_row("SELECT * FROM blobs WHERE id = ?1", [id], blob_from_row)
+                .optional()
+                .map_err(db_err)?
+            else {
+                return Ok(None);
+            };
+            load_received(conn, &mut blob).map_err(db_err)?;
+            Ok(Some(blob))
+        })
+        .await
+    }
+
+    async fn list_user_blobs(&self, user_id: Uuid) -> AppResult<Vec<Blob>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM blobs WHERE user_id = ?1 ORDER BY created_at")
+                .map_err(db_err)?;
+            let mut blobs = stmt
+                .query_map([user_id], blob_from_row)
+                .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+                .map_err(db_err)?;
+            for blob in &mut blobs {
+                load_received(conn, blob).map_err(db_err)?;
+            }
+            Ok(blobs)
+        })
+        .await
+    }
+
+    async fn put_blob_chunk(&self, id: Uuid, index: u32, data: Vec<u8>) -> AppResult<Blob> {
+        self.call(move |conn| {
+            let tx = conn.transaction().map_err(db_err)?;
+            let mut blob = tx
+                .query_row("SELECT * FROM blobs WHERE id = ?1", [id], blob_from_row)
+                .optional()
+                .map_err(db_err)?
+                .ok_or(AppError::BlobNotFound(id))?;
+            if blob.is_complete() {
+                return Err(AppError::BlobComplete(id));
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO blob_chunks (blob_id, idx, data) VALUES (?1, ?2, ?3)",
+                params![id, index, data],
+            )
+            .map_err(db_err)?;
+            load_received(&tx, &mut blob).map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            Ok(blob)
+        })
+        .await
+    }
+
+    async fn get_blob_chunk(&self, id: Uuid, index: u32) -> AppResult<Option<Vec<u8>>> {
+        self.call(move |conn| {
+            conn.query_row(
+                "SELECT data FROM blob_chunks WHERE blob_id = ?1 AND idx = ?2",
+                params![id, index],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn complete_blob(&self, id: Uuid, completed_at: u64) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE blobs SET completed_at = ?2 WHERE id = ?1",
+                params![id, completed_at as i64],
+            )
+            .map(|updated| updated > 0)
+            .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn list_blobs_created_before(&self, cutoff: u64) -> AppResult<Vec<Blob>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM blobs WHERE created_at < ?1")
+                .map_err(db_err)?;
+            let mut blobs = stmt
+                .query_map([cutoff as i64], blob_from_row)
+                .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+                .map_err(db_err)?;
+            for blob in &mut blobs {
+                load_received(conn, blob).map_err(db_err)?;
+            }
+            Ok(blobs)
+        })
+        .await
+    }
+
+    async fn delete_blob(&self, id: Uuid) -> AppResult<bool> {
+        self.call(move |conn| {
+            let tx = conn
+                .transaction_with_behavior(TransactionBehavior::Immediate)
+                .map_err(db_err)?;
+            let referenced: bool = tx
+                .query_row(BLOB_REFERENCED, [id.to_string()], |row| row.get(0))
+                .map_err(db_err)?;
+            if referenced {
+                return Err(AppError::BlobInUse(id));
+            }
+            tx.execute("DELETE FROM blob_chunks WHERE blob_id = ?1", [id])
+                .map_err(db_err)?;
+            let deleted = tx
+                .execute("DELETE FROM blobs WHERE id = ?1", [id])
+                .map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            Ok(deleted > 0)
+        })
+        .await
+    }
+
+    async fn delete_user_blobs(&self, user_id: Uuid) -> AppResult<usize> {
+        self.call(move |conn| {
+            let tx = conn.transaction().map_err(db_err)?;
+            tx.execute(
+                "DELETE FROM blob_chunks WHERE blob_id IN (SELECT id FROM blobs WHERE user_id = ?1)",
+                [user_id],
+            )
+            .map_err(db_err)?;
+            let deleted = tx
+                .execute("DELETE FROM blobs WHERE user_id = ?1", [user_id])
+                .map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            Ok(deleted)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl TokenStore for SqliteStore {
+    async fn revoke_token(&self, token: &str, expires_at: u64) -> AppResult<bool> {
+        let token = token.to_string();
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT OR IGNORE INTO revoked_tokens (token, expires_at) VALUES (?1, ?2)",
+                params![token, expires_at as i64],
+            )
+            .map(|inserted| inserted > 0)
+            .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn is_token_revoked(&self, token: &str) -> AppResult<bool> {
+        let token = token.to_string();
+        self.call(move |conn| {
+            conn.query_row("SELECT 1 FROM revoked_tokens WHERE token = ?1", [token], |_| Ok(()))
+                .optional()
+                .map(|found| found.is_some())
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn prune_revoked_tokens(&self, now: u64) -> AppResult<usize> {
+        self.call(move |conn| {
+            conn.execute("DELETE FROM revoked_tokens WHERE expires_at <= ?1", [now as i64])
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn insert_token_family(&self, family: TokenFamily) -> AppResult<TokenFamily> {
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO token_families
+                    (id, user_id, device_id, current_jti, created_at, expires_at, revoked,
+                     ip_address, user_agent, last_used_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                params![
+                    family.id,
+                    family.user_id,
+                    family.device_id,
+                    family.current_jti,
+                    family.created_at as i64,
+                    family.expires_at as i64,
+                    family.revoked,
+                    family.ip_address,
+                    family.user_agent,
+                    family.last_used_at as i64
+                ],
+            )
+            .map_err(db_err)?;
+            Ok(family)
+        })
+        .await
+    }
+
+    async fn get_token_family(&self, id: Uuid) -> AppResult<Option<TokenFamily>> {
+        self.call(move |conn| {
+            conn.query_row("SELECT * FROM token_families WHERE id = ?1", [id], token_family_from_row)
+                .optional()
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn list_user_token_families(&self, user_id: Uuid) -> AppResult<Vec<TokenFamily>> {
+        self.call(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT * FROM token_families WHERE user_id = ?1 ORDER BY created_at")
+                .map_err(db_err)?;
+            let families = stmt
+                .query_map([user_id], token_family_from_row)
+                .and_then(|rows| rows.collect())
+                .map_err(db_err);
+            families
+        })
+        .await
+    }
+
+    async fn touch_token_family(&self, id: Uuid, last_used_at: u64) -> AppResult<()> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE token_families SET last_used_at = MAX(last_used_at, ?2) WHERE id = ?1",
+                params![id, last_used_at as i64],
+            )
+            .map_err(db_err)?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn rotate_token_family(&self, id: Uuid, expected_jti: Uuid, new_jti: Uuid, expires_at: u64) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute(
+                "UPDATE token_families SET current_jti = ?3, expires_at = ?4
+                 WHERE id = ?1 AND current_jti = ?2 AND revoked = 0",
+                params![id, expected_jti, new_jti, expires_at as i64],
+            )
+            .map(|updated| updated > 0)
+            .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn revoke_token_family(&self, id: Uuid) -> AppResult<bool> {
+        self.call(move |conn| {
+            conn.execute("UPDATE token_families SET revoked = 1 WHERE id = ?1", [id])
+                .map(|updated| updated > 0)
+                .map_err(db_err)
+        })
+        .await
+    }
+
+    async fn prune_token_families(&self, now: u64) -> AppResult<usize> {
+        self.call(move |conn| {
+            conn.execute("DELETE FROM token_families WHERE expires_at <= ?1", [now as i64])
+                .map_err(db_err)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::conformance;
+
+    fn temp_db_path() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("clipman-test-{}.db", Uuid::new_v4()))
+    }
+
+    fn remove_db(path: &Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_store_conformance() {
+        conformance::run_all(&SqliteStore::open_in_memory().unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn test_migrations_are_applied_once() {
+        let path = temp_db_path();
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.schema_version().await.unwrap(), MIGRATIONS.len());
+        drop(store);
+
+        // Reopening must not re-run the initial schema (it would fail on CREATE TABLE)
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.schema_version().await.unwrap(), MIGRATIONS.len());
+
+        drop(store);
+        remove_db(&path);
+    }
+
+    #[tokio::test]
+    async fn test_seq_migration_backfills_existing_clips() {
+        let path = temp_db_path();
+        let user_id = Uuid::new_v4();
+
+        // A database that only has the initial schema
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(MIGRATIONS[0]).unwrap();
+            conn.pragma_update(None, "user_version", 1).unwrap();
+            for received_at in [30i64, 10, 20] {
+                conn.execute(
+                    "INSERT INTO clips (id, content, device_id, user_id, sent_at, received_at)
+                     VALUES (?1, 'old', ?2, ?3, 0, ?4)",
+                    params![Uuid::new_v4(), Uuid::new_v4(), user_id, received_at],
+                )
+                .unwrap();
+            }
+        }
+
+        let store = SqliteStore::open(&path).unwrap();
+        let clips = store.list_user_clips_after(user_id, 0).await.unwrap();
+        let order: Vec<(u64, u64)> = clips.iter().map(|d| (d.seq, d.received_at)).collect();
+        assert_eq!(order, vec![(1, 10), (2, 20), (3, 30)]);
+
+        let data = ClipboardData::new("new".to_string(), Uuid::new_v4(), user_id);
+        assert_eq!(store.insert_clip(data).await.unwrap().seq, 4);
+
+        drop(store);
+        remove_db(&path);
+    }
+
+    #[tokio::test]
+    async fn test_data_survives_reopen() {
+        let path = temp_db_path();
+        let user = User::new("persistent".to_string(), "hash".to_string());
+        let mut clip = ClipboardData::new("hello".to_string(), Uuid::new_v4(), user.id);
+        clip.received_at = 42;
+
+        {
+            let store = SqliteStore::open(&path).unwrap();
+            store.insert_user(user.clone()).await.unwrap();
+            store.insert_clip(clip.clone()).await.unwrap();
+        }
+
+        let store = SqliteStore::open(&path).unwrap();
+        let loaded = store.get_user_by_username("persistent").await.unwrap().unwrap();
+        assert_eq!(loaded.id, user.id);
+        assert_eq!(loaded.created_at, user.created_at);
+        let clips = store.list_user_clips(user.id).await.unwrap();
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].content, "hello");
+        assert_eq!(clips[0].received_at, 42);
+
+        drop(store);
+        remove_db(&path);
+    }
+}
@@ -1,50 +1,85 @@
 use std::sync::Arc;
-use crate::config::{Config, AuthConfig, ServerConfig, UserConfig, WebSocketConfig, ClipboardConfig, AppConfig};
+use crate::config::{Config, AuthConfig, JwtAlgorithm, ServerConfig, UserConfig, WebSocketConfig, ClipboardConfig, AppConfig, StorageConfig, MaintenanceConfig, ThrottleConfig, PairingConfig, BlobConfig};
 use crate::state::AppState;
-use crate::services::{AuthService, UserService, DeviceService, WebSocketService};
+use crate::services::{AuthService, UserService, DeviceService, WebSocketService, ClipboardService, MaintenanceService, MfaService, ThrottleService, PairingService, BlobService};
+use crate::store::{Store, MemoryStore};
 
 // Mock Config
 pub fn mock_config() -> Config {
     Config {
         auth: AuthConfig {
             jwt_secret: "test_secret".to_string(),
+            jwt_key_id: "test".to_string(),
+            jwt_algorithm: JwtAlgorithm::Hs256,
+            jwt_private_key_path: None,
+            previous_keys: Vec::new(),
             access_token_expiry: 3600,   // 1 hour
             refresh_token_expiry: 604800, // 7 days
+            mfa_token_expiry: 300,        // 5 minutes
+            totp_issuer: "Clipman".to_string(),
+            admin_user_ids: Vec::new(),
         },
         server: ServerConfig {
             host: "127.0.0.1".to_string(),
             port: 8080,
+            trust_forwarded_for: false,
         },
         user: UserConfig {
             min_password_length: 8,
             max_username_length: 32,
             password_rounds: 3,
             memory_size: 65536,
+            max_password_length: 128,
+            password_character_classes: 0,
+            min_password_score: 2,
+            password_blocklist_path: None,
+            max_devices: 10,
         },
         websocket: WebSocketConfig {
             channel_capacity: 100,
+            replay_limit: 500,
+            heartbeat_interval: 30,
+            heartbeat_timeout: 90,
         },
         clipboard: ClipboardConfig {
             retention_period: 3600, // 1 hour
             max_size: 1024 * 1024,  // 1 MB
+            max_format_size: 1024 * 1024,
+            max_formats: 8,
             broadcast_capacity: 100,
         },
         app: AppConfig {
             history_size: 10,
             broadcast_capacity: 100,
         },
+        storage: StorageConfig::default(),
+        maintenance: MaintenanceConfig::default(),
+        throttle: ThrottleConfig::default(),
+        pairing: PairingConfig::default(),
+        blob: BlobConfig::default(),
     }
 }
 
 // Mock AppState
 pub async fn mock_app_state() -> AppState {
     let config = Arc::new(mock_config());
+    let store: Arc<dyn Store> = Arc::new(MemoryStore::new());
+    let clipboard_service = Arc::new(ClipboardService::new(config.clone(), store.clone()));
+    let auth_service = Arc::new(AuthService::new(config.clone(), store.clone()));
+    let device_service = Arc::new(DeviceService::new(config.clone(), store.clone()));
+    let blob_service = Arc::new(BlobService::new(config.clone(), store.clone()));
 
     AppState {
         config: config.clone(),
-        user_service: Arc::new(UserService::new(config.clone())),
-        auth_service: Arc::new(AuthService::new(config.clone())),
-        device_service: Arc::new(DeviceService::new(config.clone())),
-        ws_service: Arc::new(WebSocketService::new(config.clone())),
+        user_service: Arc::new(UserService::new(config.clone(), store.clone())),
+        auth_service: auth_service.clone(),
+        mfa_service: Arc::new(MfaService::new(config.clone(), store.clone())),
+        throttle_service: Arc::new(ThrottleService::new(config.clone())),
+        pairing_service: Arc::new(PairingService::new(config.clone())),
+        device_service: device_service.clone(),
+        clipboard_service: clipboard_service.clone(),
+        blob_service: blob_service.clone(),
+        ws_service: Arc::new(WebSocketService::new(config.clone(), clipboard_service.clone(), device_service)),
+        maintenance_service: Arc::new(MaintenanceService::new(config.clone(), clipboard_service, auth_service, blob_service)),
     }
 }
@@ -4,7 +4,7 @@ use tracing_subscriber::{
     fmt::{self},
     prelude::*,
 };
-use tracing_subscriber::filter::EnvFilter;
+use tracing_subscriber::filter::{EnvFilter, Targets};
 use tracing_subscriber::fmt::time::UtcTime;
 // use tracing_subscriber::fmt::time::SystemTime;
 
@@ -33,6 +33,21 @@ pub fn setup_logger() {
             "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
         )));
 
+    // Security events (failed logins, lockouts, token reuse) also get their
+    // own file so they can be shipped and retained separately
+    let security_appender = RollingFileAppender::new(
+        Rotation::DAILY,
+        "logs",
+        "security.log",
+    );
+    let security_layer = fmt::layer()
+        .with_ansi(false)
+        .with_writer(security_appender)
+        .with_timer(UtcTime::new(time::macros::format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+        )))
+        .with_filter(Targets::new().with_target("security", tracing::Level::INFO));
+
     // Create formatting layer for console with colors
     let console_layer = fmt::layer()
         .with_file(true)
@@ -51,6 +66,7 @@ pub fn setup_logger() {
         .with(env_filter)
         .with(console_layer)
         .with(file_layer)
+        .with(security_layer)
         .init();
 
     tracing::info!("Logger initialized successfully");